@@ -0,0 +1,177 @@
+// Loudness outline of a track for the seek bar, peak and RMS per bucket scaled to 0..=255
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Envelope {
+    pub peaks: Vec<u8>,
+    pub rms: Vec<u8>,
+}
+
+impl Envelope {
+    // Peaks first, then RMS; how it is cached in the library
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.peaks.clone();
+        bytes.extend_from_slice(&self.rms);
+        bytes
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.is_empty() || !bytes.len().is_multiple_of(2) {
+            return None;
+        }
+        let (peaks, rms) = bytes.split_at(bytes.len() / 2);
+        Some(Self {
+            peaks: peaks.to_vec(),
+            rms: rms.to_vec(),
+        })
+    }
+    pub fn is_empty(&self) -> bool {
+        self.peaks.is_empty()
+    }
+    // Loudest peak and RMS between two fractions of the track, for drawing into fewer columns
+    pub fn range(&self, from: f64, to: f64) -> (u8, u8) {
+        let len = self.peaks.len();
+        if len == 0 {
+            return (0, 0);
+        }
+        let first = ((from * len as f64) as usize).min(len.saturating_sub(1));
+        let last = ((to * len as f64).ceil() as usize).clamp(first + 1, len);
+        let peak = self.peaks[first..last].iter().copied().max();
+        let rms = self.rms[first..last].iter().copied().max();
+        (peak.unwrap_or_default(), rms.unwrap_or_default())
+    }
+}
+
+// Collects mono samples in 10ms blocks, the length of the track isnt known up front
+#[derive(Debug)]
+pub struct EnvelopeBuilder {
+    block_size: usize,
+    // Peak and mean square per block
+    blocks: Vec<(f32, f32)>,
+    peak: f32,
+    square_sum: f64,
+    count: usize,
+}
+
+impl EnvelopeBuilder {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            block_size: (sample_rate as usize / 100).max(1),
+            blocks: Vec::new(),
+            peak: 0.0,
+            square_sum: 0.0,
+            count: 0,
+        }
+    }
+    pub fn push(&mut self, samples: &[f32]) {
+        for sample in samples {
+            self.peak = self.peak.max(sample.abs());
+            self.square_sum += (*sample as f64) * (*sample as f64);
+            self.count += 1;
+            if self.count == self.block_size {
+                self.finish_block();
+            }
+        }
+    }
+    fn finish_block(&mut self) {
+        if self.count == 0 {
+            return;
+        }
+        let mean_square = (self.square_sum / self.count as f64) as f32;
+        self.blocks.push((self.peak, mean_square));
+        self.peak = 0.0;
+        self.square_sum = 0.0;
+        self.count = 0;
+    }
+    pub fn finish(mut self, buckets: usize) -> Envelope {
+        self.finish_block();
+        let blocks = self.blocks.len();
+        let buckets = buckets.min(blocks);
+        let scale = |f: f32| (f.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let mut envelope = Envelope::default();
+        for i in 0..buckets {
+            let blocks = &self.blocks[i * blocks / buckets..(i + 1) * blocks / buckets];
+            let peak = blocks.iter().map(|f| f.0).fold(0.0, f32::max);
+            let mean_square = blocks.iter().map(|f| f.1).sum::<f32>() / blocks.len() as f32;
+            envelope.peaks.push(scale(peak));
+            envelope.rms.push(scale(mean_square.sqrt()));
+        }
+        envelope
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One sample per block, so every sample is its own peak and RMS
+    fn built(samples: &[f32], buckets: usize) -> Envelope {
+        let mut builder = EnvelopeBuilder::new(100);
+        builder.push(samples);
+        builder.finish(buckets)
+    }
+
+    #[test]
+    fn blocks_are_ten_milliseconds() {
+        let mut builder = EnvelopeBuilder::new(1000);
+        // Split across pushes, with a short block at the end
+        builder.push(&[0.5; 6]);
+        builder.push(&[-1.0; 6]);
+        builder.push(&[0.25; 3]);
+        let envelope = builder.finish(10);
+        assert_eq!(envelope.peaks, [255, 255]);
+        // Six of ten samples at 0.5 and four at 1.0, then two at 1.0 and three at 0.25
+        assert_eq!(envelope.rms[0], (0.55f32.sqrt() * 255.0).round() as u8);
+        assert_eq!(envelope.rms[1], (0.4375f32.sqrt() * 255.0).round() as u8);
+    }
+
+    #[test]
+    fn fewer_blocks_than_buckets() {
+        let envelope = built(&[0.5, 1.0, 0.0], 100);
+        assert_eq!(envelope.peaks, [128, 255, 0]);
+        assert_eq!(envelope.rms, [128, 255, 0]);
+        assert!(EnvelopeBuilder::new(44100).finish(100).is_empty());
+    }
+
+    #[test]
+    fn buckets_share_blocks_evenly() {
+        let samples = [0.0, 1.0, 0.0, 0.0, 0.5, 0.0, -0.2, 0.2];
+        let envelope = built(&samples, 3);
+        // 8 blocks into 3 buckets, 2 + 3 + 3
+        assert_eq!(envelope.peaks, [255, 128, 51]);
+        assert_eq!(envelope.rms[0], (0.5f32.sqrt() * 255.0).round() as u8);
+        // Clipped samples dont go past the top
+        assert_eq!(built(&[4.0], 1).peaks, [255]);
+    }
+
+    #[test]
+    fn range_of_a_part() {
+        let envelope = Envelope {
+            peaks: Vec::from([10, 20, 30, 40]),
+            rms: Vec::from([1, 2, 3, 4]),
+        };
+        assert_eq!(envelope.range(0.0, 1.0), (40, 4));
+        assert_eq!(envelope.range(0.0, 0.5), (20, 2));
+        assert_eq!(envelope.range(0.3, 0.6), (30, 3));
+        // Narrower than a bucket still shows that bucket
+        assert_eq!(envelope.range(0.26, 0.27), (20, 2));
+    }
+
+    #[test]
+    fn range_at_the_ends() {
+        let envelope = Envelope {
+            peaks: Vec::from([10, 20, 30, 40]),
+            rms: Vec::from([1, 2, 3, 4]),
+        };
+        assert_eq!(envelope.range(0.0, 0.0), (10, 1));
+        assert_eq!(envelope.range(1.0, 1.0), (40, 4));
+        assert_eq!(envelope.range(0.9, 1.5), (40, 4));
+        assert_eq!(envelope.range(-0.5, 0.1), (10, 1));
+        assert_eq!(Envelope::default().range(0.0, 1.0), (0, 0));
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let envelope = built(&[0.5, 1.0, 0.0], 3);
+        assert_eq!(Envelope::from_bytes(&envelope.to_bytes()), Some(envelope));
+        assert_eq!(Envelope::from_bytes(&[]), None);
+        assert_eq!(Envelope::from_bytes(&[1, 2, 3]), None);
+    }
+}
@@ -18,7 +18,10 @@ use ringbuf::{HeapRb, SharedRb};
 use std::{f32::consts::PI, mem::MaybeUninit, ops::Mul, path::Path, sync::Arc, time::Duration};
 use tracing::info;
 
+pub mod envelope;
 pub mod player;
+pub mod queue;
+pub mod spectrum;
 
 /* WHY THIS MAGIC NUMBER
  * 12 is the LCM (least common multiple) of 1,2,3,4
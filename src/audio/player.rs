@@ -1,24 +1,48 @@
-use std::path::Path;
+use std::{
+    collections::VecDeque,
+    ffi::{c_void, CStr},
+    marker::PhantomData,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
-use super::FFmpegSampleFormatConversion;
+use super::{
+    _packed,
+    envelope::{Envelope, EnvelopeBuilder},
+    FFmpegSampleFormatConversion,
+};
+use crate::db::AudioData;
 use cpal::{
-    traits::*, Device, Host, Sample as CpalSample, SampleFormat as CpalSampleFormat, StreamConfig,
-    SupportedStreamConfig,
+    traits::*, Device, Host, Sample, SampleFormat as CpalSampleFormat, SizedSample, StreamConfig,
 };
 
 use ffmpeg_next::{
     codec::Context as FFMpegCodecContext,
     decoder::Audio as FFMpegAudio,
-    format::{context::Input, input as FFMpegInput},
+    ffi::{av_free, av_opt_get, AV_OPT_SEARCH_CHILDREN, AV_TIME_BASE},
+    format::{
+        context::Input, input as FFMpegInput, input_with_dictionary as FFMpegInputWithDictionary,
+    },
+    frame::{audio::Sample as FFMpegFrameSample, Audio as FFMpegFrame},
     media::Type as FFMpegMediaType,
     util::{
+        dictionary::Owned as FFMpegDictionary,
         error::Error as FFMpegError,
-        format::{sample::Buffer as FFMpegBuffer, Sample as FFMpegSample},
+        format::{sample::Type as FFMpegSampleType, Sample as FFMpegSample},
     },
+    ChannelLayout as FFMpegChannelLayout, Packet as FFMpegPacket,
 };
-use ringbuf::HeapRb;
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
 
 const AUDIO_DATA_BUFFER_SIZE: usize = 15600;
+// Network sources jitter a lot more than files, so buffer more before playing
+const AUDIO_NETWORK_BUFFER_SIZE: usize = AUDIO_DATA_BUFFER_SIZE * 16;
+// Mono samples kept for the visualizer, about a third of a second
+const AUDIO_TAP_SIZE: usize = 16384;
 
 #[derive(Debug)]
 enum AudioContextError {
@@ -26,6 +50,8 @@ enum AudioContextError {
     NoAudioStream,
     FFMpegCodecError(FFMpegError),
     FFMpegAudioDecoder(FFMpegError),
+    FFMpegResamplerError(FFMpegError),
+    FFMpegSeekError(FFMpegError),
 }
 
 impl std::fmt::Display for AudioContextError {
@@ -40,6 +66,12 @@ impl std::fmt::Display for AudioContextError {
             Self::FFMpegAudioDecoder(e) => {
                 write!(f, "FFMpegAudioDecoder: {}", e)
             }
+            Self::FFMpegResamplerError(e) => {
+                write!(f, "FFMpegResamplerError: {}", e)
+            }
+            Self::FFMpegSeekError(e) => {
+                write!(f, "FFMpegSeekError: {}", e)
+            }
             Self::NoAudioStream => write!(f, "NoAudioStream"),
             _ => write!(f, ""),
         }
@@ -48,10 +80,67 @@ impl std::fmt::Display for AudioContextError {
 
 impl std::error::Error for AudioContextError {}
 
+// No sound card, or one that wont tell what it can play
+#[derive(Debug)]
+pub enum AudioPlayerError {
+    NoOutputDevice,
+    OutputConfigError(cpal::SupportedStreamConfigsError),
+    NoOutputConfig,
+}
+
+impl std::fmt::Display for AudioPlayerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoOutputDevice => write!(f, "NoOutputDevice"),
+            Self::OutputConfigError(e) => write!(f, "OutputConfigError: {}", e),
+            Self::NoOutputConfig => write!(f, "NoOutputConfig"),
+        }
+    }
+}
+
+impl std::error::Error for AudioPlayerError {}
+
+#[derive(Debug, Clone)]
+pub struct AudioNetworkOptions {
+    // How long to wait on a dead connection before giving up
+    pub timeout: Duration,
+    // Reconnect when the server drops us mid-stream
+    pub reconnect: bool,
+    // Upper bound for the backoff between reconnect attempts
+    pub reconnect_delay_max: Duration,
+    // Ask internet radio for in-band (ICY) metadata
+    pub icy: bool,
+    // How much audio to have decoded before starting playback
+    pub prebuffer: Duration,
+}
+
+impl Default for AudioNetworkOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            reconnect: true,
+            reconnect_delay_max: Duration::from_secs(5),
+            icy: true,
+            prebuffer: Duration::from_secs(2),
+        }
+    }
+}
+
 struct AudioContext {
     input_context: Input,
     index: usize,
     decoder: FFMpegAudio,
+    // Amount of samples to queue up before playing, 0 for local files
+    prebuffer: usize,
+    // How long reads may keep failing while ffmpeg reconnects, None for local files
+    read_timeout: Option<Duration>,
+    // Virtual tracks (CUE sheets) only play part of the file
+    start: Option<Duration>,
+    end: Option<Duration>,
+    // Where playback starts, relative to `start`
+    offset: Duration,
+    // Handed out by the player so callers can tell tracks apart
+    id: u64,
 }
 
 impl AudioContext {
@@ -75,8 +164,533 @@ impl AudioContext {
             input_context,
             index,
             decoder,
+            prebuffer: 0,
+            read_timeout: None,
+            start: None,
+            end: None,
+            offset: Duration::ZERO,
+            id: 0,
         })
     }
+    pub fn new_url(
+        url: &str,
+        options: &AudioNetworkOptions,
+    ) -> Result<AudioContext, AudioContextError> {
+        // All of these are ffmpeg protocol options, timeouts are in microseconds
+        let timeout = options.timeout.as_micros().to_string();
+        let mut dictionary = FFMpegDictionary::new();
+        dictionary.set("rw_timeout", &timeout);
+        dictionary.set("timeout", &timeout);
+        if options.reconnect {
+            dictionary.set("reconnect", "1");
+            dictionary.set("reconnect_streamed", "1");
+            dictionary.set("reconnect_on_network_error", "1");
+            dictionary.set(
+                "reconnect_delay_max",
+                &options.reconnect_delay_max.as_secs().to_string(),
+            );
+        }
+        dictionary.set("icy", if options.icy { "1" } else { "0" });
+        let input_context = FFMpegInputWithDictionary(&url, dictionary)
+            .map_err(AudioContextError::FFMpegInputError)?;
+        let stream = input_context
+            .streams()
+            .best(FFMpegMediaType::Audio)
+            .ok_or(AudioContextError::NoAudioStream)?;
+        let index = stream.index();
+        let codec = FFMpegCodecContext::from_parameters(stream.parameters())
+            .map_err(AudioContextError::FFMpegCodecError)?;
+        let decoder = codec
+            .decoder()
+            .audio()
+            .map_err(AudioContextError::FFMpegAudioDecoder)?;
+        let prebuffer = (options.prebuffer.as_secs_f64()
+            * decoder.rate() as f64
+            * decoder.channels() as f64) as usize;
+        Ok(Self {
+            input_context,
+            index,
+            decoder,
+            prebuffer,
+            read_timeout: Some(options.timeout),
+            start: None,
+            end: None,
+            offset: Duration::ZERO,
+            id: 0,
+        })
+    }
+    // Current "now playing" title of an internet radio stream (if any)
+    pub fn icy_title(&self) -> Option<String> {
+        let packet = self.icy_option("icy_metadata_packet")?;
+        parse_icy_title(&packet)
+    }
+    fn icy_option(&self, name: &str) -> Option<String> {
+        let name = std::ffi::CString::new(name).ok()?;
+        unsafe {
+            let pb = (*self.input_context.as_ptr()).pb;
+            if pb.is_null() {
+                return None;
+            }
+            let mut value: *mut u8 = std::ptr::null_mut();
+            // The http protocol is a child of the io context
+            if av_opt_get(
+                pb as *mut c_void,
+                name.as_ptr(),
+                AV_OPT_SEARCH_CHILDREN,
+                &mut value,
+            ) < 0
+                || value.is_null()
+            {
+                return None;
+            }
+            let result = CStr::from_ptr(value as *const _)
+                .to_string_lossy()
+                .into_owned();
+            av_free(value as *mut c_void);
+            Some(result)
+        }
+    }
+    fn time_base(&self) -> f64 {
+        self.input_context
+            .stream(self.index)
+            .map_or(0f64, |f| f64::from(f.time_base()))
+    }
+    fn seek(&mut self, position: Duration) -> Result<(), AudioContextError> {
+        let timestamp = (position.as_secs_f64() * AV_TIME_BASE as f64) as i64;
+        self.input_context
+            .seek(timestamp, ..timestamp)
+            .map_err(AudioContextError::FFMpegSeekError)?;
+        self.decoder.flush();
+        Ok(())
+    }
+    // Decodes the whole context into the ring buffer, returns early on skip/stop
+    fn play<T: FFMpegFrameSample + Copy>(
+        &mut self,
+        shared: &AudioPlayerShared,
+        producer: &mut HeapProducer<T>,
+        output: (FFMpegSample, FFMpegChannelLayout, u32),
+    ) -> Result<(), AudioContextError> {
+        let (sample_format, channel_layout, sample_rate) = output;
+        let channels = channel_layout.channels().max(1) as usize;
+        let default_channel_layout = FFMpegChannelLayout::default(self.decoder.channels() as i32);
+        if self.decoder.channel_layout().is_empty() {
+            self.decoder.set_channel_layout(default_channel_layout);
+        }
+        let new_resampler = |decoder: &FFMpegAudio| {
+            decoder
+                .resampler(sample_format, channel_layout, sample_rate)
+                .map_err(AudioContextError::FFMpegResamplerError)
+        };
+        let mut resampler = new_resampler(&self.decoder)?;
+        let time_base = self.time_base();
+        let start = self.start.unwrap_or_default();
+        if start + self.offset > Duration::ZERO {
+            self.seek(start + self.offset)?;
+        }
+        shared.mark(self.id, self.offset);
+        *shared.title.lock().unwrap() = None;
+        let prebuffer = self.prebuffer.min(producer.capacity());
+        // Local files dont need to run that far ahead
+        let limit = match prebuffer {
+            0 => AUDIO_DATA_BUFFER_SIZE.min(producer.capacity()),
+            _ => producer.capacity(),
+        };
+        shared.buffering.store(prebuffer > 0, Ordering::Relaxed);
+        let mut packet = FFMpegPacket::empty();
+        let mut decoded = FFMpegFrame::empty();
+        let mut resampled = FFMpegFrame::empty();
+        // Media time of the next sample, used when frames have no timestamp
+        let mut position = start + self.offset;
+        let mut eof = false;
+        // When reads started failing
+        let mut failing_since: Option<Instant> = None;
+        let mut packet_count: u64 = 0;
+        'decode: loop {
+            if !shared.running.load(Ordering::Relaxed) || shared.skip.swap(false, Ordering::Relaxed)
+            {
+                shared.flush();
+                return Ok(());
+            }
+            if let Some(target) = shared.take_seek() {
+                self.seek(start + target)?;
+                resampler = new_resampler(&self.decoder)?;
+                shared.flush();
+                shared.mark(self.id, target);
+                position = start + target;
+                eof = false;
+            }
+            if !eof {
+                match packet.read(&mut self.input_context) {
+                    Ok(()) => {
+                        failing_since = None;
+                        if packet.stream() != self.index {
+                            continue;
+                        }
+                        packet_count += 1;
+                        // Internet radio updates its title in-band
+                        if self.prebuffer > 0 && packet_count & 63 == 0 {
+                            *shared.title.lock().unwrap() = self.icy_title();
+                        }
+                        if let Err(e) = self.decoder.send_packet(&packet) {
+                            tracing::warn!("Dropping packet: {}", e);
+                            continue;
+                        }
+                    }
+                    Err(FFMpegError::Eof) => {
+                        let _ = self.decoder.send_eof();
+                        eof = true;
+                    }
+                    // Network hiccups, ffmpeg will reconnect on its own; broken files stay broken
+                    Err(e) => {
+                        let since = *failing_since.get_or_insert_with(Instant::now);
+                        match self.read_timeout {
+                            Some(timeout) if since.elapsed() < timeout => {
+                                std::thread::sleep(Duration::from_millis(10));
+                                continue;
+                            }
+                            _ => return Err(AudioContextError::FFMpegInputError(e)),
+                        }
+                    }
+                }
+            }
+            let mut received = false;
+            while self.decoder.receive_frame(&mut decoded).is_ok() {
+                received = true;
+                if decoded.channel_layout().is_empty() {
+                    decoded.set_channel_layout(default_channel_layout);
+                }
+                let frame_time = decoded
+                    .timestamp()
+                    .map(|f| Duration::from_secs_f64((f as f64 * time_base).max(0f64)))
+                    .unwrap_or(position);
+                if resampler.run(&decoded, &mut resampled).is_err() || resampled.samples() == 0 {
+                    continue;
+                }
+                let mut samples: &[T] = _packed(&resampled);
+                position = frame_time
+                    + Duration::from_secs_f64(resampled.samples() as f64 / sample_rate as f64);
+                // Seeking lands on a packet boundary, cut what comes before the track
+                let from = frame_time.max(start);
+                if frame_time < start {
+                    let skip = ((start - frame_time).as_secs_f64() * sample_rate as f64) as usize
+                        * channels;
+                    samples = &samples[skip.min(samples.len())..];
+                }
+                // And stop exactly where the next virtual track begins
+                let mut finished = false;
+                if let Some(end) = self.end {
+                    let allowed = (end.saturating_sub(from).as_secs_f64() * sample_rate as f64)
+                        as usize
+                        * channels;
+                    if allowed <= samples.len() {
+                        samples = &samples[..allowed];
+                        finished = true;
+                    }
+                }
+                if !shared.push(producer, samples, limit, prebuffer) {
+                    // Skip, seek or stop; handled at the top of the loop
+                    continue 'decode;
+                }
+                if finished {
+                    break 'decode;
+                }
+            }
+            if eof && !received {
+                break;
+            }
+        }
+        shared.buffering.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+    // Decodes the whole range as fast as it can, mono at the file's own rate
+    fn envelope(&mut self, buckets: usize) -> Result<Envelope, AudioContextError> {
+        let default_channel_layout = FFMpegChannelLayout::default(self.decoder.channels() as i32);
+        if self.decoder.channel_layout().is_empty() {
+            self.decoder.set_channel_layout(default_channel_layout);
+        }
+        let sample_rate = self.decoder.rate().max(1);
+        let mut resampler = self
+            .decoder
+            .resampler(
+                FFMpegSample::F32(FFMpegSampleType::Packed),
+                FFMpegChannelLayout::MONO,
+                sample_rate,
+            )
+            .map_err(AudioContextError::FFMpegResamplerError)?;
+        let time_base = self.time_base();
+        let start = self.start.unwrap_or_default();
+        if start > Duration::ZERO {
+            self.seek(start)?;
+        }
+        let mut builder = EnvelopeBuilder::new(sample_rate);
+        let mut packet = FFMpegPacket::empty();
+        let mut decoded = FFMpegFrame::empty();
+        let mut resampled = FFMpegFrame::empty();
+        let mut position = start;
+        let mut eof = false;
+        'decode: loop {
+            if !eof {
+                match packet.read(&mut self.input_context) {
+                    Ok(()) => {
+                        if packet.stream() != self.index {
+                            continue;
+                        }
+                        if let Err(e) = self.decoder.send_packet(&packet) {
+                            tracing::warn!("Dropping packet: {}", e);
+                            continue;
+                        }
+                    }
+                    Err(FFMpegError::Eof) => {
+                        let _ = self.decoder.send_eof();
+                        eof = true;
+                    }
+                    Err(e) => return Err(AudioContextError::FFMpegInputError(e)),
+                }
+            }
+            let mut received = false;
+            while self.decoder.receive_frame(&mut decoded).is_ok() {
+                received = true;
+                if decoded.channel_layout().is_empty() {
+                    decoded.set_channel_layout(default_channel_layout);
+                }
+                let frame_time = decoded
+                    .timestamp()
+                    .map(|f| Duration::from_secs_f64((f as f64 * time_base).max(0f64)))
+                    .unwrap_or(position);
+                if resampler.run(&decoded, &mut resampled).is_err() || resampled.samples() == 0 {
+                    continue;
+                }
+                let mut samples: &[f32] = _packed(&resampled);
+                position =
+                    frame_time + Duration::from_secs_f64(samples.len() as f64 / sample_rate as f64);
+                // Same cuts as `play`, virtual tracks only cover their part of the file
+                let from = frame_time.max(start);
+                if frame_time < start {
+                    let skip = ((start - frame_time).as_secs_f64() * sample_rate as f64) as usize;
+                    samples = &samples[skip.min(samples.len())..];
+                }
+                let mut finished = false;
+                if let Some(end) = self.end {
+                    let allowed =
+                        (end.saturating_sub(from).as_secs_f64() * sample_rate as f64) as usize;
+                    if allowed <= samples.len() {
+                        samples = &samples[..allowed];
+                        finished = true;
+                    }
+                }
+                builder.push(samples);
+                if finished {
+                    break 'decode;
+                }
+            }
+            if eof && !received {
+                break;
+            }
+        }
+        Ok(builder.finish(buckets))
+    }
+}
+
+// Loudness outline of a track for the seek bar. Decodes all of it, so keep it off the UI thread.
+pub fn read_envelope(data: &AudioData, buckets: usize) -> Option<Envelope> {
+    let mut context =
+        match AudioContext::new_file(&data.path, FFMpegSample::F32(FFMpegSampleType::Packed)) {
+            Ok(context) => context,
+            Err(e) => {
+                tracing::error!("Could not open {}: {}", data.path.display(), e);
+                return None;
+            }
+        };
+    context.start = data.start;
+    context.end = data.end;
+    match context.envelope(buckets) {
+        Ok(envelope) => Some(envelope).filter(|f| !f.is_empty()),
+        Err(e) => {
+            tracing::error!("Could not decode {}: {}", data.path.display(), e);
+            None
+        }
+    }
+}
+
+// ICY packets look like `StreamTitle='Artist - Title';StreamUrl='';`
+fn parse_icy_title(packet: &str) -> Option<String> {
+    let start = packet.find("StreamTitle='")? + "StreamTitle='".len();
+    let rest = &packet[start..];
+    // Titles may have `';` in them, the next field or the last one ends it
+    let end = rest
+        .find("';Stream")
+        .or_else(|| rest.rfind("';"))
+        .unwrap_or(rest.len());
+    // Packets are padded with zeros to a multiple of 16 bytes
+    let title = rest[..end].trim_matches(|f: char| f == '\0' || f.is_whitespace());
+    if title.is_empty() {
+        return None;
+    }
+    Some(title.to_string())
+}
+
+// Where a track starts in the stream of samples sent to the device
+#[derive(Debug, Clone, Copy)]
+struct AudioMarker {
+    sample: u64,
+    id: u64,
+    offset: Duration,
+}
+
+// Shared between the player, the decoding thread and the output callback
+struct AudioPlayerShared {
+    contexts: Mutex<VecDeque<AudioContext>>,
+    markers: Mutex<VecDeque<AudioMarker>>,
+    title: Mutex<Option<String>>,
+    // f32 bits, from 0 to 1
+    volume: AtomicU32,
+    running: AtomicBool,
+    paused: AtomicBool,
+    decoding: AtomicBool,
+    // Output stays silent while a network source fills up its pre-buffer
+    buffering: AtomicBool,
+    skip: AtomicBool,
+    // Requested seek in milliseconds plus one, 0 when nothing is requested
+    seek: AtomicU64,
+    // Interleaved samples that went into and out of the ring buffer
+    pushed: AtomicU64,
+    played: AtomicU64,
+    // Everything pushed before this is stale (after a seek/skip)
+    flush_to: AtomicU64,
+}
+
+impl AudioPlayerShared {
+    fn new() -> Self {
+        Self {
+            contexts: Mutex::new(VecDeque::new()),
+            markers: Mutex::new(VecDeque::new()),
+            title: Mutex::new(None),
+            volume: AtomicU32::new(1f32.to_bits()),
+            running: AtomicBool::new(true),
+            paused: AtomicBool::new(false),
+            decoding: AtomicBool::new(false),
+            buffering: AtomicBool::new(false),
+            skip: AtomicBool::new(false),
+            seek: AtomicU64::new(0),
+            pushed: AtomicU64::new(0),
+            played: AtomicU64::new(0),
+            flush_to: AtomicU64::new(0),
+        }
+    }
+    fn mark(&self, id: u64, offset: Duration) {
+        let sample = self.pushed.load(Ordering::Relaxed);
+        let played = self.played.load(Ordering::Relaxed);
+        let mut markers = self.markers.lock().unwrap();
+        // Forget about tracks that are done playing
+        while markers.len() > 1 && markers[1].sample <= played {
+            markers.pop_front();
+        }
+        markers.push_back(AudioMarker { sample, id, offset });
+    }
+    fn current_marker(&self) -> Option<AudioMarker> {
+        let played = self.played.load(Ordering::Relaxed);
+        let markers = self.markers.lock().unwrap();
+        markers.iter().rev().find(|f| f.sample <= played).copied()
+    }
+    fn flush(&self) {
+        self.flush_to
+            .store(self.pushed.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+    fn take_seek(&self) -> Option<Duration> {
+        match self.seek.swap(0, Ordering::Relaxed) {
+            0 => None,
+            x => Some(Duration::from_millis(x - 1)),
+        }
+    }
+    fn interrupted(&self) -> bool {
+        !self.running.load(Ordering::Relaxed)
+            || self.skip.load(Ordering::Relaxed)
+            || self.seek.load(Ordering::Relaxed) != 0
+    }
+    // Blocks until all samples are in the ring buffer, false when interrupted
+    fn push<T: Copy>(
+        &self,
+        producer: &mut HeapProducer<T>,
+        mut samples: &[T],
+        limit: usize,
+        prebuffer: usize,
+    ) -> bool {
+        while !samples.is_empty() {
+            if self.interrupted() {
+                return false;
+            }
+            let free = limit.saturating_sub(producer.len());
+            let pushed = producer.push_slice(&samples[..free.min(samples.len())]);
+            self.pushed.fetch_add(pushed as u64, Ordering::Relaxed);
+            samples = &samples[pushed..];
+            if producer.len() >= prebuffer {
+                self.buffering.store(false, Ordering::Relaxed);
+            }
+            if !samples.is_empty() {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        }
+        true
+    }
+}
+
+fn decode_audio<T: FFMpegFrameSample + Copy>(
+    shared: Arc<AudioPlayerShared>,
+    mut producer: HeapProducer<T>,
+    output: (FFMpegSample, FFMpegChannelLayout, u32),
+) {
+    while shared.running.load(Ordering::Relaxed) {
+        let context = shared.contexts.lock().unwrap().pop_front();
+        let Some(mut context) = context else {
+            shared.decoding.store(false, Ordering::Relaxed);
+            std::thread::sleep(Duration::from_millis(10));
+            continue;
+        };
+        shared.decoding.store(true, Ordering::Relaxed);
+        // Contexts are played back to back, so there is no gap between tracks
+        if let Err(e) = context.play(&shared, &mut producer, output) {
+            tracing::error!("Playback stopped: {}", e);
+        }
+    }
+}
+
+fn play_audio<T: SizedSample>(
+    data: &mut [T],
+    _: &cpal::OutputCallbackInfo,
+    samples: &mut HeapConsumer<T>,
+    shared: &AudioPlayerShared,
+    tap: &mut HeapProducer<f32>,
+    channels: usize,
+) {
+    // Throw away whatever was buffered before a seek/skip
+    let played = shared.played.load(Ordering::Relaxed);
+    let flush_to = shared.flush_to.load(Ordering::Relaxed);
+    if played < flush_to {
+        let skipped = samples.skip((flush_to - played) as usize);
+        shared.played.fetch_add(skipped as u64, Ordering::Relaxed);
+    }
+    if shared.paused.load(Ordering::Relaxed) || shared.buffering.load(Ordering::Relaxed) {
+        data.fill(T::EQUILIBRIUM);
+        return;
+    }
+    let popped = samples.pop_slice(data);
+    data[popped..].fill(T::EQUILIBRIUM);
+    let volume = f32::from_bits(shared.volume.load(Ordering::Relaxed));
+    if volume < 1f32 {
+        let amplitude = T::Float::from_sample(volume);
+        for sample in data[..popped].iter_mut() {
+            *sample = sample.mul_amp(amplitude);
+        }
+    }
+    // Mono copy for the visualizer. Never waits, whatever does not fit is dropped.
+    for frame in data[..popped].chunks(channels) {
+        let sum: f32 = frame
+            .iter()
+            .map(|f| f.to_float_sample().to_sample::<f32>())
+            .sum();
+        let _ = tap.push(sum / channels as f32);
+    }
+    shared.played.fetch_add(popped as u64, Ordering::Relaxed);
 }
 
 pub struct AudioPlayer<T>
@@ -89,42 +703,481 @@ where
     sample_format: CpalSampleFormat,
     sample_rate: u32,
     channels: u16,
-    data_buffer: HeapRb<T>,
-    contexts: Vec<AudioContext>,
+    shared: Arc<AudioPlayerShared>,
+    network_options: AudioNetworkOptions,
+    next_id: u64,
+    // What `play_audio` sent to the device, for the visualizer
+    tap: HeapConsumer<f32>,
+    _sample: PhantomData<T>,
 }
 
 impl<T> AudioPlayer<T>
 where
-    T: num::Num,
+    T: num::Num + SizedSample + FFMpegFrameSample + Send + 'static,
 {
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self, AudioPlayerError> {
         let host = cpal::default_host();
-        let device = host.default_output_device().unwrap();
-        let mut supported_config_range = device.supported_output_configs().unwrap();
+        let device = host
+            .default_output_device()
+            .ok_or(AudioPlayerError::NoOutputDevice)?;
+        let mut supported_config_range = device
+            .supported_output_configs()
+            .map_err(AudioPlayerError::OutputConfigError)?;
         let supported_config = supported_config_range
             .next()
-            .unwrap()
+            .ok_or(AudioPlayerError::NoOutputConfig)?
             .with_max_sample_rate();
         let config = supported_config.config();
         let sample_format = supported_config.sample_format();
         let sample_rate = supported_config.sample_rate().0;
         let channels = supported_config.channels();
-        let data_buffer: HeapRb<T> = HeapRb::new(AUDIO_DATA_BUFFER_SIZE);
-        Self {
+        let shared = Arc::new(AudioPlayerShared::new());
+        // Big enough for network pre-buffering, local files only use part of it
+        let data_buffer: HeapRb<T> = HeapRb::new(AUDIO_NETWORK_BUFFER_SIZE);
+        let (producer, consumer) = data_buffer.split();
+        let output = (
+            T::FORMAT.as_ffmpeg_sample_format(),
+            FFMpegChannelLayout::default(channels as i32),
+            sample_rate,
+        );
+        let decoder_shared = shared.clone();
+        std::thread::spawn(move || decode_audio(decoder_shared, producer, output));
+        let (tap_producer, tap) = HeapRb::<f32>::new(AUDIO_TAP_SIZE).split();
+        Self::spawn_output(&device, &config, consumer, tap_producer, shared.clone());
+        Ok(Self {
             host,
             device,
             config,
             sample_format,
             sample_rate,
             channels,
-            data_buffer,
-            contexts: vec![],
+            shared,
+            network_options: AudioNetworkOptions::default(),
+            next_id: 0,
+            tap,
+            _sample: PhantomData,
+        })
+    }
+    // cpal streams cant be sent between threads, so it lives in its own
+    fn spawn_output(
+        device: &Device,
+        config: &StreamConfig,
+        mut consumer: HeapConsumer<T>,
+        mut tap: HeapProducer<f32>,
+        shared: Arc<AudioPlayerShared>,
+    ) {
+        let device = device.clone();
+        let config = config.clone();
+        let channels = config.channels.max(1) as usize;
+        std::thread::spawn(move || {
+            let callback_shared = shared.clone();
+            let stream = device.build_output_stream(
+                &config,
+                move |data: &mut [T], cb: &cpal::OutputCallbackInfo| {
+                    play_audio(
+                        data,
+                        cb,
+                        &mut consumer,
+                        &callback_shared,
+                        &mut tap,
+                        channels,
+                    )
+                },
+                |err| tracing::error!("Audio output error: {}", err),
+                None,
+            );
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    tracing::error!("Could not open audio output: {}", e);
+                    return;
+                }
+            };
+            if let Err(e) = stream.play() {
+                tracing::error!("Could not start audio output: {}", e);
+                return;
+            }
+            while shared.running.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        });
+    }
+    fn enqueue(&mut self, mut context: AudioContext) -> u64 {
+        self.next_id += 1;
+        context.id = self.next_id;
+        self.shared.contexts.lock().unwrap().push_back(context);
+        self.next_id
+    }
+    pub fn play_file<P: AsRef<Path>>(&mut self, file: P) -> Option<u64> {
+        self.play_file_range(file, None, None)
+    }
+    // Plays `start..end` of a file, used for CUE sheet tracks
+    pub fn play_file_range<P: AsRef<Path>>(
+        &mut self,
+        file: P,
+        start: Option<Duration>,
+        end: Option<Duration>,
+    ) -> Option<u64> {
+        self.play_file_from(file, start, end, Duration::ZERO)
+    }
+    // Like `play_file_range`, but starts `offset` into the range
+    pub fn play_file_from<P: AsRef<Path>>(
+        &mut self,
+        file: P,
+        start: Option<Duration>,
+        end: Option<Duration>,
+        offset: Duration,
+    ) -> Option<u64> {
+        let file = file.as_ref();
+        match AudioContext::new_file(file, self.sample_format.as_ffmpeg_sample_format()) {
+            Ok(mut context) => {
+                context.start = start;
+                context.end = end;
+                context.offset = offset;
+                Some(self.enqueue(context))
+            }
+            Err(e) => {
+                tracing::error!("Could not open {}: {}", file.display(), e);
+                None
+            }
         }
     }
-    pub fn play_file<P: AsRef<Path>>(&mut self, file: P) {
-        if let Ok(file) = AudioContext::new_file(file, self.sample_format.as_ffmpeg_sample_format())
-        {
-            self.contexts.push(file)
+    pub fn play_audio_data(&mut self, data: &AudioData) -> Option<u64> {
+        self.play_file_range(&data.path, data.start, data.end)
+    }
+    pub fn resume_audio_data(&mut self, data: &AudioData, offset: Duration) -> Option<u64> {
+        self.play_file_from(&data.path, data.start, data.end, offset)
+    }
+    pub fn play_url(&mut self, url: &str) -> Option<u64> {
+        match AudioContext::new_url(url, &self.network_options) {
+            Ok(context) => Some(self.enqueue(context)),
+            Err(e) => {
+                tracing::error!("Could not open {}: {}", url, e);
+                None
+            }
         }
     }
+    pub fn set_network_options(&mut self, options: AudioNetworkOptions) {
+        self.network_options = options;
+    }
+    // Drops everything queued up and whatever is playing right now
+    pub fn stop(&mut self) {
+        self.clear_queued();
+        self.skip();
+    }
+    // Drops the tracks lined up after the current one, which keeps playing
+    pub fn clear_queued(&mut self) {
+        self.shared.contexts.lock().unwrap().clear();
+    }
+    pub fn skip(&mut self) {
+        if self.shared.decoding.load(Ordering::Relaxed) {
+            self.shared.skip.store(true, Ordering::Relaxed);
+        }
+    }
+    pub fn seek(&mut self, position: Duration) {
+        if !self.shared.decoding.load(Ordering::Relaxed) {
+            return;
+        }
+        self.shared
+            .seek
+            .store(position.as_millis() as u64 + 1, Ordering::Relaxed);
+    }
+    pub fn pause(&mut self) {
+        self.shared.paused.store(true, Ordering::Relaxed);
+    }
+    pub fn resume(&mut self) {
+        self.shared.paused.store(false, Ordering::Relaxed);
+    }
+    pub fn set_volume(&mut self, volume: f32) {
+        self.shared
+            .volume
+            .store(volume.clamp(0f32, 1f32).to_bits(), Ordering::Relaxed);
+    }
+    pub fn volume(&self) -> f32 {
+        f32::from_bits(self.shared.volume.load(Ordering::Relaxed))
+    }
+    pub fn is_paused(&self) -> bool {
+        self.shared.paused.load(Ordering::Relaxed)
+    }
+    pub fn is_idle(&self) -> bool {
+        !self.shared.decoding.load(Ordering::Relaxed)
+            && self.shared.contexts.lock().unwrap().is_empty()
+            && self.shared.played.load(Ordering::Relaxed)
+                >= self.shared.pushed.load(Ordering::Relaxed)
+    }
+    // Id (as returned by `play_*`) of the track coming out of the speakers
+    pub fn current(&self) -> Option<u64> {
+        if self.is_idle() {
+            return None;
+        }
+        self.shared.current_marker().map(|f| f.id)
+    }
+    pub fn position(&self) -> Option<Duration> {
+        if self.is_idle() {
+            return None;
+        }
+        let marker = self.shared.current_marker()?;
+        let played = self.shared.played.load(Ordering::Relaxed) - marker.sample;
+        let samples_per_second = self.sample_rate as f64 * self.channels.max(1) as f64;
+        Some(marker.offset + Duration::from_secs_f64(played as f64 / samples_per_second))
+    }
+    // Amount of tracks waiting to be decoded
+    pub fn queued(&self) -> usize {
+        self.shared.contexts.lock().unwrap().len()
+    }
+    // Ids of the tracks waiting to be decoded, in order
+    pub fn queued_ids(&self) -> Vec<u64> {
+        self.shared
+            .contexts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|f| f.id)
+            .collect()
+    }
+    // Mono samples played since the last call, oldest first
+    pub fn tapped(&mut self) -> impl Iterator<Item = f32> + '_ {
+        self.tap.pop_iter()
+    }
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    // "Now playing" title sent by internet radio
+    pub fn stream_title(&self) -> Option<String> {
+        self.shared.title.lock().unwrap().clone()
+    }
+}
+
+impl<T> Drop for AudioPlayer<T>
+where
+    T: num::Num,
+{
+    fn drop(&mut self) {
+        self.shared.running.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        f32::consts::PI,
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+    // A tenth of a second
+    const SAMPLES: usize = 4410;
+
+    // Mono 16 bit PCM, small enough to build in memory
+    fn sine_wav() -> Vec<u8> {
+        let data_size = (SAMPLES * 2) as u32;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_size.to_le_bytes());
+        for i in 0..SAMPLES {
+            let sample = (2.0 * PI * 440.0 * i as f32 / SAMPLE_RATE as f32).sin();
+            wav.extend_from_slice(&((sample * i16::MAX as f32 * 0.5) as i16).to_le_bytes());
+        }
+        wav
+    }
+
+    // Serves `body` to every request, honoring `Range: bytes=<from>-` as ffmpeg may ask for it
+    fn serve(body: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut from = 0;
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or_default() == 0
+                        || line.trim().is_empty()
+                    {
+                        break;
+                    }
+                    let lower = line.to_lowercase();
+                    if let Some(range) = lower.strip_prefix("range: bytes=") {
+                        from = range
+                            .split('-')
+                            .next()
+                            .and_then(|f| f.trim().parse::<usize>().ok())
+                            .unwrap_or_default()
+                            .min(body.len());
+                    }
+                }
+                let status = match from {
+                    0 => String::from("200 OK"),
+                    _ => String::from("206 Partial Content"),
+                };
+                let header = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: audio/wav\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\nAccept-Ranges: bytes\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len() - from,
+                    from,
+                    body.len().saturating_sub(1),
+                    body.len()
+                );
+                let _ = stream.write_all(header.as_bytes());
+                let _ = stream.write_all(&body[from..]);
+            }
+        });
+        format!("http://{}/sine.wav", address)
+    }
+
+    #[test]
+    fn decodes_a_file_served_over_http() {
+        ffmpeg_next::init().unwrap();
+        let url = serve(sine_wav());
+        let options = AudioNetworkOptions {
+            timeout: Duration::from_secs(5),
+            reconnect: false,
+            ..Default::default()
+        };
+        let mut context = AudioContext::new_url(&url, &options).unwrap();
+        assert_eq!(context.decoder.rate(), SAMPLE_RATE);
+        assert_eq!(context.decoder.channels(), 1);
+        assert_eq!(context.read_timeout, Some(Duration::from_secs(5)));
+        let mut packet = FFMpegPacket::empty();
+        let mut frame = FFMpegFrame::empty();
+        let mut decoded = 0;
+        while packet.read(&mut context.input_context).is_ok() {
+            if packet.stream() != context.index {
+                continue;
+            }
+            context.decoder.send_packet(&packet).unwrap();
+            while context.decoder.receive_frame(&mut frame).is_ok() {
+                decoded += frame.samples();
+            }
+        }
+        let _ = context.decoder.send_eof();
+        while context.decoder.receive_frame(&mut frame).is_ok() {
+            decoded += frame.samples();
+        }
+        assert_eq!(decoded, SAMPLES);
+    }
+
+    #[test]
+    fn unreachable_urls_fail_to_open() {
+        ffmpeg_next::init().unwrap();
+        // Bound and dropped right away, nothing is listening there anymore
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let options = AudioNetworkOptions {
+            timeout: Duration::from_secs(1),
+            reconnect: false,
+            ..Default::default()
+        };
+        assert!(AudioContext::new_url(&format!("http://{}/none.wav", address), &options).is_err());
+    }
+
+    // Like `serve`, as an endless radio station sending `packet` every `meta_interval` bytes
+    fn serve_icy(body: Vec<u8>, meta_interval: usize, packet: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut metadata = packet.as_bytes().to_vec();
+        metadata.resize(packet.len().div_ceil(16) * 16, 0);
+        metadata.insert(0, (metadata.len() / 16) as u8);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or_default() > 0 && line.trim() != "" {
+                    line.clear();
+                }
+                let header = format!(
+                    "HTTP/1.0 200 OK\r\nContent-Type: audio/wav\r\nicy-name: Test\r\nicy-metaint: {}\r\n\r\n",
+                    meta_interval
+                );
+                let _ = stream.write_all(header.as_bytes());
+                for chunk in body.chunks(meta_interval) {
+                    let _ = stream.write_all(chunk);
+                    if chunk.len() == meta_interval {
+                        let _ = stream.write_all(&metadata);
+                    }
+                }
+            }
+        });
+        format!("http://{}/radio", address)
+    }
+
+    #[test]
+    fn icy_titles() {
+        let title = |packet: &str| parse_icy_title(packet);
+        assert_eq!(
+            title("StreamTitle='Artist - Title';StreamUrl='';").as_deref(),
+            Some("Artist - Title")
+        );
+        assert_eq!(
+            title("StreamTitle='Guns N' Roses - Sweet Child O' Mine';").as_deref(),
+            Some("Guns N' Roses - Sweet Child O' Mine")
+        );
+        assert_eq!(
+            title("StreamTitle='Artist - Part 1; Part 2';").as_deref(),
+            Some("Artist - Part 1; Part 2")
+        );
+        // Only the next field or the end of the packet close the title
+        assert_eq!(
+            title("StreamTitle='Say 'Hi';Bye';StreamUrl='http://radio';").as_deref(),
+            Some("Say 'Hi';Bye")
+        );
+        assert_eq!(
+            title("StreamTitle='Say 'Hi';Bye';\0\0\0").as_deref(),
+            Some("Say 'Hi';Bye")
+        );
+        assert_eq!(
+            title("StreamTitle=' Unterminated ").as_deref(),
+            Some("Unterminated")
+        );
+    }
+
+    #[test]
+    fn missing_icy_titles() {
+        assert_eq!(parse_icy_title(""), None);
+        assert_eq!(parse_icy_title("StreamUrl='http://radio';"), None);
+        assert_eq!(parse_icy_title("StreamTitle='';StreamUrl='';"), None);
+        assert_eq!(parse_icy_title("StreamTitle='  ';\0\0"), None);
+    }
+
+    #[test]
+    fn reads_the_title_of_a_radio_stream() {
+        ffmpeg_next::init().unwrap();
+        let options = AudioNetworkOptions {
+            timeout: Duration::from_secs(5),
+            reconnect: false,
+            ..Default::default()
+        };
+        let url = serve_icy(sine_wav(), 1024, "StreamTitle='Artist - Part 1; Part 2';");
+        let mut context = AudioContext::new_url(&url, &options).unwrap();
+        let mut packet = FFMpegPacket::empty();
+        while packet.read(&mut context.input_context).is_ok() {}
+        assert_eq!(
+            context.icy_title().as_deref(),
+            Some("Artist - Part 1; Part 2")
+        );
+        // Plain files have no metadata packet at all
+        let context = AudioContext::new_url(&serve(sine_wav()), &options).unwrap();
+        assert_eq!(context.icy_title(), None);
+    }
 }
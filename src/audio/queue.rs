@@ -0,0 +1,622 @@
+use std::{collections::HashMap, time::Duration};
+
+use cpal::SizedSample;
+use ffmpeg_next::frame::audio::Sample as FFMpegFrameSample;
+use serde::{Deserialize, Serialize};
+
+use super::player::AudioPlayer;
+use crate::db::AudioData;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RepeatMode {
+    #[default]
+    Off,
+    One,
+    All,
+}
+
+impl RepeatMode {
+    pub fn next(&self) -> Self {
+        match self {
+            Self::Off => Self::All,
+            Self::All => Self::One,
+            Self::One => Self::Off,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Off => "repeat off",
+            Self::One => "repeat one",
+            Self::All => "repeat all",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShuffleMode {
+    #[default]
+    Off,
+    Tracks,
+    // Albums come in a random order, their tracks stay together
+    Albums,
+}
+
+impl ShuffleMode {
+    pub fn next(&self) -> Self {
+        match self {
+            Self::Off => Self::Tracks,
+            Self::Tracks => Self::Albums,
+            Self::Albums => Self::Off,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Off => "shuffle off",
+            Self::Tracks => "shuffle tracks",
+            Self::Albums => "shuffle albums",
+        }
+    }
+}
+
+// SplitMix64, small and good enough to shuffle with, the same seed always gives the same order
+#[derive(Debug, Clone, Default)]
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+    // In `0..n`, the modulo bias doesnt matter for queue sizes
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n.max(1) as u64) as usize
+    }
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct QueueEntry {
+    track: AudioData,
+    // Stays the same while the entry is in the queue, indices dont
+    key: u64,
+    // Handed out by the player every time the track was sent to it, with repeat one
+    // the track can be playing and lined up at once
+    ids: Vec<u64>,
+    // Could not be opened, skipped from then on
+    broken: bool,
+}
+
+// What plays next, the player itself only ever knows about the current and the next track
+#[derive(Debug, Default)]
+pub struct PlayQueue {
+    entries: Vec<QueueEntry>,
+    current: Option<usize>,
+    // False once the end was reached or playback was stopped
+    playing: bool,
+    // Id of the track sent ahead of time and whether it starts the next round of `RepeatMode::All`
+    lined_up: Option<(u64, bool)>,
+    repeat: RepeatMode,
+    shuffle: ShuffleMode,
+    // Entry keys in the order they play in while shuffling, every entry shows up exactly once
+    order: Vec<u64>,
+    seed: u64,
+    // Rounds played through with `RepeatMode::All`, every round is shuffled anew
+    round: u64,
+    // Decides where tracks added while shuffling end up
+    rng: Rng,
+    next_key: u64,
+}
+
+impl PlayQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn tracks(&self) -> Vec<AudioData> {
+        self.entries.iter().map(|f| f.track.clone()).collect()
+    }
+    // The track the player handed out `id` for
+    pub fn track_for_id(&self, id: u64) -> Option<&AudioData> {
+        self.position(id).map(|f| &self.entries[f].track)
+    }
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+    pub fn repeat(&self) -> RepeatMode {
+        self.repeat
+    }
+    pub fn shuffle(&self) -> ShuffleMode {
+        self.shuffle
+    }
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.repeat = repeat;
+    }
+    // The current track goes first, so everything else plays once before anything repeats
+    pub fn set_shuffle(&mut self, shuffle: ShuffleMode, seed: u64) {
+        self.shuffle = shuffle;
+        self.seed = seed;
+        self.round = 0;
+        self.rng = Rng(seed);
+        self.order.clear();
+        if shuffle == ShuffleMode::Off {
+            return;
+        }
+        self.order = self.shuffled(0);
+        if let Some(current) = self.current {
+            let group = self.group_of(&self.entries[current]);
+            let (mut front, rest): (Vec<u64>, Vec<u64>) = self
+                .order
+                .iter()
+                .partition(|f| self.entry(**f).is_some_and(|f| self.group_of(f) == group));
+            // The current track itself leads its album
+            let key = self.entries[current].key;
+            front.retain(|f| *f != key);
+            front.insert(0, key);
+            front.extend(rest);
+            self.order = front;
+        }
+    }
+    pub fn append(&mut self, tracks: Vec<AudioData>) {
+        let index = self.entries.len();
+        self.insert(index, tracks);
+    }
+    // Right after the current track, or at the front when nothing is playing
+    pub fn insert_next(&mut self, tracks: Vec<AudioData>) {
+        let index = self.current.map_or(0, |f| f + 1);
+        let keys = self.insert(index, tracks);
+        if self.shuffle == ShuffleMode::Off {
+            return;
+        }
+        // Next means next, shuffled or not
+        self.order.retain(|f| !keys.contains(f));
+        let position = self.order_position().map_or(0, |f| f + 1);
+        self.order.splice(position..position, keys);
+    }
+    fn insert(&mut self, index: usize, tracks: Vec<AudioData>) -> Vec<u64> {
+        let entries: Vec<QueueEntry> = tracks
+            .into_iter()
+            .map(|track| {
+                self.next_key += 1;
+                QueueEntry {
+                    track,
+                    key: self.next_key,
+                    ids: Vec::new(),
+                    broken: false,
+                }
+            })
+            .collect();
+        let keys: Vec<u64> = entries.iter().map(|f| f.key).collect();
+        let count = entries.len();
+        self.entries.splice(index..index, entries);
+        if let Some(current) = self.current.filter(|f| *f >= index) {
+            self.current = Some(current + count);
+        }
+        if self.shuffle != ShuffleMode::Off {
+            for key in keys.iter() {
+                self.shuffle_in(*key);
+            }
+        }
+        keys
+    }
+    // Somewhere in what is left to play, next to the rest of the album when shuffling albums
+    fn shuffle_in(&mut self, key: u64) {
+        let start = self.order_position().map_or(0, |f| f + 1);
+        let group = self.entry(key).map(|f| self.group_of(f));
+        let album_end = self.order[start..]
+            .iter()
+            .rposition(|f| self.entry(*f).map(|f| self.group_of(f)) == group)
+            .filter(|_| self.shuffle == ShuffleMode::Albums)
+            .map(|f| start + f + 1);
+        let position = match album_end {
+            Some(position) => position,
+            None => {
+                // Only between albums, never in the middle of one
+                let boundaries: Vec<usize> = (start..=self.order.len())
+                    .filter(|f| {
+                        self.shuffle == ShuffleMode::Tracks
+                            || *f == 0
+                            || *f == self.order.len()
+                            || self.group_of_key(self.order[f - 1])
+                                != self.group_of_key(self.order[*f])
+                    })
+                    .collect();
+                boundaries[self.rng.below(boundaries.len())]
+            }
+        };
+        self.order.insert(position, key);
+    }
+    // Works without a player too (no audio output), nothing can be playing then
+    pub fn remove<T>(
+        &mut self,
+        index: usize,
+        player: Option<&mut AudioPlayer<T>>,
+    ) -> Option<AudioData>
+    where
+        T: num::Num + SizedSample + FFMpegFrameSample + Send + 'static,
+    {
+        if index >= self.entries.len() {
+            return None;
+        }
+        // Whatever would have played next takes its place
+        let next = match self.current {
+            Some(current) if current == index && self.playing => self
+                .next_index()
+                .filter(|f| f.0 != index)
+                .map(|f| self.entries[f.0].key),
+            _ => None,
+        };
+        let entry = self.entries.remove(index);
+        self.order.retain(|f| *f != entry.key);
+        match self.current {
+            Some(current) if current == index => {
+                self.current = None;
+                let next = next.and_then(|f| self.entries.iter().position(|entry| entry.key == f));
+                match (next, player) {
+                    (Some(next), Some(player)) => self.play(next, player),
+                    (_, Some(player)) => self.stop(player),
+                    (_, None) => self.stopped(),
+                }
+            }
+            Some(current) if current > index => self.current = Some(current - 1),
+            _ => {}
+        }
+        Some(entry.track)
+    }
+    // Moves an entry to `to`, everything in between shifts by one
+    pub fn move_entry(&mut self, from: usize, to: usize) {
+        if from == to || from >= self.entries.len() || to >= self.entries.len() {
+            return;
+        }
+        let entry = self.entries.remove(from);
+        self.entries.insert(to, entry);
+        self.current = self.current.map(|current| match current {
+            _ if current == from => to,
+            _ if from < current && current <= to => current - 1,
+            _ if to <= current && current < from => current + 1,
+            _ => current,
+        });
+    }
+    pub fn clear<T>(&mut self, player: Option<&mut AudioPlayer<T>>)
+    where
+        T: num::Num + SizedSample + FFMpegFrameSample + Send + 'static,
+    {
+        match player {
+            Some(player) => self.stop(player),
+            None => self.stopped(),
+        }
+        self.entries.clear();
+        self.order.clear();
+    }
+    // Starts over at `index`, tracks that cant be opened are skipped
+    pub fn play<T>(&mut self, index: usize, player: &mut AudioPlayer<T>)
+    where
+        T: num::Num + SizedSample + FFMpegFrameSample + Send + 'static,
+    {
+        self.stop(player);
+        // Ids from earlier runs mean nothing to the player anymore
+        for entry in self.entries.iter_mut() {
+            entry.ids.clear();
+        }
+        let mut index = Some(index).filter(|f| *f < self.entries.len());
+        while let Some(i) = index {
+            let entry = &mut self.entries[i];
+            let id = player.play_audio_data(&entry.track);
+            entry.ids.extend(id);
+            entry.broken = id.is_none();
+            self.current = Some(i);
+            if id.is_some() {
+                self.playing = true;
+                return;
+            }
+            index = self.next_index().map(|f| f.0);
+        }
+        self.current = None;
+    }
+    // Picks up where an earlier session left off, paused `offset` into the track
+    pub fn resume<T>(&mut self, index: usize, offset: Duration, player: &mut AudioPlayer<T>)
+    where
+        T: num::Num + SizedSample + FFMpegFrameSample + Send + 'static,
+    {
+        self.stop(player);
+        let Some(entry) = self.entries.get_mut(index) else {
+            return;
+        };
+        player.pause();
+        let id = player.resume_audio_data(&entry.track, offset);
+        entry.ids.extend(id);
+        entry.broken = id.is_none();
+        if id.is_some() {
+            self.current = Some(index);
+            self.playing = true;
+        }
+    }
+    pub fn stop<T>(&mut self, player: &mut AudioPlayer<T>)
+    where
+        T: num::Num + SizedSample + FFMpegFrameSample + Send + 'static,
+    {
+        player.stop();
+        self.stopped();
+    }
+    fn stopped(&mut self) {
+        self.current = None;
+        self.playing = false;
+        self.lined_up = None;
+    }
+    // Follows the player along the queue and keeps the next track lined up,
+    // true when the current track changed
+    pub fn sync<T>(&mut self, player: &mut AudioPlayer<T>) -> bool
+    where
+        T: num::Num + SizedSample + FFMpegFrameSample + Send + 'static,
+    {
+        if !self.playing {
+            return false;
+        }
+        let previous = self.current;
+        if let Some(id) = player.current() {
+            if let Some(index) = self.position(id) {
+                self.current = Some(index);
+            }
+            if let Some((lined_up, next_round)) = self.lined_up {
+                if lined_up == id {
+                    self.lined_up = None;
+                    if next_round {
+                        self.next_round();
+                    }
+                }
+            }
+        }
+        let next = self.next_index();
+        // Editing the queue or switching modes can leave the wrong track lined up
+        if let Some((lined_up, _)) = self.lined_up {
+            let expected = next.map(|(f, _)| self.entries[f].ids.contains(&lined_up));
+            if expected != Some(true) && player.queued_ids().contains(&lined_up) {
+                player.clear_queued();
+                self.lined_up = None;
+            }
+        }
+        match (next, self.lined_up) {
+            (Some((next, next_round)), None) => {
+                // Sent ahead of time, so there is no gap between tracks
+                let current = player.current();
+                let queued = player.queued_ids();
+                let entry = &mut self.entries[next];
+                // Ids the player is done with, the one still playing has to stay
+                entry
+                    .ids
+                    .retain(|f| Some(*f) == current || queued.contains(f));
+                match player.play_audio_data(&entry.track) {
+                    Some(id) => {
+                        entry.ids.push(id);
+                        self.lined_up = Some((id, next_round));
+                    }
+                    None => entry.broken = true,
+                }
+            }
+            // Ran dry before the lined up track showed up, so it failed to decode
+            (_, Some((lined_up, _))) if player.is_idle() => {
+                if let Some(index) = self.position(lined_up) {
+                    self.entries[index].broken = true;
+                }
+                self.lined_up = None;
+            }
+            (None, None) if player.is_idle() => {
+                self.current = None;
+                self.playing = false;
+            }
+            _ => {}
+        }
+        previous != self.current
+    }
+    fn entry(&self, key: u64) -> Option<&QueueEntry> {
+        self.entries.iter().find(|f| f.key == key)
+    }
+    fn position(&self, id: u64) -> Option<usize> {
+        self.entries.iter().position(|f| f.ids.contains(&id))
+    }
+    // Where the current track is in `order`
+    fn order_position(&self) -> Option<usize> {
+        let key = self.entries.get(self.current?)?.key;
+        self.order.iter().position(|f| *f == key)
+    }
+    // Tracks without an album are an album of their own
+    fn group_of(&self, entry: &QueueEntry) -> (String, String, u64) {
+        if entry.track.album.is_empty() || self.shuffle != ShuffleMode::Albums {
+            return (String::new(), String::new(), entry.key);
+        }
+        (
+            entry.track.album_artist().to_lowercase(),
+            entry.track.album.to_lowercase(),
+            0,
+        )
+    }
+    fn group_of_key(&self, key: u64) -> Option<(String, String, u64)> {
+        self.entry(key).map(|f| self.group_of(f))
+    }
+    // Entry keys of one shuffled round, albums keep their queue order
+    fn shuffled(&self, round: u64) -> Vec<u64> {
+        let mut groups: Vec<((String, String, u64), Vec<u64>)> = Vec::new();
+        for entry in self.entries.iter() {
+            let group = self.group_of(entry);
+            match groups.iter_mut().find(|f| f.0 == group) {
+                Some(found) => found.1.push(entry.key),
+                None => groups.push((group, Vec::from([entry.key]))),
+            }
+        }
+        Rng(self.seed.wrapping_add(round)).shuffle(&mut groups);
+        groups.into_iter().flat_map(|f| f.1).collect()
+    }
+    fn next_round(&mut self) {
+        self.round += 1;
+        if self.shuffle != ShuffleMode::Off {
+            self.order = self.shuffled(self.round);
+        }
+    }
+    // Indices of the entries in the order they play in
+    fn sequence(&self, order: &[u64]) -> Vec<usize> {
+        if self.shuffle == ShuffleMode::Off {
+            return (0..self.entries.len()).collect();
+        }
+        let indices: HashMap<u64, usize> = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.key, i))
+            .collect();
+        order
+            .iter()
+            .filter_map(|f| indices.get(f).copied())
+            .collect()
+    }
+    // The entry to play after the current one and whether that starts a new round
+    fn next_index(&self) -> Option<(usize, bool)> {
+        let playable = |f: &usize| !self.entries[*f].broken;
+        if self.repeat == RepeatMode::One {
+            if let Some(current) = self.current.filter(playable) {
+                return Some((current, false));
+            }
+        }
+        let sequence = self.sequence(&self.order);
+        let start = self
+            .current
+            .and_then(|current| sequence.iter().position(|f| *f == current))
+            .map_or(0, |f| f + 1);
+        if let Some(next) = sequence[start..].iter().copied().find(playable) {
+            return Some((next, false));
+        }
+        if self.repeat != RepeatMode::All {
+            return None;
+        }
+        let order = match self.shuffle {
+            ShuffleMode::Off => Vec::new(),
+            _ => self.shuffled(self.round + 1),
+        };
+        self.sequence(&order)
+            .into_iter()
+            .find(playable)
+            .map(|f| (f, true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn queue(tracks: &[(&str, &str)]) -> PlayQueue {
+        let mut queue = PlayQueue::new();
+        queue.append(
+            tracks
+                .iter()
+                .map(|(name, album)| {
+                    let mut track = AudioData::new(PathBuf::from(format!("/music/{}.flac", name)));
+                    track.album = album.to_string();
+                    track
+                })
+                .collect(),
+        );
+        queue
+    }
+
+    fn singles(count: usize) -> PlayQueue {
+        let names: Vec<String> = (0..count).map(|f| f.to_string()).collect();
+        queue(&names.iter().map(|f| (f.as_str(), "")).collect::<Vec<_>>())
+    }
+
+    fn keys(queue: &PlayQueue) -> Vec<u64> {
+        queue.entries.iter().map(|f| f.key).collect()
+    }
+
+    fn sorted(mut order: Vec<u64>) -> Vec<u64> {
+        order.sort();
+        order
+    }
+
+    #[test]
+    fn same_seed_same_order() {
+        let mut first = singles(20);
+        let mut second = singles(20);
+        first.set_shuffle(ShuffleMode::Tracks, 42);
+        second.set_shuffle(ShuffleMode::Tracks, 42);
+        assert_eq!(first.order, second.order);
+        assert_ne!(first.order, keys(&first));
+        second.set_shuffle(ShuffleMode::Tracks, 43);
+        assert_ne!(first.order, second.order);
+        // Every round is shuffled anew, but the same way for the same seed
+        first.next_round();
+        second.set_shuffle(ShuffleMode::Tracks, 42);
+        second.next_round();
+        assert_eq!(first.order, second.order);
+        assert_ne!(first.order, first.shuffled(0));
+    }
+
+    #[test]
+    fn every_entry_once_per_round() {
+        let mut queue = singles(50);
+        queue.set_shuffle(ShuffleMode::Tracks, 7);
+        for _ in 0..5 {
+            assert_eq!(sorted(queue.order.clone()), keys(&queue));
+            queue.next_round();
+        }
+        // Added tracks join the round once too
+        queue.append(Vec::from([AudioData::new(PathBuf::from(
+            "/music/new.flac",
+        ))]));
+        queue.insert_next(Vec::from([AudioData::new(PathBuf::from(
+            "/music/next.flac",
+        ))]));
+        assert_eq!(sorted(queue.order.clone()), sorted(keys(&queue)));
+    }
+
+    #[test]
+    fn albums_stay_together() {
+        let mut queue = queue(&[
+            ("a1", "A"),
+            ("b1", "B"),
+            ("a2", "A"),
+            ("single", ""),
+            ("c1", "C"),
+            ("b2", "B"),
+            ("c2", "C"),
+        ]);
+        queue.set_shuffle(ShuffleMode::Albums, 3);
+        let names: Vec<String> = queue
+            .sequence(&queue.order)
+            .into_iter()
+            .map(|f| queue.entries[f].track.path.display().to_string())
+            .collect();
+        assert_eq!(names.len(), 7);
+        for (first, second) in [("a1", "a2"), ("b1", "b2"), ("c1", "c2")] {
+            let position = |name: &str| {
+                names
+                    .iter()
+                    .position(|f| *f == format!("/music/{}.flac", name))
+                    .unwrap()
+            };
+            assert_eq!(position(first) + 1, position(second));
+        }
+    }
+
+    #[test]
+    fn current_track_leads_the_shuffle() {
+        let mut queue = singles(10);
+        queue.current = Some(6);
+        queue.set_shuffle(ShuffleMode::Tracks, 11);
+        assert_eq!(queue.order[0], queue.entries[6].key);
+        assert_eq!(sorted(queue.order.clone()), keys(&queue));
+    }
+}
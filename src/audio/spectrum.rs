@@ -0,0 +1,233 @@
+use std::f32::consts::PI;
+
+// 2048 samples are ~43ms at 48kHz, enough resolution for the low bands
+const FFT_SIZE: usize = 2048;
+// Bands below this are mostly rumble, above it nobody hears much
+const MIN_FREQUENCY: f32 = 30.0;
+const MAX_FREQUENCY: f32 = 16000.0;
+// Magnitudes are shown from here up to 0 dB
+const MIN_DB: f32 = -70.0;
+// How much of its height a band loses per frame, bars fall instead of flickering
+const FALLOFF: f32 = 0.06;
+
+// Mono samples as they come out of the speakers, turned into bands and a waveform
+#[derive(Debug)]
+pub struct SpectrumAnalyzer {
+    // Last `FFT_SIZE` samples, `next` is the oldest
+    samples: Vec<f32>,
+    next: usize,
+    window: Vec<f32>,
+    re: Vec<f32>,
+    im: Vec<f32>,
+    bands: Vec<f32>,
+}
+
+impl SpectrumAnalyzer {
+    pub fn new() -> Self {
+        // Hann, keeps loud bands from smearing all over the others
+        let window = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (FFT_SIZE - 1) as f32).cos())
+            .collect();
+        Self {
+            samples: vec![0.0; FFT_SIZE],
+            next: 0,
+            window,
+            re: vec![0.0; FFT_SIZE],
+            im: vec![0.0; FFT_SIZE],
+            bands: Vec::new(),
+        }
+    }
+    pub fn push<I: IntoIterator<Item = f32>>(&mut self, samples: I) {
+        for sample in samples {
+            self.samples[self.next] = sample;
+            self.next = (self.next + 1) % FFT_SIZE;
+        }
+    }
+    // Oldest to newest
+    pub fn waveform(&self) -> impl Iterator<Item = f32> + '_ {
+        self.samples[self.next..]
+            .iter()
+            .chain(self.samples[..self.next].iter())
+            .copied()
+    }
+    // `count` log spaced bands from 0 to 1, smoothed over the frames they are asked for
+    pub fn bands(&mut self, count: usize, sample_rate: u32) -> &[f32] {
+        for i in 0..FFT_SIZE {
+            self.re[i] = self.samples[(self.next + i) % FFT_SIZE] * self.window[i];
+            self.im[i] = 0.0;
+        }
+        fft(&mut self.re, &mut self.im);
+        let bin_width = sample_rate.max(1) as f32 / FFT_SIZE as f32;
+        let max_frequency = MAX_FREQUENCY.min(sample_rate as f32 / 2.0);
+        let ratio = (max_frequency / MIN_FREQUENCY).max(1.0);
+        // A full scale sine ends up at about `FFT_SIZE / 4` with the window
+        let reference = FFT_SIZE as f32 / 4.0;
+        self.bands.resize(count, 0.0);
+        for (i, band) in self.bands.iter_mut().enumerate() {
+            let low = MIN_FREQUENCY * ratio.powf(i as f32 / count as f32);
+            let high = MIN_FREQUENCY * ratio.powf((i + 1) as f32 / count as f32);
+            let first = ((low / bin_width) as usize).clamp(1, FFT_SIZE / 2 - 1);
+            let last = ((high / bin_width) as usize).clamp(first, FFT_SIZE / 2 - 1);
+            let magnitude = (first..=last)
+                .map(|f| (self.re[f] * self.re[f] + self.im[f] * self.im[f]).sqrt())
+                .fold(0.0, f32::max);
+            let db = 20.0 * (magnitude / reference).max(1e-9).log10();
+            let level = ((db - MIN_DB) / -MIN_DB).clamp(0.0, 1.0);
+            *band = level.max(*band - FALLOFF);
+        }
+        &self.bands
+    }
+    // Paused or stopped, the bars fall down instead of freezing
+    pub fn silence(&mut self) {
+        self.samples.fill(0.0);
+    }
+}
+
+impl Default for SpectrumAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// In place iterative radix-2, the length has to be a power of two
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let bits = n.trailing_zeros();
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if j > i {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut size = 2;
+    while size <= n {
+        let angle = -2.0 * PI / size as f32;
+        for start in (0..n).step_by(size) {
+            for k in 0..size / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + size / 2);
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        size *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    // Full scale and exactly on an FFT bin, the window only spreads it to the bins next to it
+    fn sine(bin: usize, length: usize) -> impl Iterator<Item = f32> {
+        (0..length).map(move |i| (2.0 * PI * (bin * i) as f32 / FFT_SIZE as f32).sin())
+    }
+
+    fn magnitudes(re: &[f32], im: &[f32]) -> Vec<f32> {
+        re.iter()
+            .zip(im)
+            .map(|(re, im)| (re * re + im * im).sqrt())
+            .collect()
+    }
+
+    // Same bins as `bands` takes
+    fn band_of(bin: usize, count: usize) -> usize {
+        let bin_width = SAMPLE_RATE as f32 / FFT_SIZE as f32;
+        let ratio = MAX_FREQUENCY / MIN_FREQUENCY;
+        (0..count)
+            .find(|i| {
+                let low = MIN_FREQUENCY * ratio.powf(*i as f32 / count as f32);
+                let high = MIN_FREQUENCY * ratio.powf((i + 1) as f32 / count as f32);
+                (low / bin_width) as usize <= bin && bin < (high / bin_width) as usize
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn fft_of_an_impulse_is_flat() {
+        let mut re = vec![0.0; 16];
+        let mut im = vec![0.0; 16];
+        re[0] = 1.0;
+        fft(&mut re, &mut im);
+        for magnitude in magnitudes(&re, &im) {
+            assert!((magnitude - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn fft_finds_the_frequency() {
+        let n = 64;
+        let mut re: Vec<f32> = (0..n)
+            .map(|i| 0.5 + (2.0 * PI * 5.0 * i as f32 / n as f32).cos())
+            .collect();
+        let mut im = vec![0.0; n];
+        fft(&mut re, &mut im);
+        let magnitudes = magnitudes(&re, &im);
+        // The constant part in bin 0, the cosine split between its bin and the mirrored one
+        assert!((magnitudes[0] - 32.0).abs() < 1e-3);
+        assert!((magnitudes[5] - 32.0).abs() < 1e-3);
+        assert!((magnitudes[n - 5] - 32.0).abs() < 1e-3);
+        for (i, magnitude) in magnitudes.iter().enumerate() {
+            if ![0, 5, n - 5].contains(&i) {
+                assert!(*magnitude < 1e-3, "bin {} is {}", i, magnitude);
+            }
+        }
+    }
+
+    #[test]
+    fn sine_peaks_in_its_band() {
+        let count = 32;
+        let mut analyzer = SpectrumAnalyzer::new();
+        // 2343.75 Hz
+        analyzer.push(sine(100, FFT_SIZE));
+        let bands = analyzer.bands(count, SAMPLE_RATE).to_vec();
+        let loudest = (0..count)
+            .max_by(|a, b| bands[*a].total_cmp(&bands[*b]))
+            .unwrap();
+        assert_eq!(loudest, band_of(100, count));
+        // Full scale is about 0 dB
+        assert!(bands[loudest] > 0.95, "{:?}", bands);
+        assert!(bands[0] < 0.3 && bands[count - 1] < 0.3, "{:?}", bands);
+    }
+
+    #[test]
+    fn silence_gives_empty_bands() {
+        let mut analyzer = SpectrumAnalyzer::new();
+        assert!(analyzer.bands(16, SAMPLE_RATE).iter().all(|f| *f == 0.0));
+        analyzer.push(std::iter::repeat_n(0.0, FFT_SIZE));
+        assert!(analyzer.bands(16, SAMPLE_RATE).iter().all(|f| *f == 0.0));
+    }
+
+    #[test]
+    fn bands_fall_after_silence() {
+        let mut analyzer = SpectrumAnalyzer::new();
+        analyzer.push(sine(100, FFT_SIZE));
+        let before = analyzer.bands(8, SAMPLE_RATE).to_vec();
+        analyzer.silence();
+        let after = analyzer.bands(8, SAMPLE_RATE).to_vec();
+        for (before, after) in before.iter().zip(after.iter()) {
+            assert!((after - (before - FALLOFF).max(0.0)).abs() < 1e-6);
+        }
+        for _ in 0..(1.0 / FALLOFF).ceil() as usize {
+            analyzer.bands(8, SAMPLE_RATE);
+        }
+        assert!(analyzer.bands(8, SAMPLE_RATE).iter().all(|f| *f == 0.0));
+    }
+
+    #[test]
+    fn waveform_is_oldest_first() {
+        let mut analyzer = SpectrumAnalyzer::new();
+        analyzer.push((0..FFT_SIZE + 2).map(|f| f as f32));
+        let waveform: Vec<f32> = analyzer.waveform().collect();
+        assert_eq!(waveform.len(), FFT_SIZE);
+        assert_eq!(waveform[0], 2.0);
+        assert_eq!(waveform[FFT_SIZE - 1], (FFT_SIZE + 1) as f32);
+    }
+}
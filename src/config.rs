@@ -7,29 +7,167 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
+use crate::db::query::QuerySort;
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct AppConfig {
     pub dir: AppConfigDir,
+    #[serde(default)]
+    pub scan: AppConfigScan,
+    #[serde(default)]
+    pub playlists: Vec<AppConfigPlaylist>,
+    #[serde(default)]
+    pub cover: AppConfigCover,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AppConfigCoverProtocol {
+    // Kitty or sixel graphics if the terminal looks like it supports them, half blocks otherwise
+    #[default]
+    Auto,
+    HalfBlocks,
+    Kitty,
+    Sixel,
+    // No cover pane at all
+    Off,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AppConfigCover {
+    #[serde(default)]
+    pub protocol: AppConfigCoverProtocol,
+}
+
+// A smart playlist, e.g. the 50 most recently added FLAC tracks:
+// `{ name = "New FLAC", query = "codec:flac", sort = "added", descending = true, limit = 50 }`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AppConfigPlaylist {
+    pub name: String,
+    // Same syntax as the `:` filter, empty matches everything
+    #[serde(default)]
+    pub query: String,
+    #[serde(default)]
+    pub sort: QuerySort,
+    #[serde(default)]
+    pub descending: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AppConfigDir {
-    #[serde(default = "AppConfigDir::default_music_dir")]
-    pub music_dir: String,
+    // Single directory from older configs, scanned like an extra root. Those wrote `"no"` when
+    // there was no music folder, that one is skipped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub music_dir: Option<String>,
+    #[serde(default)]
+    pub roots: Vec<AppConfigRoot>,
 }
 
 impl AppConfigDir {
-    fn default_music_dir() -> String {
+    const NO_MUSIC_DIR: &'static str = "no";
+    fn default_roots() -> Vec<AppConfigRoot> {
         directories::UserDirs::new()
-            .and_then(|f| f.audio_dir().map(|f| f.to_path_buf()))
-            .map_or_else(|| String::from("no"), |f| f.display().to_string())
+            .and_then(|f| f.audio_dir().map(|f| f.display().to_string()))
+            .map(AppConfigRoot::new)
+            .into_iter()
+            .collect()
+    }
+    pub fn roots(&self) -> Vec<AppConfigRoot> {
+        let mut roots = self.roots.clone();
+        let music_dir = self
+            .music_dir
+            .as_deref()
+            .map(str::trim)
+            .filter(|f| !f.is_empty() && *f != Self::NO_MUSIC_DIR);
+        if let Some(music_dir) = music_dir {
+            roots.push(AppConfigRoot::new(music_dir.to_string()));
+        }
+        if roots.is_empty() {
+            return Self::default_roots();
+        }
+        roots
     }
 }
 
 impl Default for AppConfigDir {
     fn default() -> Self {
         Self {
-            music_dir: Self::default_music_dir(),
+            music_dir: None,
+            roots: Self::default_roots(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AppConfigRoot {
+    pub path: String,
+    // Globs relative to `path`, patterns without a `/` match any file or folder name
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    // How many folders deep to go, 0 only scans `path` itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    // Scan dot files and folders as well
+    #[serde(default)]
+    pub hidden: bool,
+}
+
+impl AppConfigRoot {
+    pub fn new(path: String) -> Self {
+        Self {
+            path,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            max_depth: None,
+            hidden: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AppConfigScan {
+    #[serde(default = "AppConfigScan::default_extensions")]
+    pub extensions: Vec<String>,
+    // Probe files ffmpeg might play even if their extension is not listed
+    #[serde(default)]
+    pub sniff: bool,
+    // How many files get probed at once
+    #[serde(default = "AppConfigScan::default_workers")]
+    pub workers: usize,
+    // Linked folders are only entered with this on (loops are detected), linked files always count
+    #[serde(default)]
+    pub follow_symlinks: bool,
+}
+
+impl AppConfigScan {
+    fn default_extensions() -> Vec<String> {
+        [
+            "flac", "mp3", "wav", "ogg", "oga", "m4a", "aac", "alac", "aiff", "aif", "ape", "wv",
+            "wma", "mka", "mpc", "tta",
+        ]
+        .into_iter()
+        .chain(cfg!(feature = "opus").then_some("opus"))
+        .map(String::from)
+        .collect()
+    }
+    fn default_workers() -> usize {
+        std::thread::available_parallelism()
+            .map(|f| f.get())
+            .unwrap_or(4)
+    }
+}
+
+impl Default for AppConfigScan {
+    fn default() -> Self {
+        Self {
+            extensions: Self::default_extensions(),
+            sniff: false,
+            workers: Self::default_workers(),
+            follow_symlinks: false,
         }
     }
 }
@@ -70,3 +208,34 @@ impl AppConfigHandler {
         &self.config
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(toml: &str) -> AppConfigDir {
+        toml_edit::de::from_str::<AppConfig>(toml).unwrap().dir
+    }
+
+    fn paths(dir: &AppConfigDir) -> Vec<String> {
+        dir.roots().into_iter().map(|f| f.path).collect()
+    }
+
+    #[test]
+    fn legacy_music_dir_is_an_extra_root() {
+        let config = dir("[dir]\nmusic_dir = \"/old\"\n[[dir.roots]]\npath = \"/music\"\n");
+        assert_eq!(paths(&config), ["/music", "/old"]);
+        let config = dir("[dir]\nmusic_dir = \"/old\"\n");
+        assert_eq!(paths(&config), ["/old"]);
+    }
+
+    #[test]
+    fn legacy_no_music_dir_is_skipped() {
+        let config = dir("[dir]\nmusic_dir = \"no\"\n[[dir.roots]]\npath = \"/music\"\n");
+        assert_eq!(paths(&config), ["/music"]);
+        // Nothing else configured falls back to the default like an empty config
+        let config = dir("[dir]\nmusic_dir = \"no\"\n");
+        assert_eq!(paths(&config), paths(&dir("[dir]\n")));
+        assert!(!paths(&config).contains(&String::from("no")));
+    }
+}
@@ -1,73 +1,666 @@
 use async_stream::stream;
-//use futures::Stream;
-use std::path::{Path, PathBuf};
+use futures::{pin_mut, StreamExt};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
 use tokio_stream::Stream;
+use tokio_util::sync::CancellationToken;
 
-use crate::config::AppConfig;
+use crate::config::{AppConfig, AppConfigScan};
+
+use super::{
+    cover::find_cover_file, cue_sheet::CueSheet, metadata::read_metadata_and_cover,
+    scan_root::ScanRoot, AudioData,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    // Milliseconds since the unix epoch
+    pub modified: i64,
+    pub size: u64,
+}
+
+impl FileStamp {
+    pub fn read<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|f| f.duration_since(UNIX_EPOCH).ok())
+            .map(|f| f.as_millis() as i64)
+            .unwrap_or_default();
+        Some(Self {
+            modified,
+            size: metadata.len(),
+        })
+    }
+    pub fn combine(self, other: Self) -> Self {
+        Self {
+            modified: self.modified.max(other.modified),
+            size: self.size + other.size,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanItem {
+    Unchanged(PathBuf),
+    Changed {
+        path: PathBuf,
+        stamp: FileStamp,
+        tracks: Vec<AudioData>,
+        // Embedded picture, or the cover file of the directory
+        cover: Option<Vec<u8>>,
+    },
+    // Missing or unreadable (offline mount, permissions), whatever is below it stays as it was
+    Unreadable(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanProgress {
+    pub dirs_visited: usize,
+    pub files_found: usize,
+    pub files_done: usize,
+    pub files_probed: usize,
+    pub errors: usize,
+    pub broken_links: usize,
+}
+
+impl ScanProgress {
+    pub fn ratio(&self) -> f64 {
+        if self.files_found == 0 {
+            return 0.0;
+        }
+        (self.files_done as f64 / self.files_found as f64).min(1.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanEvent {
+    Progress(ScanProgress),
+    Item(ScanItem),
+    Finished { root: PathBuf, cancelled: bool },
+}
+
+enum ScanJob {
+    Visited {
+        files: usize,
+    },
+    Failed,
+    BrokenLink,
+    Unreadable(PathBuf),
+    Unchanged(PathBuf),
+    Probe {
+        path: PathBuf,
+        stamp: FileStamp,
+        allowed: bool,
+        cover_file: Option<PathBuf>,
+    },
+    Cue {
+        path: PathBuf,
+        stamp: FileStamp,
+        tracks: Vec<AudioData>,
+        cover_file: Option<PathBuf>,
+    },
+}
+
+enum ScanJobResult {
+    Visited {
+        files: usize,
+    },
+    Item {
+        item: ScanItem,
+        probed: bool,
+        failed: bool,
+    },
+    Failed,
+    BrokenLink,
+    Unreadable(PathBuf),
+    Skipped,
+}
+
+// Identifies a file no matter which link it was reached through
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum FileId {
+    #[cfg(unix)]
+    Inode(u64, u64),
+    #[cfg(not(unix))]
+    Path(PathBuf),
+}
+
+impl FileId {
+    #[cfg(unix)]
+    async fn read(path: &Path) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = tokio::fs::metadata(path).await.ok()?;
+        Some(Self::Inode(metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    async fn read(path: &Path) -> Option<Self> {
+        tokio::fs::canonicalize(path).await.ok().map(Self::Path)
+    }
+}
 
 pub struct AudioScanner {
     config: AppConfig,
+    roots: Vec<ScanRoot>,
 }
 
 impl AudioScanner {
     pub fn new(config: AppConfig) -> Self {
-        Self { config }
+        let roots = Self::compile_roots(&config);
+        Self { config, roots }
+    }
+    fn compile_roots(config: &AppConfig) -> Vec<ScanRoot> {
+        config.dir.roots().iter().map(ScanRoot::new).collect()
+    }
+    pub fn roots(&self) -> &[ScanRoot] {
+        &self.roots
     }
-    pub fn scan_dir<P: AsRef<Path>>(music_dir: P) -> impl Stream<Item = PathBuf> {
+    // The innermost root containing `dir`, its filters apply to the whole scan
+    fn scan_root(&self, dir: &Path) -> ScanRoot {
+        self.roots
+            .iter()
+            .filter(|f| f.contains(dir))
+            .max_by_key(|f| f.path.components().count())
+            .cloned()
+            .unwrap_or_else(|| ScanRoot::unfiltered(dir))
+    }
+    // Files whose stamp matches `known` are reported as unchanged without probing them
+    pub fn scan_dir<P: AsRef<Path>>(
+        &self,
+        music_dir: P,
+        known: HashMap<PathBuf, FileStamp>,
+        cancel: CancellationToken,
+    ) -> impl Stream<Item = ScanEvent> {
+        let root = music_dir.as_ref().to_path_buf();
+        let workers = self.config.scan.workers.max(1);
+        let walk = Self::walk_dir(
+            self.config.scan.clone(),
+            self.scan_root(&root),
+            root.clone(),
+            known,
+            cancel.clone(),
+        );
+        stream! {
+            // Walking stays sequential, probing runs on up to `workers` files at once
+            let jobs = walk.map(Self::run_job).buffer_unordered(workers);
+            // We need to pin this as of now
+            pin_mut!(jobs);
+            let mut progress = ScanProgress::default();
+            while let Some(result) = jobs.next().await {
+                if cancel.is_cancelled() {
+                    break;
+                }
+                match result {
+                    ScanJobResult::Visited { files } => {
+                        progress.dirs_visited += 1;
+                        progress.files_found += files;
+                    }
+                    ScanJobResult::Item { item, probed, failed } => {
+                        progress.files_done += 1;
+                        progress.files_probed += probed as usize;
+                        progress.errors += failed as usize;
+                        yield ScanEvent::Progress(progress);
+                        yield ScanEvent::Item(item);
+                        continue;
+                    }
+                    ScanJobResult::Failed => {
+                        progress.files_done += 1;
+                        progress.errors += 1;
+                    }
+                    ScanJobResult::BrokenLink => {
+                        progress.files_done += 1;
+                        progress.broken_links += 1;
+                    }
+                    ScanJobResult::Unreadable(path) => {
+                        progress.errors += 1;
+                        yield ScanEvent::Progress(progress);
+                        yield ScanEvent::Item(ScanItem::Unreadable(path));
+                        continue;
+                    }
+                    ScanJobResult::Skipped => {
+                        progress.files_done += 1;
+                    }
+                }
+                yield ScanEvent::Progress(progress);
+            }
+            yield ScanEvent::Finished {
+                root,
+                cancelled: cancel.is_cancelled(),
+            };
+        }
+    }
+    // Everything expensive is left to `run_job`
+    fn walk_dir(
+        scan_config: AppConfigScan,
+        scan_root: ScanRoot,
+        music_dir: PathBuf,
+        known: HashMap<PathBuf, FileStamp>,
+        cancel: CancellationToken,
+    ) -> impl Stream<Item = ScanJob> {
         stream! {
-            let mut path = music_dir.as_ref().to_path_buf();
             // The recursive stack
-            let mut recursive_paths: Vec<PathBuf> = Vec::new();
-            loop {
-                // If this directory is fake, then exit early :)
-                let Ok(mut directory) = tokio::fs::read_dir(path).await else {
+            let mut recursive_paths: Vec<PathBuf> = Vec::from([music_dir]);
+            // Followed links can point back up the tree, never enter a directory twice
+            let mut visited_dirs: HashSet<FileId> = HashSet::new();
+            let mut visited_files: HashSet<FileId> = HashSet::new();
+            while let Some(path) = recursive_paths.pop() {
+                if cancel.is_cancelled() {
                     break;
+                }
+                if !scan_root.allows_dir(&path) {
+                    continue;
+                }
+                if let Some(id) = FileId::read(&path).await {
+                    if !visited_dirs.insert(id) {
+                        tracing::info!("Skipping {}, already visited", path.display());
+                        continue;
+                    }
+                }
+                // If this directory is fake, then skip it :)
+                let mut directory = match tokio::fs::read_dir(&path).await {
+                    Ok(directory) => directory,
+                    Err(e) => {
+                        tracing::warn!("Could not read {}: {}", path.display(), e);
+                        yield ScanJob::Unreadable(path);
+                        continue;
+                    }
                 };
-                while let Ok(Some(item)) = directory.next_entry().await {
-                        match item.file_type().await {
-                            // We want to push this path to the "recursive" stack
-                            Ok(x) if x.is_dir() => recursive_paths.push(item.path()),
-                            // If anything *but* a directory
-                            Ok(x) => {
-                                let mut item = item.path();
-                                if x.is_symlink() {
-                                    // We already checked if it is a symlink
-                                    item = std::fs::read_link(item).unwrap();
+                // We need the whole directory first, CUE sheets hide their audio file
+                let mut files: Vec<PathBuf> = Vec::new();
+                // Cover files arent audio, the filters of the root dont apply to them
+                let mut all_files: Vec<PathBuf> = Vec::new();
+                let mut broken_links = 0;
+                // Only part of the directory was listed, the rest must not look deleted
+                let mut listed = true;
+                loop {
+                    let item = match directory.next_entry().await {
+                        Ok(Some(item)) => item,
+                        Ok(None) => break,
+                        Err(e) => {
+                            tracing::warn!("Could not read {}: {}", path.display(), e);
+                            listed = false;
+                            break;
+                        }
+                    };
+                    let file_type = match item.file_type().await {
+                        Ok(x) if x.is_symlink() => {
+                            // Resolving the link also tells us if it is broken
+                            let target = match tokio::fs::canonicalize(item.path()).await {
+                                Ok(target) => tokio::fs::metadata(&target).await,
+                                Err(e) => Err(e),
+                            };
+                            match target {
+                                // Linked directories can loop, they are only entered when asked to
+                                Ok(metadata) if metadata.is_dir() && !scan_config.follow_symlinks => {
+                                    continue;
                                 }
-                                if Self::check_file_extension(&item) {
-                                    // TODO: Get and yield song metadata instead of a string
-                                    //yield item.file_name()
-                                    //.unwrap_or_default()
-                                    //    .to_str()
-                                    //    .map(String::from)
-                                    //    .unwrap_or_default();
-                                    yield item;
+                                Ok(metadata) => metadata.file_type(),
+                                Err(e) => {
+                                    tracing::warn!("Broken link {}: {}", item.path().display(), e);
+                                    broken_links += 1;
+                                    continue;
                                 }
-                        },
-                            _ => continue,
+                            }
+                        }
+                        Ok(x) => x,
+                        _ => continue,
+                    };
+                    // Links keep their own path, so the library stays below the root
+                    let item = item.path();
+                    if file_type.is_dir() {
+                        // We want to push this path to the "recursive" stack
+                        recursive_paths.push(item);
+                        continue;
+                    }
+                    all_files.push(item.clone());
+                    if !scan_root.allows_file(&item) {
+                        continue;
+                    }
+                    // The same file behind several links is only scanned once
+                    if scan_config.follow_symlinks {
+                        if let Some(id) = FileId::read(&item).await {
+                            if !visited_files.insert(id) {
+                                continue;
+                            }
                         }
+                    }
+                    files.push(item);
                 }
-                if !recursive_paths.is_empty() {
-                    // We already know this element exists
-                    path = recursive_paths.pop().unwrap();
-                    continue;
+                let cover_file = find_cover_file(&all_files);
+                let mut jobs: Vec<ScanJob> = Vec::new();
+                // Every TRACK of a CUE sheet is its own (virtual) entry
+                let mut cue_audio_files: Vec<PathBuf> = Vec::new();
+                for cue_file in files.iter().filter(|f| Self::is_cue_sheet(f)) {
+                    let sheet = match CueSheet::read(cue_file).await {
+                        Ok(sheet) => sheet,
+                        Err(e) => {
+                            tracing::warn!("Skipping {}: {}", cue_file.display(), e);
+                            jobs.push(ScanJob::Failed);
+                            continue;
+                        }
+                    };
+                    // Group the tracks per audio file, thats what the library keys on
+                    let mut grouped: Vec<(PathBuf, Vec<AudioData>)> = Vec::new();
+                    for mut item in sheet.audio_data() {
+                        let Some(audio_file) =
+                            Self::find_cue_audio_file(&scan_config, &item.path, &files)
+                        else {
+                            continue;
+                        };
+                        item.path = audio_file.clone();
+                        match grouped.iter_mut().find(|(f, _)| *f == audio_file) {
+                            Some((_, tracks)) => tracks.push(item),
+                            None => grouped.push((audio_file, vec![item])),
+                        }
+                    }
+                    for (audio_file, tracks) in grouped {
+                        if cue_audio_files.contains(&audio_file) {
+                            continue;
+                        }
+                        cue_audio_files.push(audio_file.clone());
+                        // Editing either the sheet or the audio file changes the tracks
+                        let Some(stamp) = FileStamp::read(&audio_file)
+                            .zip(FileStamp::read(cue_file))
+                            .map(|(audio, cue)| audio.combine(cue))
+                        else {
+                            jobs.push(ScanJob::Failed);
+                            continue;
+                        };
+                        if known.get(&audio_file) == Some(&stamp) {
+                            jobs.push(ScanJob::Unchanged(audio_file));
+                            continue;
+                        }
+                        jobs.push(ScanJob::Cue {
+                            path: audio_file,
+                            stamp,
+                            tracks,
+                            cover_file: cover_file.clone(),
+                        });
+                    }
+                }
+                for item in files {
+                    if cue_audio_files.contains(&item) || Self::is_cue_sheet(&item) {
+                        continue;
+                    }
+                    let allowed = Self::check_file_extension(&scan_config, &item);
+                    if !allowed && !scan_config.sniff {
+                        continue;
+                    }
+                    let Some(stamp) = FileStamp::read(&item) else {
+                        jobs.push(ScanJob::Failed);
+                        continue;
+                    };
+                    if known.get(&item) == Some(&stamp) {
+                        jobs.push(ScanJob::Unchanged(item));
+                        continue;
+                    }
+                    jobs.push(ScanJob::Probe {
+                        path: item,
+                        stamp,
+                        allowed,
+                        cover_file: cover_file.clone(),
+                    });
+                }
+                for _ in 0..broken_links {
+                    jobs.push(ScanJob::BrokenLink);
+                }
+                if !listed {
+                    jobs.push(ScanJob::Unreadable(path));
+                }
+                yield ScanJob::Visited { files: jobs.len() };
+                for job in jobs {
+                    yield job;
+                }
+            }
+        }
+    }
+    async fn run_job(job: ScanJob) -> ScanJobResult {
+        match job {
+            ScanJob::Visited { files } => ScanJobResult::Visited { files },
+            ScanJob::Failed => ScanJobResult::Failed,
+            ScanJob::BrokenLink => ScanJobResult::BrokenLink,
+            ScanJob::Unreadable(path) => ScanJobResult::Unreadable(path),
+            ScanJob::Unchanged(path) => ScanJobResult::Item {
+                item: ScanItem::Unchanged(path),
+                probed: false,
+                failed: false,
+            },
+            ScanJob::Cue {
+                path,
+                stamp,
+                mut tracks,
+                cover_file,
+            } => {
+                // Tags of the whole file fill in what the sheet doesnt say
+                let (file_data, cover) = match Self::read_metadata(path.clone()).await {
+                    Some((data, cover)) => (Some(data), cover),
+                    None => (None, None),
+                };
+                if let Some(ref file_data) = file_data {
+                    for item in tracks.iter_mut() {
+                        Self::inherit_metadata(item, file_data);
+                    }
+                }
+                ScanJobResult::Item {
+                    item: ScanItem::Changed {
+                        path,
+                        stamp,
+                        tracks,
+                        cover: Self::read_cover(cover, cover_file).await,
+                    },
+                    probed: true,
+                    failed: file_data.is_none(),
+                }
+            }
+            ScanJob::Probe {
+                path,
+                stamp,
+                allowed,
+                cover_file,
+            } => {
+                // Probing doubles as sniffing, no audio stream means no metadata
+                let (data, cover, failed) = match Self::read_metadata(path.clone()).await {
+                    Some((data, cover)) => (data, cover, false),
+                    None if allowed => (AudioData::new(path.clone()), None, true),
+                    None => return ScanJobResult::Skipped,
+                };
+                ScanJobResult::Item {
+                    item: ScanItem::Changed {
+                        path,
+                        stamp,
+                        tracks: vec![data],
+                        cover: Self::read_cover(cover, cover_file).await,
+                    },
+                    probed: true,
+                    failed,
+                }
+            }
+        }
+    }
+    // Runs the scan in the background, dropping the receiver stops it as well
+    pub fn spawn_scan<P: AsRef<Path>>(
+        &self,
+        music_dir: P,
+        known: HashMap<PathBuf, FileStamp>,
+        cancel: CancellationToken,
+    ) -> UnboundedReceiver<ScanEvent> {
+        let (tx, rx) = mpsc::unbounded_channel::<ScanEvent>();
+        let stream = self.scan_dir(music_dir.as_ref().to_path_buf(), known, cancel.clone());
+        tokio::spawn(async move {
+            pin_mut!(stream);
+            while let Some(event) = stream.next().await {
+                if tx.send(event).is_err() {
+                    cancel.cancel();
+                    break;
                 }
-                break;
             }
+        });
+        rx
+    }
+    // CUE sheets often point at the original `.wav` while the rip is a `.flac`
+    fn find_cue_audio_file(
+        scan_config: &AppConfigScan,
+        path: &Path,
+        files: &[PathBuf],
+    ) -> Option<PathBuf> {
+        if files.iter().any(|f| f == path) {
+            return Some(path.to_path_buf());
         }
+        files
+            .iter()
+            .find(|f| {
+                f.file_stem() == path.file_stem() && Self::check_file_extension(scan_config, f)
+            })
+            .cloned()
+    }
+    fn is_cue_sheet<P: AsRef<Path>>(file: P) -> bool {
+        file.as_ref()
+            .extension()
+            .and_then(|f| f.to_str())
+            .is_some_and(|f| f.eq_ignore_ascii_case("cue"))
     }
-    fn check_file_extension<P: AsRef<Path>>(file: P) -> bool {
+    fn check_file_extension<P: AsRef<Path>>(scan_config: &AppConfigScan, file: P) -> bool {
         let file = file.as_ref();
         match file.extension() {
-            Some(x) => match x.to_str().unwrap_or_default() {
-                // TODO: Add more formats
-                "opus" => true,
-                _ => false,
-            },
+            Some(x) => {
+                let extension = x.to_str().unwrap_or_default();
+                scan_config
+                    .extensions
+                    .iter()
+                    .any(|f| f.eq_ignore_ascii_case(extension))
+            }
             _ => false,
         }
     }
+    // ffmpeg blocks, keep it off the async threads
+    async fn read_metadata(path: PathBuf) -> Option<(AudioData, Option<Vec<u8>>)> {
+        let probe_path = path.clone();
+        match tokio::task::spawn_blocking(move || read_metadata_and_cover(probe_path)).await {
+            Ok(Ok(data)) => Some(data),
+            Ok(Err(e)) => {
+                tracing::info!("No metadata for {}: {}", path.display(), e);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+    // Embedded pictures win over cover files
+    async fn read_cover(embedded: Option<Vec<u8>>, cover_file: Option<PathBuf>) -> Option<Vec<u8>> {
+        if embedded.is_some() {
+            return embedded;
+        }
+        tokio::fs::read(cover_file?).await.ok()
+    }
+    fn inherit_metadata(item: &mut AudioData, file_data: &AudioData) {
+        let inherit = |value: &mut String, file_value: &String| {
+            if value.is_empty() {
+                *value = file_value.clone();
+            }
+        };
+        inherit(&mut item.author, &file_data.author);
+        inherit(&mut item.album, &file_data.album);
+        inherit(&mut item.album_artist, &file_data.album_artist);
+        inherit(&mut item.date, &file_data.date);
+        inherit(&mut item.genre, &file_data.genre);
+        item.disc = item.disc.or(file_data.disc);
+        item.codec = file_data.codec.clone();
+        item.bit_rate = file_data.bit_rate;
+        item.sample_rate = file_data.sample_rate;
+        // The last track runs until the end of the file
+        item.duration = item
+            .end
+            .or(file_data.duration)
+            .map(|f| f.saturating_sub(item.start.unwrap_or_default()));
+    }
     pub fn set_config(&mut self, config: AppConfig) {
+        self.roots = Self::compile_roots(&config);
         self.config = config;
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "music_player_scanner_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Files that would be probed and how many links were broken
+    async fn walk(dir: &Path, follow_symlinks: bool) -> (Vec<PathBuf>, usize) {
+        let scan_config = AppConfigScan {
+            follow_symlinks,
+            ..AppConfigScan::default()
+        };
+        let jobs = AudioScanner::walk_dir(
+            scan_config,
+            ScanRoot::unfiltered(dir),
+            dir.to_path_buf(),
+            HashMap::new(),
+            CancellationToken::new(),
+        )
+        .collect::<Vec<_>>()
+        .await;
+        let mut probed = Vec::new();
+        let mut broken = 0;
+        for job in jobs {
+            match job {
+                ScanJob::Probe { path, .. } => probed.push(path),
+                ScanJob::BrokenLink => broken += 1,
+                _ => {}
+            }
+        }
+        probed.sort();
+        (probed, broken)
+    }
+
+    fn library(name: &str) -> PathBuf {
+        let dir = test_dir(name);
+        let outside = dir.join("outside");
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("linked.flac"), b"").unwrap();
+        std::fs::write(outside.join("album.flac"), b"").unwrap();
+        let root = dir.join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("song.flac"), b"").unwrap();
+        symlink(outside.join("linked.flac"), root.join("linked.flac")).unwrap();
+        symlink(&outside, root.join("album")).unwrap();
+        symlink(dir.join("missing.flac"), root.join("broken.flac")).unwrap();
+        root
+    }
+
+    #[tokio::test]
+    async fn linked_files_are_kept_without_following() {
+        let root = library("no_follow");
+        let (probed, broken) = walk(&root, false).await;
+        assert_eq!(probed, [root.join("linked.flac"), root.join("song.flac")]);
+        assert_eq!(broken, 1);
+    }
+
+    #[tokio::test]
+    async fn linked_directories_are_entered_when_following() {
+        let root = library("follow");
+        // Points back up, the loop must not be walked twice
+        symlink(&root, root.join("loop")).unwrap();
+        let (probed, broken) = walk(&root, true).await;
+        // `linked.flac` is reached through both links, it is only scanned once
+        assert_eq!(probed.len(), 3, "{:?}", probed);
+        assert!(probed.contains(&root.join("song.flac")));
+        assert!(probed.contains(&root.join("album").join("album.flac")));
+        assert_eq!(broken, 1);
+    }
+}
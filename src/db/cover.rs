@@ -0,0 +1,264 @@
+use std::path::PathBuf;
+
+use ffmpeg_next::{
+    codec::Id as FFMpegCodecId,
+    decoder,
+    format::{context::Input as FFMpegInput, stream::Disposition as FFMpegDisposition, Pixel},
+    frame::Video as FFMpegVideoFrame,
+    software::scaling::{Context as FFMpegScaler, Flags as FFMpegScalerFlags},
+    util::error::Error as FFMpegError,
+    Packet as FFMpegPacket,
+};
+
+// Looked for next to audio files without an embedded picture, the first match wins
+const COVER_FILE_STEMS: &[&str] = &["cover", "folder", "front", "albumart", "album"];
+const COVER_FILE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png"];
+
+// Decoded covers are scaled down to fit this, terminals dont need more
+const COVER_MAX_SIZE: u32 = 512;
+
+// Packed RGB, 3 bytes per pixel without padding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgb: Vec<u8>,
+}
+
+impl CoverImage {
+    pub fn pixel(&self, x: u32, y: u32) -> (u8, u8, u8) {
+        let index = ((y * self.width + x) * 3) as usize;
+        (self.rgb[index], self.rgb[index + 1], self.rgb[index + 2])
+    }
+    // Averages the pixels each target pixel covers, good enough for shrinking
+    pub fn resized(&self, width: u32, height: u32) -> CoverImage {
+        let (width, height) = (width.max(1), height.max(1));
+        let mut rgb: Vec<u8> = Vec::with_capacity((width * height * 3) as usize);
+        for y in 0..height {
+            let (top, bottom) = span(y, height, self.height);
+            for x in 0..width {
+                let (left, right) = span(x, width, self.width);
+                let mut sum = [0u32; 3];
+                for source_y in top..bottom {
+                    for source_x in left..right {
+                        let (r, g, b) = self.pixel(source_x, source_y);
+                        sum[0] += u32::from(r);
+                        sum[1] += u32::from(g);
+                        sum[2] += u32::from(b);
+                    }
+                }
+                let count = (bottom - top) * (right - left);
+                rgb.extend(sum.iter().map(|f| (f / count) as u8));
+            }
+        }
+        CoverImage { width, height, rgb }
+    }
+}
+
+// Source pixels target pixel `index` out of `target` covers, at least one
+fn span(index: u32, target: u32, source: u32) -> (u32, u32) {
+    let start = (index as u64 * source as u64 / target as u64) as u32;
+    let end = ((index as u64 + 1) * source as u64 / target as u64) as u32;
+    (start, end.clamp(start + 1, source))
+}
+
+// Embedded pictures are stored as attached picture streams, the picture is the stream's only packet
+pub fn embedded_cover(input_context: &FFMpegInput) -> Option<Vec<u8>> {
+    let picture = input_context
+        .streams()
+        .find(|f| f.disposition().contains(FFMpegDisposition::ATTACHED_PIC))?;
+    // Copied out before the input (and with it the packet) goes away
+    unsafe {
+        let packet = &(*picture.as_ptr()).attached_pic;
+        if packet.data.is_null() || packet.size <= 0 {
+            return None;
+        }
+        Some(std::slice::from_raw_parts(packet.data, packet.size as usize).to_vec())
+    }
+}
+
+// `cover.jpg`, `Folder.png` and friends among the files of a directory, names ignore case
+pub fn find_cover_file(files: &[PathBuf]) -> Option<PathBuf> {
+    let is_named = |file: &PathBuf, stem: &str| {
+        let matches = |part: Option<&std::ffi::OsStr>, value: &str| {
+            part.and_then(|f| f.to_str())
+                .is_some_and(|f| f.eq_ignore_ascii_case(value))
+        };
+        matches(file.file_stem(), stem)
+            && COVER_FILE_EXTENSIONS
+                .iter()
+                .any(|extension| matches(file.extension(), extension))
+    };
+    COVER_FILE_STEMS
+        .iter()
+        .find_map(|stem| files.iter().find(|f| is_named(f, stem)))
+        .cloned()
+}
+
+// Stable across runs (unlike `DefaultHasher`), covers are deduplicated by it (FNV-1a)
+pub fn cover_hash(data: &[u8]) -> i64 {
+    let hash = data.iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    });
+    hash as i64
+}
+
+// Covers are JPEG or PNG, anything else is left to the decoders to reject
+pub fn decode_cover(data: &[u8]) -> Result<CoverImage, FFMpegError> {
+    let codec = match data {
+        [0x89, b'P', b'N', b'G', ..] => FFMpegCodecId::PNG,
+        _ => FFMpegCodecId::MJPEG,
+    };
+    let mut decoder = decoder::new().open_as(codec)?.video()?;
+    decoder.send_packet(&FFMpegPacket::copy(data))?;
+    decoder.send_eof()?;
+    let mut frame = FFMpegVideoFrame::empty();
+    decoder.receive_frame(&mut frame)?;
+    let (width, height) = (frame.width(), frame.height());
+    if width == 0 || height == 0 {
+        return Err(FFMpegError::InvalidData);
+    }
+    // Keeps the aspect ratio, small covers stay as they are
+    let scale = (COVER_MAX_SIZE as f64 / width.max(height) as f64).min(1.0);
+    let target_width = ((width as f64 * scale) as u32).max(1);
+    let target_height = ((height as f64 * scale) as u32).max(1);
+    let mut scaler = FFMpegScaler::get(
+        frame.format(),
+        width,
+        height,
+        Pixel::RGB24,
+        target_width,
+        target_height,
+        FFMpegScalerFlags::AREA,
+    )?;
+    let mut rgb_frame = FFMpegVideoFrame::empty();
+    scaler.run(&frame, &mut rgb_frame)?;
+    // Rows can be padded, copy them out one by one
+    let stride = rgb_frame.stride(0);
+    let row_length = target_width as usize * 3;
+    let rgb = rgb_frame
+        .data(0)
+        .chunks(stride)
+        .take(target_height as usize)
+        .flat_map(|f| f[..row_length].iter().copied())
+        .collect();
+    Ok(CoverImage {
+        width: target_width,
+        height: target_height,
+        rgb,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every pixel gray, its value from `value(x, y)`
+    fn image(width: u32, height: u32, value: impl Fn(u32, u32) -> u8) -> CoverImage {
+        let mut rgb = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                rgb.extend([value(x, y); 3]);
+            }
+        }
+        CoverImage { width, height, rgb }
+    }
+
+    fn grays(image: &CoverImage) -> Vec<u8> {
+        image.rgb.iter().step_by(3).copied().collect()
+    }
+
+    fn files(names: &[&str]) -> Vec<PathBuf> {
+        names
+            .iter()
+            .map(|f| PathBuf::from("/music/Album").join(f))
+            .collect()
+    }
+
+    #[test]
+    fn spans_cover_every_source_pixel() {
+        assert_eq!(span(0, 2, 4), (0, 2));
+        assert_eq!(span(1, 2, 4), (2, 4));
+        // Uneven splits leave nothing out
+        assert_eq!(span(0, 3, 4), (0, 1));
+        assert_eq!(span(1, 3, 4), (1, 2));
+        assert_eq!(span(2, 3, 4), (2, 4));
+        // Growing repeats pixels, every span has at least one
+        assert_eq!(span(0, 4, 2), (0, 1));
+        assert_eq!(span(1, 4, 2), (0, 1));
+        assert_eq!(span(3, 4, 2), (1, 2));
+    }
+
+    #[test]
+    fn resized_averages() {
+        let source = image(4, 2, |x, _| if x < 2 { 0 } else { 200 });
+        let resized = source.resized(2, 1);
+        assert_eq!((resized.width, resized.height), (2, 1));
+        assert_eq!(grays(&resized), [0, 200]);
+        assert_eq!(grays(&source.resized(1, 1)), [100]);
+        // Colors are averaged on their own
+        let source = CoverImage {
+            width: 2,
+            height: 1,
+            rgb: Vec::from([255, 0, 0, 0, 0, 255]),
+        };
+        assert_eq!(source.resized(1, 1).rgb, [127, 0, 127]);
+    }
+
+    #[test]
+    fn resized_keeps_or_grows() {
+        let source = image(3, 2, |x, y| (x + y * 3) as u8 * 10);
+        assert_eq!(source.resized(3, 2), source);
+        let grown = source.resized(6, 2);
+        assert_eq!(grays(&grown)[..6], [0, 0, 10, 10, 20, 20]);
+        // Nothing to draw into still gives a pixel
+        let tiny = source.resized(0, 0);
+        assert_eq!((tiny.width, tiny.height, tiny.rgb.len()), (1, 1, 3));
+    }
+
+    #[test]
+    fn cover_files_by_preference() {
+        let found = |names: &[&str]| {
+            find_cover_file(&files(names))
+                .map(|f| f.file_name().unwrap().to_string_lossy().to_string())
+        };
+        assert_eq!(
+            found(&["01.flac", "back.jpg", "cover.png"]).as_deref(),
+            Some("cover.png")
+        );
+        // `cover` before `folder` before `front`, whatever order the directory lists them in
+        assert_eq!(
+            found(&["front.jpg", "Folder.JPG", "album.png"]).as_deref(),
+            Some("Folder.JPG")
+        );
+        assert_eq!(
+            found(&["album.jpeg", "front.png"]).as_deref(),
+            Some("front.png")
+        );
+        assert_eq!(found(&["AlbumArt.Jpeg"]).as_deref(), Some("AlbumArt.Jpeg"));
+    }
+
+    #[test]
+    fn no_cover_files() {
+        assert_eq!(find_cover_file(&[]), None);
+        // Other images, other names or the name without an image extension
+        assert_eq!(
+            find_cover_file(&files(&[
+                "cover.gif",
+                "cover",
+                "my cover.jpg",
+                "cover.jpg.txt"
+            ])),
+            None
+        );
+    }
+
+    #[test]
+    fn cover_hash_is_fnv_1a() {
+        // The reference values of 64 bit FNV-1a, stored hashes depend on them
+        assert_eq!(cover_hash(b""), 0xcbf29ce484222325u64 as i64);
+        assert_eq!(cover_hash(b"a"), 0xaf63dc4c8601ec8cu64 as i64);
+        assert_eq!(cover_hash(b"foobar"), 0x85944171f73967e8u64 as i64);
+        assert_ne!(cover_hash(b"ab"), cover_hash(b"ba"));
+    }
+}
@@ -0,0 +1,327 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use super::AudioData;
+
+// CUE timestamps are `mm:ss:ff` with 75 frames a second (CD sectors)
+const CUE_FRAMES_PER_SECOND: u64 = 75;
+
+#[derive(Debug)]
+pub enum CueSheetError {
+    Io(std::io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl std::fmt::Display for CueSheetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "CueSheetIoError: {}", e),
+            Self::Parse { line, message } => {
+                write!(f, "CueSheetParseError: line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CueSheetError {}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub genre: Option<String>,
+    pub date: Option<String>,
+    pub files: Vec<CueFile>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CueFile {
+    pub path: PathBuf,
+    pub tracks: Vec<CueTrack>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub start: Duration,
+    // None means "until the end of the file"
+    pub end: Option<Duration>,
+}
+
+impl CueSheet {
+    pub async fn read<P: AsRef<Path>>(path: P) -> Result<Self, CueSheetError> {
+        let path = path.as_ref();
+        let file_buffer = tokio::fs::read(path).await.map_err(CueSheetError::Io)?;
+        // Lots of CUE sheets are not UTF-8 (EAC loves ANSI code pages)
+        let contents = String::from_utf8_lossy(&file_buffer);
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+        Self::parse(&contents, base_dir)
+    }
+    pub fn parse<P: AsRef<Path>>(contents: &str, base_dir: P) -> Result<Self, CueSheetError> {
+        let base_dir = base_dir.as_ref();
+        let mut sheet = CueSheet::default();
+        for (line_number, line) in contents.lines().enumerate() {
+            let line_number = line_number + 1;
+            let words = split_words(line.trim().trim_start_matches('\u{feff}'));
+            let Some(command) = words.first() else {
+                continue;
+            };
+            let parse_error = |message: &str| CueSheetError::Parse {
+                line: line_number,
+                message: message.to_string(),
+            };
+            match command.to_uppercase().as_str() {
+                "FILE" => {
+                    let name = words
+                        .get(1)
+                        .ok_or_else(|| parse_error("FILE without a name"))?;
+                    sheet.files.push(CueFile {
+                        path: base_dir.join(name),
+                        tracks: Vec::new(),
+                    });
+                }
+                "TRACK" => {
+                    let file = sheet
+                        .files
+                        .last_mut()
+                        .ok_or_else(|| parse_error("TRACK before FILE"))?;
+                    let number = words
+                        .get(1)
+                        .and_then(|f| f.parse::<u32>().ok())
+                        .ok_or_else(|| parse_error("TRACK without a number"))?;
+                    file.tracks.push(CueTrack {
+                        number,
+                        title: None,
+                        performer: None,
+                        start: Duration::ZERO,
+                        end: None,
+                    });
+                }
+                // INDEX 00 is the pregap, it stays with the previous track (gapless)
+                "INDEX" if words.get(1).map(String::as_str) == Some("01") => {
+                    let track = sheet
+                        .files
+                        .last_mut()
+                        .and_then(|f| f.tracks.last_mut())
+                        .ok_or_else(|| parse_error("INDEX before TRACK"))?;
+                    track.start = words
+                        .get(2)
+                        .and_then(|f| parse_timestamp(f))
+                        .ok_or_else(|| parse_error("invalid INDEX timestamp"))?;
+                }
+                // EAC puts the album info in comments
+                "REM" => match words.get(1).map(|f| f.to_uppercase()).as_deref() {
+                    Some("GENRE") => sheet.genre = words.get(2).cloned(),
+                    Some("DATE") => sheet.date = words.get(2).cloned(),
+                    _ => continue,
+                },
+                "TITLE" | "PERFORMER" => {
+                    let value = words.get(1).cloned();
+                    let is_title = command.eq_ignore_ascii_case("TITLE");
+                    // Before the first TRACK these describe the whole album
+                    match sheet.files.last_mut().and_then(|f| f.tracks.last_mut()) {
+                        Some(track) if is_title => track.title = value,
+                        Some(track) => track.performer = value,
+                        None if is_title => sheet.title = value,
+                        None => sheet.performer = value,
+                    }
+                }
+                _ => continue,
+            }
+        }
+        // A track ends where the next one in the same file begins
+        for file in sheet.files.iter_mut() {
+            let starts: Vec<Duration> = file.tracks.iter().skip(1).map(|f| f.start).collect();
+            for (track, end) in file.tracks.iter_mut().zip(starts) {
+                track.end = Some(end);
+            }
+        }
+        Ok(sheet)
+    }
+    pub fn audio_data(&self) -> Vec<AudioData> {
+        let mut data: Vec<AudioData> = Vec::new();
+        for file in self.files.iter() {
+            for track in file.tracks.iter() {
+                let mut item = AudioData::new(file.path.clone());
+                item.name = track
+                    .title
+                    .clone()
+                    .unwrap_or_else(|| format!("Track {:02}", track.number));
+                item.author = track
+                    .performer
+                    .clone()
+                    .or_else(|| self.performer.clone())
+                    .unwrap_or_default();
+                item.album = self.title.clone().unwrap_or_default();
+                item.album_artist = self.performer.clone().unwrap_or_default();
+                item.genre = self.genre.clone().unwrap_or_default();
+                item.date = self.date.clone().unwrap_or_default();
+                item.track = Some(track.number);
+                item.start = Some(track.start);
+                item.end = track.end;
+                data.push(item);
+            }
+        }
+        data
+    }
+}
+
+// Whitespace separated words, double quotes group words together
+fn split_words(line: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    let mut in_word = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+    let mut parts = timestamp.split(':').map(|f| f.parse::<u64>().ok());
+    let minutes = parts.next()??;
+    let seconds = parts.next()??;
+    let frames = parts.next()??;
+    if parts.next().is_some() || seconds >= 60 || frames >= CUE_FRAMES_PER_SECOND {
+        return None;
+    }
+    Some(
+        Duration::from_secs(minutes * 60 + seconds)
+            + Duration::from_nanos(frames * 1_000_000_000 / CUE_FRAMES_PER_SECOND),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_frames_are_75ths_of_a_second() {
+        assert_eq!(parse_timestamp("00:00:00"), Some(Duration::ZERO));
+        assert_eq!(
+            parse_timestamp("03:25:15"),
+            Some(Duration::from_millis(205_200))
+        );
+        assert_eq!(
+            parse_timestamp("00:01:74"),
+            Some(Duration::from_nanos(1_986_666_666))
+        );
+        // Minutes go past an hour on long rips
+        assert_eq!(parse_timestamp("75:00:00"), Some(Duration::from_secs(4500)));
+    }
+
+    #[test]
+    fn invalid_index_timestamps() {
+        for timestamp in [
+            "00:60:00",
+            "00:00:75",
+            "00:00",
+            "00:00:00:00",
+            "aa:00:00",
+            "",
+        ] {
+            assert_eq!(parse_timestamp(timestamp), None, "{}", timestamp);
+        }
+    }
+
+    #[test]
+    fn tracks_end_where_the_next_begins() {
+        let sheet = CueSheet::parse(
+            "REM GENRE Rock\nREM DATE 1997\nPERFORMER \"Some Band\"\nTITLE \"Some Album\"\n\
+             FILE \"Some Album.flac\" WAVE\n  TRACK 01 AUDIO\n    TITLE \"First\"\n    INDEX 01 00:00:00\n\
+             \x20 TRACK 02 AUDIO\n    TITLE \"Second\"\n    PERFORMER \"Guest\"\n    INDEX 00 03:20:00\n    INDEX 01 03:25:15\n",
+            "/music",
+        )
+        .unwrap();
+        assert_eq!(sheet.title.as_deref(), Some("Some Album"));
+        assert_eq!(sheet.performer.as_deref(), Some("Some Band"));
+        assert_eq!(sheet.genre.as_deref(), Some("Rock"));
+        assert_eq!(sheet.date.as_deref(), Some("1997"));
+        let file = &sheet.files[0];
+        assert_eq!(file.path, PathBuf::from("/music/Some Album.flac"));
+        // The pregap (INDEX 00) stays with the first track
+        assert_eq!(
+            file.tracks,
+            [
+                CueTrack {
+                    number: 1,
+                    title: Some("First".to_string()),
+                    performer: None,
+                    start: Duration::ZERO,
+                    end: Some(Duration::from_millis(205_200)),
+                },
+                CueTrack {
+                    number: 2,
+                    title: Some("Second".to_string()),
+                    performer: Some("Guest".to_string()),
+                    start: Duration::from_millis(205_200),
+                    end: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn audio_data_falls_back_to_the_album() {
+        let sheet = CueSheet::parse(
+            "PERFORMER Band\nTITLE Album\nFILE a.wav WAVE\nTRACK 3 AUDIO\nINDEX 01 01:00:00\n",
+            "/music",
+        )
+        .unwrap();
+        let data = sheet.audio_data();
+        assert_eq!(data.len(), 1);
+        assert_eq!(data[0].name, "Track 03");
+        assert_eq!(data[0].author, "Band");
+        assert_eq!(data[0].album, "Album");
+        assert_eq!(data[0].track, Some(3));
+        assert_eq!(data[0].start, Some(Duration::from_secs(60)));
+        assert_eq!(data[0].end, None);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = |contents: &str| match CueSheet::parse(contents, "") {
+            Err(CueSheetError::Parse { line, message }) => (line, message),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(
+            error("TRACK 01 AUDIO\n"),
+            (1, "TRACK before FILE".to_string())
+        );
+        assert_eq!(
+            error("FILE a.wav WAVE\nINDEX 01 00:00:00\n"),
+            (2, "INDEX before TRACK".to_string())
+        );
+        assert_eq!(
+            error("FILE a.wav WAVE\nTRACK 01 AUDIO\nINDEX 01 00:61:00\n"),
+            (3, "invalid INDEX timestamp".to_string())
+        );
+        assert_eq!(
+            error("FILE a.wav WAVE\nTRACK AUDIO\n"),
+            (2, "TRACK without a number".to_string())
+        );
+    }
+}
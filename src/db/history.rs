@@ -0,0 +1,359 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use super::AudioData;
+
+#[derive(Debug)]
+pub enum HistoryError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnknownFormat(PathBuf),
+}
+
+impl std::fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "HistoryIoError: {}", e),
+            Self::Json(e) => write!(f, "HistoryJsonError: {}", e),
+            Self::UnknownFormat(path) => {
+                write!(f, "UnknownHistoryFormat: {}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for HistoryError {}
+
+impl From<std::io::Error> for HistoryError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<serde_json::Error> for HistoryError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Json(value)
+    }
+}
+
+// One listen, title and artist are copied so the history outlives the library entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlayRecord {
+    pub path: PathBuf,
+    // Virtual tracks (CUE sheets) share their path
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_ms: Option<i64>,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    // Unix time in milliseconds
+    pub started: i64,
+    pub listened_ms: i64,
+    pub completed: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlayCount {
+    pub label: String,
+    pub plays: u64,
+    pub listened: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistoryStats {
+    pub top_tracks: Vec<PlayCount>,
+    pub top_artists: Vec<PlayCount>,
+    // Monday of the week (`YYYY-MM-DD`) and the time listened in it, newest first
+    pub weeks: Vec<(String, Duration)>,
+    pub recent: Vec<PlayRecord>,
+    pub total: Duration,
+}
+
+// Whatever counts as a listen has to be at least this long, skipping through doesnt count
+const PLAY_MIN_LISTENED: Duration = Duration::from_secs(5);
+// Played up to here counts as completed, outros and silence at the end dont matter
+const PLAY_COMPLETED_RATIO: f64 = 0.9;
+
+// Follows the track coming out of the speakers and turns it into a `PlayRecord` once it is done
+#[derive(Debug, Default)]
+pub struct PlayTracker {
+    // Player id, track, start time and time listened so far
+    current: Option<(u64, AudioData, i64, Duration)>,
+    last_position: Option<Duration>,
+}
+
+impl PlayTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn id(&self) -> Option<u64> {
+        self.current.as_ref().map(|f| f.0)
+    }
+    pub fn start(&mut self, id: u64, track: AudioData) {
+        self.current = Some((id, track, unix_millis(), Duration::ZERO));
+        self.last_position = None;
+    }
+    // Seeking jumps around, only small steps forward are listening
+    pub fn update(&mut self, position: Option<Duration>) {
+        let Some((_, _, _, ref mut listened)) = self.current else {
+            return;
+        };
+        if let (Some(last), Some(position)) = (self.last_position, position) {
+            if position > last && position - last < Duration::from_secs(1) {
+                *listened += position - last;
+            }
+        }
+        if position.is_some() {
+            self.last_position = position;
+        }
+    }
+    pub fn finish(&mut self) -> Option<PlayRecord> {
+        let (_, track, started, listened) = self.current.take()?;
+        let last_position = self.last_position.take().unwrap_or_default();
+        if listened < PLAY_MIN_LISTENED {
+            return None;
+        }
+        let length = match (track.start, track.end) {
+            (Some(start), Some(end)) => Some(end.saturating_sub(start)),
+            _ => track.duration,
+        };
+        let completed = length.is_some_and(|length| {
+            last_position.as_secs_f64() >= length.as_secs_f64() * PLAY_COMPLETED_RATIO
+        });
+        Some(PlayRecord {
+            start_ms: track.start.map(|f| f.as_millis() as i64),
+            title: track.name.clone(),
+            artist: track.author.clone(),
+            album: track.album.clone(),
+            path: track.path,
+            started,
+            listened_ms: listened.as_millis() as i64,
+            completed,
+        })
+    }
+}
+
+pub fn unix_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|f| f.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+// Picked by extension, `.csv` or `.json`
+pub fn export_plays<P: AsRef<Path>>(plays: &[PlayRecord], path: P) -> Result<(), HistoryError> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .and_then(|f| f.to_str())
+        .map(str::to_lowercase);
+    let mut writer = match extension.as_deref() {
+        Some("csv") | Some("json") => BufWriter::new(File::create(path)?),
+        _ => return Err(HistoryError::UnknownFormat(path.to_path_buf())),
+    };
+    if extension.as_deref() == Some("json") {
+        serde_json::to_writer_pretty(&mut writer, plays)?;
+    } else {
+        writeln!(
+            writer,
+            "started,path,start_ms,title,artist,album,listened_ms,completed"
+        )?;
+        for play in plays {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{}",
+                format_utc(play.started),
+                csv_field(&play.path.to_string_lossy()),
+                play.start_ms.map(|f| f.to_string()).unwrap_or_default(),
+                csv_field(&play.title),
+                csv_field(&play.artist),
+                csv_field(&play.album),
+                play.listened_ms,
+                play.completed
+            )?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn csv_field(value: &str) -> String {
+    if !value.contains([',', '"', '\n', '\r']) {
+        return value.to_string();
+    }
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+// `2023-11-05T21:04:10Z`, days to date from http://howardhinnant.github.io/date_algorithms.html
+fn format_utc(millis: i64) -> String {
+    let seconds = millis.div_euclid(1000);
+    let (days, time) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker_with(track: AudioData, positions: &[u64]) -> PlayTracker {
+        let mut tracker = PlayTracker::new();
+        tracker.start(1, track);
+        for position in positions {
+            tracker.update(Some(Duration::from_millis(*position)));
+        }
+        tracker
+    }
+
+    fn track(duration: u64) -> AudioData {
+        let mut track = AudioData::new(PathBuf::from("/music/song.flac"));
+        track.duration = Some(Duration::from_secs(duration));
+        track
+    }
+
+    // Half a second at a time, like the player reports it
+    fn listen(from: u64, to: u64) -> Vec<u64> {
+        (from * 2..=to * 2).map(|f| f * 500).collect()
+    }
+
+    #[test]
+    fn format_utc_dates() {
+        assert_eq!(format_utc(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_utc(999), "1970-01-01T00:00:00Z");
+        assert_eq!(format_utc(-1000), "1969-12-31T23:59:59Z");
+        assert_eq!(format_utc(951868799000), "2000-02-29T23:59:59Z");
+        assert_eq!(format_utc(1704888000000), "2024-01-10T12:00:00Z");
+        assert_eq!(format_utc(1735732800000), "2025-01-01T12:00:00Z");
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("Airbag"), "Airbag");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(
+            csv_field("Hail to the Thief, Disc 1"),
+            "\"Hail to the Thief, Disc 1\""
+        );
+        assert_eq!(csv_field("The \"Best\" Of"), "\"The \"\"Best\"\" Of\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn short_listens_are_dropped() {
+        let mut tracker = tracker_with(track(200), &listen(0, 4));
+        assert_eq!(tracker.finish(), None);
+        // Nothing left to finish twice
+        assert_eq!(tracker.finish(), None);
+        let record = tracker_with(track(200), &listen(0, 5)).finish().unwrap();
+        assert_eq!(record.listened_ms, 5000);
+        assert!(!record.completed);
+    }
+
+    #[test]
+    fn seeking_is_not_listening() {
+        // Jumping to the end counts neither the jump nor going back
+        let mut positions = listen(0, 2);
+        positions.extend(listen(190, 191));
+        positions.extend(listen(10, 11));
+        assert_eq!(tracker_with(track(200), &positions).finish(), None);
+        // Pauses leave the position as it is
+        let mut positions = listen(0, 3);
+        positions.extend([3000, 3000, 3000]);
+        positions.extend(listen(3, 6));
+        let record = tracker_with(track(200), &positions).finish().unwrap();
+        assert_eq!(record.listened_ms, 6000);
+    }
+
+    #[test]
+    fn completed_from_ninety_percent() {
+        let record = tracker_with(track(10), &listen(0, 8)).finish().unwrap();
+        assert!(!record.completed);
+        let record = tracker_with(track(10), &listen(0, 9)).finish().unwrap();
+        assert!(record.completed);
+        // Unknown lengths are never completed
+        let mut unknown = track(10);
+        unknown.duration = None;
+        assert!(
+            !tracker_with(unknown, &listen(0, 10))
+                .finish()
+                .unwrap()
+                .completed
+        );
+    }
+
+    #[test]
+    fn cue_tracks_use_their_own_length() {
+        // Positions are relative to the start of the track, not the file
+        let mut cue = track(600);
+        cue.start = Some(Duration::from_secs(100));
+        cue.end = Some(Duration::from_secs(110));
+        let record = tracker_with(cue, &listen(0, 9)).finish().unwrap();
+        assert!(record.completed);
+        assert_eq!(record.start_ms, Some(100000));
+    }
+
+    #[test]
+    fn export_by_extension() {
+        let dir = std::env::temp_dir().join(format!(
+            "music_player_history_export_{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let plays = [PlayRecord {
+            path: PathBuf::from("/music/a, b.flac"),
+            start_ms: None,
+            title: String::from("Airbag"),
+            artist: String::from("Radiohead"),
+            album: String::from("OK Computer"),
+            started: 0,
+            listened_ms: 5000,
+            completed: true,
+        }];
+        export_plays(&plays, dir.join("history.CSV")).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("history.CSV")).unwrap(),
+            "started,path,start_ms,title,artist,album,listened_ms,completed\n\
+            1970-01-01T00:00:00Z,\"/music/a, b.flac\",,Airbag,Radiohead,OK Computer,5000,true\n"
+        );
+        export_plays(&plays, dir.join("history.json")).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("history.json")).unwrap())
+                .unwrap();
+        assert_eq!(json[0]["title"], "Airbag");
+        assert!(json[0].get("start_ms").is_none());
+        assert!(matches!(
+            export_plays(&plays, dir.join("history.txt")),
+            Err(HistoryError::UnknownFormat(_))
+        ));
+        assert!(!dir.join("history.txt").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1366 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::DirBuilder,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension, Row};
+
+use crate::{config::AppConfigPlaylist, playlist::Playlist};
+
+use super::{
+    audio_scanner::{FileStamp, ScanItem},
+    cover::cover_hash,
+    history::{HistoryStats, PlayCount, PlayRecord},
+    query::{Query, QueryCondition, QueryError, QueryField, QuerySort, QueryTermKind},
+    AudioData,
+};
+
+const LIBRARY_FILE_NAME: &str = "library.db";
+
+// Index is the schema version (`PRAGMA user_version`) the step upgrades to
+const LIBRARY_MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE files (
+        path TEXT PRIMARY KEY,
+        modified INTEGER NOT NULL,
+        size INTEGER NOT NULL
+    );
+    CREATE TABLE tracks (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL REFERENCES files(path) ON DELETE CASCADE,
+        start_ms INTEGER NOT NULL DEFAULT -1,
+        end_ms INTEGER,
+        title TEXT NOT NULL,
+        artist TEXT NOT NULL,
+        album TEXT NOT NULL,
+        album_artist TEXT NOT NULL,
+        track INTEGER,
+        disc INTEGER,
+        date TEXT NOT NULL,
+        genre TEXT NOT NULL,
+        duration_ms INTEGER,
+        codec TEXT NOT NULL,
+        bit_rate INTEGER,
+        sample_rate INTEGER,
+        added INTEGER NOT NULL,
+        UNIQUE (path, start_ms)
+    );
+    ",
+    "
+    CREATE TABLE plays (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL,
+        start_ms INTEGER,
+        title TEXT NOT NULL,
+        artist TEXT NOT NULL,
+        album TEXT NOT NULL,
+        started INTEGER NOT NULL,
+        listened_ms INTEGER NOT NULL,
+        completed INTEGER NOT NULL
+    );
+    CREATE INDEX plays_started ON plays (started);
+    ",
+    "
+    CREATE TABLE user_data (
+        path TEXT NOT NULL,
+        start_ms INTEGER NOT NULL DEFAULT -1,
+        rating INTEGER,
+        favorite INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (path, start_ms)
+    );
+    CREATE TABLE user_tags (
+        path TEXT NOT NULL,
+        start_ms INTEGER NOT NULL DEFAULT -1,
+        tag TEXT NOT NULL COLLATE NOCASE,
+        PRIMARY KEY (path, start_ms, tag)
+    );
+    ",
+    "
+    CREATE TABLE covers (
+        id INTEGER PRIMARY KEY,
+        hash INTEGER NOT NULL UNIQUE,
+        data BLOB NOT NULL
+    );
+    ALTER TABLE files ADD COLUMN cover INTEGER REFERENCES covers(id);
+    -- Known files have to be probed again for their covers
+    UPDATE files SET modified = -1;
+    ",
+    "
+    CREATE TABLE envelopes (
+        path TEXT NOT NULL REFERENCES files(path) ON DELETE CASCADE,
+        start_ms INTEGER NOT NULL DEFAULT -1,
+        data BLOB NOT NULL,
+        PRIMARY KEY (path, start_ms)
+    );
+    ",
+];
+
+// Monday of the week a play started in, local time
+const WEEK_COLUMN: &str = "date(started / 1000, 'unixepoch', 'localtime', 'weekday 0', '-6 days')";
+
+const PLAY_COLUMNS: &str = "path, start_ms, title, artist, album, started, listened_ms, completed";
+
+// Dates come in all shapes, the year is up front (see `AudioData::year`)
+const YEAR_COLUMN: &str = "NULLIF(CAST(substr(date, 1, 4) AS INTEGER), 0)";
+
+// Every column of `tracks` that maps onto `AudioData`, in `row_to_audio_data` order
+const TRACK_COLUMNS: &str = "path, start_ms, end_ms, title, artist, album, album_artist, track, \
+    disc, date, genre, duration_ms, codec, bit_rate, sample_rate";
+
+// Ratings and tags are kept apart from `tracks` so they survive rescans and files moving back in
+const TRACK_SOURCE: &str = "tracks LEFT JOIN user_data USING (path, start_ms)";
+
+// Read right after `TRACK_COLUMNS`, tags are joined by the unit separator
+const USER_COLUMNS: &str = "rating, COALESCE(favorite, 0), \
+    (SELECT group_concat(tag, char(31)) FROM user_tags AS t \
+    WHERE t.path = tracks.path AND t.start_ms = tracks.start_ms)";
+
+const TAG_SEPARATOR: char = '\u{1f}';
+
+#[derive(Debug)]
+pub enum LibraryError {
+    Io(std::io::Error),
+    Sqlite(rusqlite::Error),
+    Query(QueryError),
+}
+
+impl std::fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "LibraryIoError: {}", e),
+            Self::Sqlite(e) => write!(f, "LibrarySqliteError: {}", e),
+            Self::Query(e) => write!(f, "LibraryQueryError: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LibraryError {}
+
+impl From<std::io::Error> for LibraryError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<QueryError> for LibraryError {
+    fn from(value: QueryError) -> Self {
+        Self::Query(value)
+    }
+}
+
+impl From<rusqlite::Error> for LibraryError {
+    fn from(value: rusqlite::Error) -> Self {
+        Self::Sqlite(value)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LibraryUpdate {
+    pub unchanged: usize,
+    pub updated: usize,
+    pub removed: usize,
+    // Directories that couldnt be read, nothing below them was removed
+    pub unreadable: Vec<PathBuf>,
+}
+
+impl LibraryUpdate {
+    // The whole root was missing, e.g. an offline network mount
+    pub fn root_unreadable(&self, root: &Path) -> bool {
+        self.unreadable.iter().any(|f| f == root)
+    }
+}
+
+pub struct LibraryScan {
+    pub root: PathBuf,
+    seen: HashSet<PathBuf>,
+    update: LibraryUpdate,
+}
+
+impl LibraryScan {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            seen: HashSet::new(),
+            update: LibraryUpdate::default(),
+        }
+    }
+}
+
+pub struct Library {
+    connection: Connection,
+}
+
+impl Library {
+    pub fn open<P: AsRef<Path>>(data_dir: P) -> Result<Self, LibraryError> {
+        let data_dir = data_dir.as_ref();
+        if !data_dir.exists() {
+            DirBuilder::new().recursive(true).create(data_dir)?;
+        }
+        Self::with_connection(Connection::open(data_dir.join(LIBRARY_FILE_NAME))?)
+    }
+    fn with_connection(connection: Connection) -> Result<Self, LibraryError> {
+        connection.pragma_update(None, "foreign_keys", "ON")?;
+        let mut library = Self { connection };
+        library.migrate()?;
+        Ok(library)
+    }
+    fn migrate(&mut self) -> Result<(), LibraryError> {
+        let version: usize = self
+            .connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))?;
+        for (index, migration) in LIBRARY_MIGRATIONS.iter().enumerate().skip(version) {
+            let transaction = self.connection.transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.pragma_update(None, "user_version", index + 1)?;
+            transaction.commit()?;
+        }
+        Ok(())
+    }
+    pub fn file_stamps(&self) -> Result<HashMap<PathBuf, FileStamp>, LibraryError> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT path, modified, size FROM files")?;
+        let stamps = statement
+            .query_map([], |row| {
+                Ok((
+                    PathBuf::from(row.get::<_, String>(0)?),
+                    FileStamp {
+                        modified: row.get(1)?,
+                        size: row.get::<_, i64>(2)? as u64,
+                    },
+                ))
+            })?
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        Ok(stamps)
+    }
+    pub fn replace_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        stamp: FileStamp,
+        tracks: &[AudioData],
+    ) -> Result<(), LibraryError> {
+        store_file(&self.connection, path.as_ref(), stamp, tracks)
+    }
+    pub fn remove_file<P: AsRef<Path>>(&mut self, path: P) -> Result<usize, LibraryError> {
+        let path = path_to_string(path.as_ref());
+        Ok(self
+            .connection
+            .execute("DELETE FROM files WHERE path = ?1", [path])?)
+    }
+    // Removes files below `root` that werent `seen` during a scan, except below `unreadable` directories
+    pub fn remove_missing<P: AsRef<Path>>(
+        &mut self,
+        root: P,
+        seen: &HashSet<PathBuf>,
+        unreadable: &[PathBuf],
+    ) -> Result<usize, LibraryError> {
+        let root = root.as_ref();
+        let missing: Vec<PathBuf> = self
+            .file_stamps()?
+            .into_keys()
+            .filter(|f| f.starts_with(root) && !seen.contains(f))
+            .filter(|f| !unreadable.iter().any(|dir| f.starts_with(dir)))
+            .collect();
+        for path in missing.iter() {
+            self.remove_file(path)?;
+        }
+        Ok(missing.len())
+    }
+    // Files of roots that were removed from the config
+    pub fn remove_outside(&mut self, roots: &[PathBuf]) -> Result<usize, LibraryError> {
+        let outside: Vec<PathBuf> = self
+            .file_stamps()?
+            .into_keys()
+            .filter(|f| !roots.iter().any(|root| f.starts_with(root)))
+            .collect();
+        for path in outside.iter() {
+            self.remove_file(path)?;
+        }
+        Ok(outside.len())
+    }
+    pub fn tracks(&self) -> Result<Vec<AudioData>, LibraryError> {
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT {}, {} FROM {} ORDER BY path, start_ms",
+            TRACK_COLUMNS, USER_COLUMNS, TRACK_SOURCE
+        ))?;
+        let tracks = statement
+            .query_map([], row_to_audio_data)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(tracks)
+    }
+    pub fn query(&self, query: &Query) -> Result<Vec<AudioData>, LibraryError> {
+        self.query_sorted(query, QuerySort::Path, false, None)
+    }
+    pub fn query_sorted(
+        &self,
+        query: &Query,
+        sort: QuerySort,
+        descending: bool,
+        limit: Option<usize>,
+    ) -> Result<Vec<AudioData>, LibraryError> {
+        let (filter, mut values) = query_to_sql(query);
+        let direction = if descending { "DESC" } else { "ASC" };
+        // Ties are broken by path so results are stable
+        let order = match sort {
+            QuerySort::Path => format!("path {0}, start_ms {0}", direction),
+            QuerySort::Added => format!("added {}, path, start_ms", direction),
+            QuerySort::Title => format!("title COLLATE NOCASE {}, path", direction),
+            QuerySort::Artist => format!(
+                "artist COLLATE NOCASE {0}, album COLLATE NOCASE {0}, disc, track, path",
+                direction
+            ),
+            QuerySort::Album => format!(
+                "album COLLATE NOCASE {}, disc, track, path, start_ms",
+                direction
+            ),
+            QuerySort::Year => format!(
+                "{} {}, album COLLATE NOCASE, disc, track, path",
+                YEAR_COLUMN, direction
+            ),
+            QuerySort::Duration => format!("duration_ms {}, path", direction),
+            QuerySort::Rating => format!("rating {}, path, start_ms", direction),
+            QuerySort::Random => String::from("RANDOM()"),
+        };
+        // `LIMIT -1` means no limit
+        values.push(Value::Integer(limit.map_or(-1, |f| f as i64)));
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT {}, {} FROM {} WHERE {} ORDER BY {} LIMIT ?{}",
+            TRACK_COLUMNS,
+            USER_COLUMNS,
+            TRACK_SOURCE,
+            filter,
+            order,
+            values.len()
+        ))?;
+        let tracks = statement
+            .query_map(params_from_iter(values), row_to_audio_data)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(tracks)
+    }
+    pub fn smart_playlist(
+        &self,
+        playlist: &AppConfigPlaylist,
+    ) -> Result<Vec<AudioData>, LibraryError> {
+        let query = Query::parse(&playlist.query)?;
+        self.query_sorted(&query, playlist.sort, playlist.descending, playlist.limit)
+    }
+    // The result of a query as a playlist that follows the library
+    pub fn query_playlist(&self, query: &Query) -> Result<Playlist, LibraryError> {
+        let tracks = self.query(query)?;
+        Ok(Playlist::from_tracks(Some(query.text.clone()), &tracks))
+    }
+    // Scans trickle in while the UI keeps running, so items are stored in batches
+    pub fn apply_scan_items(
+        &mut self,
+        scan: &mut LibraryScan,
+        items: Vec<ScanItem>,
+    ) -> Result<(), LibraryError> {
+        let transaction = self.connection.transaction()?;
+        for item in items {
+            match item {
+                ScanItem::Unchanged(path) => {
+                    scan.update.unchanged += 1;
+                    scan.seen.insert(path);
+                }
+                ScanItem::Changed {
+                    path,
+                    stamp,
+                    tracks,
+                    cover,
+                } => {
+                    let stored = store_file(&transaction, &path, stamp, &tracks)
+                        .and_then(|()| store_cover(&transaction, &path, cover.as_deref()));
+                    if let Err(e) = stored {
+                        tracing::error!("Could not store {}: {}", path.display(), e);
+                    }
+                    scan.update.updated += 1;
+                    scan.seen.insert(path);
+                }
+                ScanItem::Unreadable(dir) => scan.update.unreadable.push(dir),
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+    // Rating, favorite and tags of `track`, replacing whatever was stored before
+    pub fn set_user_data(&mut self, track: &AudioData) -> Result<(), LibraryError> {
+        let path = path_to_string(&track.path);
+        let start_ms = duration_to_millis(track.start).unwrap_or(-1);
+        let transaction = self.connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO user_data (path, start_ms, rating, favorite) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (path, start_ms) DO UPDATE SET rating = ?3, favorite = ?4",
+            params![path, start_ms, track.rating, track.favorite],
+        )?;
+        transaction.execute(
+            "DELETE FROM user_tags WHERE path = ?1 AND start_ms = ?2",
+            params![path, start_ms],
+        )?;
+        for tag in track.tags.iter() {
+            transaction.execute(
+                "INSERT OR IGNORE INTO user_tags (path, start_ms, tag) VALUES (?1, ?2, ?3)",
+                params![path, start_ms, tag],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+    // Seek bar outline of `track`, computed the first time it played
+    pub fn envelope(&self, track: &AudioData) -> Result<Option<Vec<u8>>, LibraryError> {
+        let envelope = self
+            .connection
+            .query_row(
+                "SELECT data FROM envelopes WHERE path = ?1 AND start_ms = ?2",
+                params![
+                    path_to_string(&track.path),
+                    duration_to_millis(track.start).unwrap_or(-1)
+                ],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(envelope)
+    }
+    pub fn set_envelope(&self, track: &AudioData, data: &[u8]) -> Result<(), LibraryError> {
+        self.connection.execute(
+            "INSERT INTO envelopes (path, start_ms, data) VALUES (?1, ?2, ?3)
+            ON CONFLICT (path, start_ms) DO UPDATE SET data = ?3",
+            params![
+                path_to_string(&track.path),
+                duration_to_millis(track.start).unwrap_or(-1),
+                data
+            ],
+        )?;
+        Ok(())
+    }
+    pub fn record_play(&self, play: &PlayRecord) -> Result<(), LibraryError> {
+        self.connection.execute(
+            &format!(
+                "INSERT INTO plays ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                PLAY_COLUMNS
+            ),
+            params![
+                path_to_string(&play.path),
+                play.start_ms,
+                play.title,
+                play.artist,
+                play.album,
+                play.started,
+                play.listened_ms,
+                play.completed,
+            ],
+        )?;
+        Ok(())
+    }
+    // Oldest first, for exports
+    pub fn plays(&self) -> Result<Vec<PlayRecord>, LibraryError> {
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT {} FROM plays ORDER BY started",
+            PLAY_COLUMNS
+        ))?;
+        let plays = statement
+            .query_map([], row_to_play)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(plays)
+    }
+    // `limit` applies to every list on its own
+    pub fn history_stats(&self, limit: usize) -> Result<HistoryStats, LibraryError> {
+        let limit = limit as i64;
+        let row_to_count = |row: &Row<'_>| {
+            Ok(PlayCount {
+                label: row.get(0)?,
+                plays: row.get::<_, i64>(1)? as u64,
+                listened: Duration::from_millis(row.get::<_, i64>(2)? as u64),
+            })
+        };
+        let top_tracks = self
+            .connection
+            .prepare(
+                "SELECT CASE artist WHEN '' THEN title ELSE artist || ' - ' || title END,
+                    COUNT(*), SUM(listened_ms) FROM plays
+                GROUP BY path, start_ms ORDER BY COUNT(*) DESC, SUM(listened_ms) DESC LIMIT ?1",
+            )?
+            .query_map([limit], row_to_count)?
+            .collect::<Result<Vec<_>, _>>()?;
+        let top_artists = self
+            .connection
+            .prepare(
+                "SELECT artist, COUNT(*), SUM(listened_ms) FROM plays WHERE artist != ''
+                GROUP BY artist COLLATE NOCASE
+                ORDER BY COUNT(*) DESC, SUM(listened_ms) DESC LIMIT ?1",
+            )?
+            .query_map([limit], row_to_count)?
+            .collect::<Result<Vec<_>, _>>()?;
+        let weeks = self
+            .connection
+            .prepare(&format!(
+                "SELECT {0}, SUM(listened_ms) FROM plays GROUP BY {0} ORDER BY {0} DESC LIMIT ?1",
+                WEEK_COLUMN
+            ))?
+            .query_map([limit], |row| {
+                Ok((
+                    row.get(0)?,
+                    Duration::from_millis(row.get::<_, i64>(1)? as u64),
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let recent = self
+            .connection
+            .prepare(&format!(
+                "SELECT {} FROM plays ORDER BY started DESC LIMIT ?1",
+                PLAY_COLUMNS
+            ))?
+            .query_map([limit], row_to_play)?
+            .collect::<Result<Vec<_>, _>>()?;
+        let total: i64 = self.connection.query_row(
+            "SELECT COALESCE(SUM(listened_ms), 0) FROM plays",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(HistoryStats {
+            top_tracks,
+            top_artists,
+            weeks,
+            recent,
+            total: Duration::from_millis(total as u64),
+        })
+    }
+    // A cancelled scan didnt see everything, neither did one that couldnt read its root,
+    // so nothing gets removed
+    pub fn finish_scan(
+        &mut self,
+        mut scan: LibraryScan,
+        cancelled: bool,
+    ) -> Result<LibraryUpdate, LibraryError> {
+        if !cancelled && !scan.update.root_unreadable(&scan.root) {
+            scan.update.removed =
+                self.remove_missing(&scan.root, &scan.seen, &scan.update.unreadable)?;
+        }
+        // Albums share their cover, it goes once the last file using it is gone
+        self.connection.execute(
+            "DELETE FROM covers WHERE id NOT IN (SELECT cover FROM files WHERE cover IS NOT NULL)",
+            [],
+        )?;
+        Ok(scan.update)
+    }
+    // Embedded picture or cover file, as it was found during the last scan
+    pub fn cover<P: AsRef<Path>>(&self, path: P) -> Result<Option<Vec<u8>>, LibraryError> {
+        let cover = self
+            .connection
+            .query_row(
+                "SELECT covers.data FROM files JOIN covers ON covers.id = files.cover
+                WHERE files.path = ?1",
+                [path_to_string(path.as_ref())],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(cover)
+    }
+}
+
+fn store_cover(
+    connection: &Connection,
+    path: &Path,
+    cover: Option<&[u8]>,
+) -> Result<(), LibraryError> {
+    let path = path_to_string(path);
+    let Some(cover) = cover else {
+        connection.execute("UPDATE files SET cover = NULL WHERE path = ?1", [path])?;
+        return Ok(());
+    };
+    let hash = cover_hash(cover);
+    connection.execute(
+        "INSERT INTO covers (hash, data) VALUES (?1, ?2) ON CONFLICT (hash) DO NOTHING",
+        params![hash, cover],
+    )?;
+    connection.execute(
+        "UPDATE files SET cover = (SELECT id FROM covers WHERE hash = ?1) WHERE path = ?2",
+        params![hash, path],
+    )?;
+    Ok(())
+}
+
+// Swaps out every track of `path`, keeping rows (and their ids) that still exist
+fn store_file(
+    connection: &Connection,
+    path: &Path,
+    stamp: FileStamp,
+    tracks: &[AudioData],
+) -> Result<(), LibraryError> {
+    let path = path_to_string(path);
+    connection.execute(
+        "INSERT INTO files (path, modified, size) VALUES (?1, ?2, ?3)
+        ON CONFLICT (path) DO UPDATE SET modified = ?2, size = ?3",
+        params![path, stamp.modified, stamp.size as i64],
+    )?;
+    // The audio itself might have changed, outlines are computed again on the next play
+    connection.execute("DELETE FROM envelopes WHERE path = ?1", [&path])?;
+    let added = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let mut starts: Vec<i64> = Vec::new();
+    for track in tracks {
+        let start_ms = duration_to_millis(track.start).unwrap_or(-1);
+        starts.push(start_ms);
+        connection.execute(
+            &format!(
+                "INSERT INTO tracks ({}, added)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)
+                ON CONFLICT (path, start_ms) DO UPDATE SET
+                    end_ms = ?3, title = ?4, artist = ?5, album = ?6, album_artist = ?7,
+                    track = ?8, disc = ?9, date = ?10, genre = ?11, duration_ms = ?12,
+                    codec = ?13, bit_rate = ?14, sample_rate = ?15",
+                TRACK_COLUMNS
+            ),
+            params![
+                path,
+                start_ms,
+                duration_to_millis(track.end),
+                track.name,
+                track.author,
+                track.album,
+                track.album_artist,
+                track.track,
+                track.disc,
+                track.date,
+                track.genre,
+                duration_to_millis(track.duration),
+                track.codec,
+                track.bit_rate.map(|f| f as i64),
+                track.sample_rate,
+                added,
+            ],
+        )?;
+    }
+    // Tracks that vanished from the file (e.g. an edited CUE sheet)
+    let mut statement = connection.prepare("SELECT start_ms FROM tracks WHERE path = ?1")?;
+    let stale: Vec<i64> = statement
+        .query_map([&path], |row| row.get(0))?
+        .filter_map(Result::ok)
+        .filter(|f| !starts.contains(f))
+        .collect();
+    for start_ms in stale {
+        connection.execute(
+            "DELETE FROM tracks WHERE path = ?1 AND start_ms = ?2",
+            params![path, start_ms],
+        )?;
+    }
+    Ok(())
+}
+
+// Builds the WHERE clause, every value is passed as a parameter
+fn query_to_sql(query: &Query) -> (String, Vec<Value>) {
+    let mut values: Vec<Value> = Vec::new();
+    let mut conditions: Vec<String> = Vec::new();
+    for term in query.terms.iter() {
+        let condition = match term.kind {
+            QueryTermKind::Text(ref text) => {
+                let columns = ["title", "artist", "album", "album_artist"];
+                text_condition(
+                    &columns,
+                    &QueryCondition::Contains(text.clone()),
+                    &mut values,
+                )
+            }
+            QueryTermKind::Field(field, ref condition) => match field_column(field) {
+                FieldColumn::Text(columns) => text_condition(columns, condition, &mut values),
+                FieldColumn::Number(column) => number_condition(column, condition, &mut values),
+                FieldColumn::Tag => format!(
+                    "EXISTS (SELECT 1 FROM user_tags AS t WHERE t.path = tracks.path \
+                    AND t.start_ms = tracks.start_ms AND {})",
+                    text_condition(&["t.tag"], condition, &mut values)
+                ),
+            },
+        };
+        // Missing values (NULL) never match, negated or not
+        conditions.push(match term.negated {
+            true => format!("NOT COALESCE({}, 1)", condition),
+            false => format!("COALESCE({}, 0)", condition),
+        });
+    }
+    if conditions.is_empty() {
+        return (String::from("1"), values);
+    }
+    (conditions.join(" AND "), values)
+}
+
+enum FieldColumn {
+    // Any of the columns can match
+    Text(&'static [&'static str]),
+    Number(&'static str),
+    // Any of the user tags can match
+    Tag,
+}
+
+fn field_column(field: QueryField) -> FieldColumn {
+    match field {
+        QueryField::Artist => FieldColumn::Text(&["artist", "album_artist"]),
+        QueryField::AlbumArtist => FieldColumn::Text(&["album_artist"]),
+        QueryField::Album => FieldColumn::Text(&["album"]),
+        QueryField::Title => FieldColumn::Text(&["title"]),
+        QueryField::Genre => FieldColumn::Text(&["genre"]),
+        QueryField::Path => FieldColumn::Text(&["path"]),
+        QueryField::Codec => FieldColumn::Text(&["codec"]),
+        QueryField::Year => FieldColumn::Number(YEAR_COLUMN),
+        QueryField::Duration => FieldColumn::Number("(duration_ms / 1000)"),
+        QueryField::BitRate => FieldColumn::Number("(bit_rate / 1000)"),
+        QueryField::SampleRate => FieldColumn::Number("sample_rate"),
+        QueryField::Track => FieldColumn::Number("track"),
+        QueryField::Disc => FieldColumn::Number("disc"),
+        QueryField::Rating => FieldColumn::Number("rating"),
+        QueryField::Favorite => FieldColumn::Number("COALESCE(favorite, 0)"),
+        QueryField::Tag => FieldColumn::Tag,
+    }
+}
+
+fn text_condition(columns: &[&str], condition: &QueryCondition, values: &mut Vec<Value>) -> String {
+    let (value, like) = match condition {
+        QueryCondition::Contains(text) => {
+            // LIKE wildcards in the text are meant literally
+            let escaped = text
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            (format!("%{}%", escaped), true)
+        }
+        QueryCondition::Is(text) => (text.clone(), false),
+        // The parser only hands out text conditions for text fields
+        _ => return String::from("0"),
+    };
+    values.push(Value::Text(value));
+    let index = values.len();
+    let matches: Vec<String> = columns
+        .iter()
+        .map(|column| match like {
+            true => format!("{} LIKE ?{} ESCAPE '\\'", column, index),
+            false => format!("{} = ?{} COLLATE NOCASE", column, index),
+        })
+        .collect();
+    format!("({})", matches.join(" OR "))
+}
+
+fn number_condition(column: &str, condition: &QueryCondition, values: &mut Vec<Value>) -> String {
+    let mut push = |value: i64| {
+        values.push(Value::Integer(value));
+        values.len()
+    };
+    match condition {
+        QueryCondition::Equals(value) => format!("({} = ?{})", column, push(*value)),
+        QueryCondition::Compare(compare, value) => {
+            format!("({} {} ?{})", column, compare.operator(), push(*value))
+        }
+        QueryCondition::Range(start, end) => {
+            let start = push(*start);
+            format!("({} BETWEEN ?{} AND ?{})", column, start, push(*end))
+        }
+        _ => String::from("0"),
+    }
+}
+
+fn row_to_audio_data(row: &Row<'_>) -> rusqlite::Result<AudioData> {
+    let mut data = AudioData::new(PathBuf::from(row.get::<_, String>(0)?));
+    data.start = millis_to_duration(Some(row.get::<_, i64>(1)?));
+    data.end = millis_to_duration(row.get(2)?);
+    data.name = row.get(3)?;
+    data.author = row.get(4)?;
+    data.album = row.get(5)?;
+    data.album_artist = row.get(6)?;
+    data.track = row.get(7)?;
+    data.disc = row.get(8)?;
+    data.date = row.get(9)?;
+    data.genre = row.get(10)?;
+    data.duration = millis_to_duration(row.get(11)?);
+    data.codec = row.get(12)?;
+    data.bit_rate = row.get::<_, Option<i64>>(13)?.map(|f| f as u64);
+    data.sample_rate = row.get(14)?;
+    data.rating = row.get(15)?;
+    data.favorite = row.get(16)?;
+    data.tags = row
+        .get::<_, Option<String>>(17)?
+        .map(|f| f.split(TAG_SEPARATOR).map(String::from).collect())
+        .unwrap_or_default();
+    Ok(data)
+}
+
+fn row_to_play(row: &Row<'_>) -> rusqlite::Result<PlayRecord> {
+    Ok(PlayRecord {
+        path: PathBuf::from(row.get::<_, String>(0)?),
+        start_ms: row.get(1)?,
+        title: row.get(2)?,
+        artist: row.get(3)?,
+        album: row.get(4)?,
+        started: row.get(5)?,
+        listened_ms: row.get(6)?,
+        completed: row.get(7)?,
+    })
+}
+
+// Paths are stored as text, non UTF-8 paths get mangled but stay unique enough
+fn path_to_string(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+fn duration_to_millis(duration: Option<Duration>) -> Option<i64> {
+    duration.map(|f| f.as_millis() as i64)
+}
+
+// Negative values mean "not set"
+fn millis_to_duration(millis: Option<i64>) -> Option<Duration> {
+    millis
+        .filter(|f| *f >= 0)
+        .map(|f| Duration::from_millis(f as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library() -> Library {
+        Library::with_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    fn track(path: &str, title: &str) -> AudioData {
+        let mut track = AudioData::new(PathBuf::from(path));
+        track.name = title.to_string();
+        track
+    }
+
+    fn stamp(modified: i64) -> FileStamp {
+        FileStamp { modified, size: 10 }
+    }
+
+    fn paths(library: &Library) -> Vec<String> {
+        let mut paths: Vec<String> = library
+            .file_stamps()
+            .unwrap()
+            .into_keys()
+            .map(|f| f.display().to_string())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    fn version(connection: &Connection) -> usize {
+        connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn migrates_a_new_library_to_the_latest_version() {
+        let mut library = library();
+        assert_eq!(version(&library.connection), LIBRARY_MIGRATIONS.len());
+        // Opening it again runs nothing
+        library.migrate().unwrap();
+        assert_eq!(version(&library.connection), LIBRARY_MIGRATIONS.len());
+    }
+
+    #[test]
+    fn upgrades_the_first_schema_without_losing_tracks() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(LIBRARY_MIGRATIONS[0]).unwrap();
+        connection.pragma_update(None, "user_version", 1).unwrap();
+        connection
+            .execute_batch(
+                "INSERT INTO files (path, modified, size) VALUES ('/music/a.flac', 1000, 10);
+                INSERT INTO tracks (path, title, artist, album, album_artist, date, genre, codec, added)
+                VALUES ('/music/a.flac', 'A', 'Artist', '', '', '1997', '', 'flac', 0);",
+            )
+            .unwrap();
+        let mut library = Library::with_connection(connection).unwrap();
+        assert_eq!(version(&library.connection), LIBRARY_MIGRATIONS.len());
+        let tracks = library.tracks().unwrap();
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].name, "A");
+        assert_eq!(tracks[0].start, None);
+        assert_eq!(tracks[0].rating, None);
+        assert!(tracks[0].tags.is_empty());
+        // Covers didnt exist yet, so the file is probed again on the next scan
+        let stamps = library.file_stamps().unwrap();
+        assert_eq!(stamps[Path::new("/music/a.flac")].modified, -1);
+        // The newer tables are there and usable
+        library.set_user_data(&tracks[0]).unwrap();
+        library.set_envelope(&tracks[0], &[1, 2, 3]).unwrap();
+    }
+
+    #[test]
+    fn replacing_a_file_keeps_its_stamp_and_drops_stale_tracks() {
+        let mut library = library();
+        let mut first = track("/music/album.flac", "First");
+        first.start = Some(Duration::ZERO);
+        first.end = Some(Duration::from_secs(60));
+        let mut second = track("/music/album.flac", "Second");
+        second.start = Some(Duration::from_secs(60));
+        library
+            .replace_file("/music/album.flac", stamp(1), &[first.clone(), second])
+            .unwrap();
+        library.set_envelope(&first, &[1]).unwrap();
+        // The CUE sheet lost its second track
+        first.name = String::from("Renamed");
+        library
+            .replace_file("/music/album.flac", stamp(2), &[first.clone()])
+            .unwrap();
+        let tracks = library.tracks().unwrap();
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].name, "Renamed");
+        assert_eq!(tracks[0].start, Some(Duration::ZERO));
+        assert_eq!(tracks[0].end, Some(Duration::from_secs(60)));
+        assert_eq!(
+            library.file_stamps().unwrap()[Path::new("/music/album.flac")],
+            stamp(2)
+        );
+        // The audio might have changed
+        assert_eq!(library.envelope(&first).unwrap(), None);
+    }
+
+    fn scanned_library() -> Library {
+        let mut library = library();
+        for path in [
+            "/music/a.flac",
+            "/music/nas/b.flac",
+            "/music/nas/deep/c.flac",
+            "/other/d.flac",
+        ] {
+            library
+                .replace_file(path, stamp(1), &[track(path, "")])
+                .unwrap();
+        }
+        library
+    }
+
+    #[test]
+    fn removes_missing_files_except_below_unreadable_directories() {
+        let mut library = scanned_library();
+        let seen = HashSet::new();
+        let removed = library
+            .remove_missing("/music", &seen, &[PathBuf::from("/music/nas")])
+            .unwrap();
+        assert_eq!(removed, 1);
+        assert_eq!(
+            paths(&library),
+            [
+                "/music/nas/b.flac",
+                "/music/nas/deep/c.flac",
+                "/other/d.flac"
+            ]
+        );
+        assert_eq!(library.tracks().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn removes_files_outside_of_the_roots() {
+        let mut library = scanned_library();
+        let removed = library
+            .remove_outside(&[PathBuf::from("/music/nas")])
+            .unwrap();
+        assert_eq!(removed, 2);
+        assert_eq!(
+            paths(&library),
+            ["/music/nas/b.flac", "/music/nas/deep/c.flac"]
+        );
+    }
+
+    fn finish(library: &mut Library, items: Vec<ScanItem>, cancelled: bool) -> LibraryUpdate {
+        let mut scan = LibraryScan::new("/music");
+        library.apply_scan_items(&mut scan, items).unwrap();
+        library.finish_scan(scan, cancelled).unwrap()
+    }
+
+    #[test]
+    fn incremental_scans_keep_what_they_could_not_read() {
+        let mut library = scanned_library();
+        let update = finish(
+            &mut library,
+            Vec::from([
+                ScanItem::Unchanged(PathBuf::from("/music/a.flac")),
+                ScanItem::Unreadable(PathBuf::from("/music/nas/deep")),
+            ]),
+            false,
+        );
+        assert_eq!(update.unchanged, 1);
+        assert_eq!(update.removed, 1);
+        assert_eq!(update.unreadable, [PathBuf::from("/music/nas/deep")]);
+        assert_eq!(
+            paths(&library),
+            ["/music/a.flac", "/music/nas/deep/c.flac", "/other/d.flac"]
+        );
+    }
+
+    #[test]
+    fn unreadable_roots_and_cancelled_scans_remove_nothing() {
+        let mut library = scanned_library();
+        let update = finish(
+            &mut library,
+            Vec::from([ScanItem::Unreadable(PathBuf::from("/music"))]),
+            false,
+        );
+        assert!(update.root_unreadable(Path::new("/music")));
+        assert_eq!(update.removed, 0);
+        let update = finish(&mut library, Vec::new(), true);
+        assert_eq!(update.removed, 0);
+        assert_eq!(paths(&library).len(), 4);
+    }
+
+    #[test]
+    fn changed_files_are_stored_with_their_cover() {
+        let mut library = library();
+        let cover = Vec::from([1u8, 2, 3]);
+        let changed = |path: &str| ScanItem::Changed {
+            path: PathBuf::from(path),
+            stamp: stamp(5),
+            tracks: Vec::from([track(path, "Song")]),
+            cover: Some(cover.clone()),
+        };
+        let update = finish(
+            &mut library,
+            Vec::from([changed("/music/a.flac"), changed("/music/b.flac")]),
+            false,
+        );
+        assert_eq!(update.updated, 2);
+        assert_eq!(library.cover("/music/a.flac").unwrap(), Some(cover.clone()));
+        // Both files share one stored cover, it stays until the last of them is gone
+        let covers = |library: &Library| -> i64 {
+            library
+                .connection
+                .query_row("SELECT COUNT(*) FROM covers", [], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(covers(&library), 1);
+        finish(
+            &mut library,
+            Vec::from([ScanItem::Unchanged(PathBuf::from("/music/b.flac"))]),
+            false,
+        );
+        assert_eq!(covers(&library), 1);
+        finish(&mut library, Vec::new(), false);
+        assert_eq!(covers(&library), 0);
+    }
+
+    // Radiohead and a few tracks with missing values
+    fn seeded_library() -> Library {
+        let mut library = library();
+        let rows = [
+            (
+                "/music/ok/airbag.flac",
+                "Airbag",
+                "Radiohead",
+                "OK Computer",
+                "1997",
+                Some(284),
+                Some(1),
+            ),
+            (
+                "/music/ok/paranoid.mp3",
+                "Paranoid Android",
+                "Radiohead",
+                "OK Computer",
+                "1997-05-21",
+                Some(383),
+                Some(2),
+            ),
+            (
+                "/music/kid/idioteque.flac",
+                "Idioteque",
+                "Radiohead",
+                "Kid A",
+                "2000",
+                Some(309),
+                Some(8),
+            ),
+            (
+                "/music/misc/100%_done.ogg",
+                "100% Done",
+                "",
+                "",
+                "",
+                None,
+                None,
+            ),
+            (
+                "/music/other/song.flac",
+                "song",
+                "Someone",
+                "Hits",
+                "2010",
+                Some(200),
+                None,
+            ),
+        ];
+        for (path, title, artist, album, date, seconds, number) in rows {
+            let mut data = track(path, title);
+            data.author = artist.to_string();
+            data.album = album.to_string();
+            data.date = date.to_string();
+            data.duration = seconds.map(Duration::from_secs);
+            data.track = number;
+            data.codec = path.rsplit('.').next().unwrap_or_default().to_string();
+            data.genre = match album {
+                "OK Computer" => String::from("Rock"),
+                "Kid A" => String::from("Electronic"),
+                _ => String::new(),
+            };
+            if title == "song" {
+                data.album_artist = String::from("Various");
+            }
+            library.replace_file(path, stamp(1), &[data]).unwrap();
+        }
+        let mut tracks = library.tracks().unwrap();
+        for track in tracks.iter_mut() {
+            match track.name.as_str() {
+                "Airbag" => {
+                    track.rating = Some(5);
+                    track.favorite = true;
+                    track.tags = Vec::from([String::from("workout")]);
+                }
+                "Idioteque" => track.rating = Some(3),
+                _ => continue,
+            }
+            library.set_user_data(track).unwrap();
+        }
+        library
+    }
+
+    fn titles(tracks: Vec<AudioData>) -> Vec<String> {
+        tracks.into_iter().map(|f| f.name).collect()
+    }
+
+    // Sorted by path, like the file list
+    fn query(library: &Library, text: &str) -> Vec<String> {
+        titles(library.query(&Query::parse(text).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn queries_match_text_and_fields() {
+        let library = seeded_library();
+        assert_eq!(
+            query(&library, ""),
+            [
+                "Idioteque",
+                "100% Done",
+                "Airbag",
+                "Paranoid Android",
+                "song"
+            ]
+        );
+        assert_eq!(query(&library, "radiohead kid"), ["Idioteque"]);
+        // Artist covers the album artist too
+        assert_eq!(query(&library, "artist:various"), ["song"]);
+        assert_eq!(
+            query(&library, "album:=\"ok computer\""),
+            ["Airbag", "Paranoid Android"]
+        );
+        assert_eq!(
+            query(&library, "codec:flac year:1990..2005"),
+            ["Idioteque", "Airbag"]
+        );
+        assert_eq!(query(&library, "track:2"), ["Paranoid Android"]);
+        // Wildcards are taken literally
+        assert_eq!(query(&library, "title:100%"), ["100% Done"]);
+        assert!(query(&library, "title:_").is_empty());
+    }
+
+    #[test]
+    fn negated_queries() {
+        let library = seeded_library();
+        assert_eq!(
+            query(&library, "-genre:rock"),
+            ["Idioteque", "100% Done", "song"]
+        );
+        assert_eq!(query(&library, "radiohead -album:ok"), ["Idioteque"]);
+        assert_eq!(
+            query(&library, "-tag:workout -codec:ogg"),
+            ["Idioteque", "Paranoid Android", "song"]
+        );
+    }
+
+    #[test]
+    fn missing_values_never_match() {
+        let library = seeded_library();
+        // No year, no duration and no rating, negated or not
+        assert_eq!(
+            query(&library, "year:<2005"),
+            ["Idioteque", "Airbag", "Paranoid Android"]
+        );
+        assert_eq!(query(&library, "-year:<2005"), ["song"]);
+        assert_eq!(query(&library, "duration:<250"), ["song"]);
+        assert_eq!(
+            query(&library, "-duration:<250"),
+            ["Idioteque", "Airbag", "Paranoid Android"]
+        );
+        assert_eq!(query(&library, "-rating:5"), ["Idioteque"]);
+        // Everything has a favorite flag, even without user data
+        assert_eq!(query(&library, "favorite:no").len(), 4);
+        assert_eq!(
+            query(&library, "tag:workout favorite:yes rating:>=4"),
+            ["Airbag"]
+        );
+    }
+
+    #[test]
+    fn sorted_and_limited() {
+        let library = seeded_library();
+        let sorted = |sort: QuerySort, descending: bool, limit: Option<usize>| {
+            titles(
+                library
+                    .query_sorted(&Query::default(), sort, descending, limit)
+                    .unwrap(),
+            )
+        };
+        // Missing years come first, tracks of an album stay in order
+        assert_eq!(
+            sorted(QuerySort::Year, false, None),
+            [
+                "100% Done",
+                "Airbag",
+                "Paranoid Android",
+                "Idioteque",
+                "song"
+            ]
+        );
+        assert_eq!(
+            sorted(QuerySort::Year, true, Some(2)),
+            ["song", "Idioteque"]
+        );
+        assert_eq!(
+            sorted(QuerySort::Title, false, None),
+            [
+                "100% Done",
+                "Airbag",
+                "Idioteque",
+                "Paranoid Android",
+                "song"
+            ]
+        );
+        assert_eq!(sorted(QuerySort::Rating, true, Some(1)), ["Airbag"]);
+        assert_eq!(sorted(QuerySort::Random, false, Some(3)).len(), 3);
+    }
+
+    #[test]
+    fn smart_playlists() {
+        let library = seeded_library();
+        let playlist = |query: &str| AppConfigPlaylist {
+            name: String::from("Longest"),
+            query: query.to_string(),
+            sort: QuerySort::Duration,
+            descending: true,
+            limit: Some(2),
+        };
+        assert_eq!(
+            titles(
+                library
+                    .smart_playlist(&playlist("artist:radiohead"))
+                    .unwrap()
+            ),
+            ["Paranoid Android", "Idioteque"]
+        );
+        assert!(matches!(
+            library.smart_playlist(&playlist("year:>soon")),
+            Err(LibraryError::Query(_))
+        ));
+    }
+
+    #[test]
+    fn user_data_round_trips() {
+        let mut library = library();
+        let mut first = track("/music/album.flac", "First");
+        first.start = Some(Duration::ZERO);
+        let mut second = track("/music/album.flac", "Second");
+        second.start = Some(Duration::from_secs(60));
+        library
+            .replace_file(
+                "/music/album.flac",
+                stamp(1),
+                &[first.clone(), second.clone()],
+            )
+            .unwrap();
+        first.rating = Some(4);
+        first.favorite = true;
+        // Tags are case insensitive, the first spelling stays
+        first.tags = Vec::from([
+            String::from("Workout"),
+            String::from("night"),
+            String::from("workout"),
+        ]);
+        library.set_user_data(&first).unwrap();
+        let tracks = library.tracks().unwrap();
+        assert_eq!(tracks[0].rating, Some(4));
+        assert!(tracks[0].favorite);
+        let mut tags = tracks[0].tags.clone();
+        tags.sort();
+        assert_eq!(tags, ["Workout", "night"]);
+        // CUE tracks of the same file keep their own
+        assert_eq!(tracks[1].rating, None);
+        assert!(!tracks[1].favorite);
+        assert!(tracks[1].tags.is_empty());
+        // It survives the file being rescanned, or removed and found again
+        library
+            .replace_file(
+                "/music/album.flac",
+                stamp(2),
+                &[first.clone(), second.clone()],
+            )
+            .unwrap();
+        library.remove_file("/music/album.flac").unwrap();
+        library
+            .replace_file("/music/album.flac", stamp(3), &[first.clone(), second])
+            .unwrap();
+        assert_eq!(library.tracks().unwrap()[0].rating, Some(4));
+        // Saving again replaces everything
+        first.rating = None;
+        first.favorite = false;
+        first.tags = Vec::new();
+        library.set_user_data(&first).unwrap();
+        let tracks = library.tracks().unwrap();
+        assert_eq!(tracks[0].rating, None);
+        assert!(!tracks[0].favorite);
+        assert!(tracks[0].tags.is_empty());
+    }
+
+    fn play(title: &str, artist: &str, started: i64, listened_secs: u64) -> PlayRecord {
+        PlayRecord {
+            path: PathBuf::from(format!("/music/{}.flac", title)),
+            start_ms: None,
+            title: title.to_string(),
+            artist: artist.to_string(),
+            album: String::new(),
+            started,
+            listened_ms: listened_secs as i64 * 1000,
+            completed: false,
+        }
+    }
+
+    #[test]
+    fn history_stats_bucket_weeks() {
+        let library = library();
+        // Wednesdays and Thursdays at noon UTC, the same week in any time zone
+        let plays = [
+            play("Airbag", "Radiohead", 1704888000000, 60),
+            play("Airbag", "Radiohead", 1704974400000, 120),
+            play("Idioteque", "radiohead", 1705492800000, 30),
+            // Weeks start on Monday, even in the previous year
+            play("Intro", "", 1735732800000, 10),
+        ];
+        for play in plays.iter() {
+            library.record_play(play).unwrap();
+        }
+        let stats = library.history_stats(10).unwrap();
+        assert_eq!(
+            stats.weeks,
+            [
+                (String::from("2024-12-30"), Duration::from_secs(10)),
+                (String::from("2024-01-15"), Duration::from_secs(30)),
+                (String::from("2024-01-08"), Duration::from_secs(180)),
+            ]
+        );
+        assert_eq!(stats.total, Duration::from_secs(220));
+        assert_eq!(stats.top_tracks[0].label, "Radiohead - Airbag");
+        assert_eq!(stats.top_tracks[0].plays, 2);
+        // Artists are counted case insensitively, nameless ones not at all
+        assert_eq!(stats.top_artists.len(), 1);
+        assert_eq!(stats.top_artists[0].plays, 3);
+        assert_eq!(stats.recent[0].title, "Intro");
+        assert_eq!(library.history_stats(2).unwrap().weeks.len(), 2);
+    }
+}
@@ -0,0 +1,279 @@
+use std::{path::Path, time::Duration};
+
+use ffmpeg_next::{
+    format::input as FFMpegInput,
+    util::{dictionary::Ref as FFMpegDictionaryRef, error::Error as FFMpegError},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LyricLine {
+    // None for plain lyrics without timestamps
+    pub time: Option<Duration>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lyrics {
+    pub lines: Vec<LyricLine>,
+    // Every line has a time, sorted by it
+    pub synced: bool,
+}
+
+impl Lyrics {
+    // LRC (`[01:02.50]Some line`) or plain text, LRC needs at least one timestamp to count
+    pub fn parse(text: &str) -> Self {
+        let mut offset_ms: i64 = 0;
+        let mut timed: Vec<LyricLine> = Vec::new();
+        let mut plain: Vec<LyricLine> = Vec::new();
+        for line in text.lines() {
+            let line = line.trim_end_matches('\r');
+            let (tags, text) = split_tags(line);
+            let text = strip_word_times(text.trim());
+            let mut times: Vec<Duration> = Vec::new();
+            let mut metadata = !tags.is_empty();
+            for tag in tags.iter() {
+                if let Some(time) = parse_timestamp(tag) {
+                    times.push(time);
+                } else if let Some((key, value)) = tag.split_once(':') {
+                    // `[ar:...]`, `[ti:...]` and friends say nothing about the timing
+                    if key.trim().eq_ignore_ascii_case("offset") {
+                        offset_ms = value.trim().parse::<i64>().unwrap_or_default();
+                    }
+                } else {
+                    metadata = false;
+                }
+            }
+            if !times.is_empty() {
+                timed.extend(times.into_iter().map(|time| LyricLine {
+                    time: Some(time),
+                    text: text.clone(),
+                }));
+            } else if !metadata {
+                // `[Chorus]` in plain lyrics
+                plain.push(LyricLine {
+                    time: None,
+                    text: line.trim().to_string(),
+                });
+            }
+        }
+        if timed.is_empty() {
+            // Leading and trailing empty lines are just noise in a pane
+            while plain.last().is_some_and(|f| f.text.is_empty()) {
+                plain.pop();
+            }
+            let start = plain.iter().take_while(|f| f.text.is_empty()).count();
+            return Self {
+                lines: plain.split_off(start),
+                synced: false,
+            };
+        }
+        // A positive offset shows the lines sooner
+        for line in timed.iter_mut() {
+            let ms = line.time.unwrap_or_default().as_millis() as i64 - offset_ms;
+            line.time = Some(Duration::from_millis(ms.max(0) as u64));
+        }
+        timed.sort_by_key(|f| f.time);
+        Self {
+            lines: timed,
+            synced: true,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.lines.iter().all(|f| f.text.is_empty())
+    }
+    // Last line that started at or before `position`
+    pub fn line_at(&self, position: Duration) -> Option<usize> {
+        if !self.synced {
+            return None;
+        }
+        self.lines
+            .partition_point(|f| f.time.is_some_and(|time| time <= position))
+            .checked_sub(1)
+    }
+}
+
+// `[..][..]text`, the leading brackets of an LRC line and whatever follows them
+fn split_tags(line: &str) -> (Vec<&str>, &str) {
+    let mut tags: Vec<&str> = Vec::new();
+    let mut rest = line.trim_start();
+    while let Some(inner) = rest.strip_prefix('[') {
+        let Some(end) = inner.find(']') else {
+            break;
+        };
+        tags.push(&inner[..end]);
+        rest = &inner[end + 1..];
+    }
+    (tags, rest)
+}
+
+// `mm:ss`, `mm:ss.xx`, `mm:ss.xxx` and the odd `mm:ss:xx`
+fn parse_timestamp(tag: &str) -> Option<Duration> {
+    let (minutes, rest) = tag.trim().split_once(':')?;
+    let (seconds, fraction) = match rest.split_once(['.', ':']) {
+        Some((seconds, fraction)) => (seconds, fraction),
+        None => (rest, ""),
+    };
+    let minutes = minutes.parse::<u64>().ok()?;
+    let seconds = seconds.parse::<u64>().ok()?;
+    if seconds >= 60 || !fraction.chars().all(|f| f.is_ascii_digit()) {
+        return None;
+    }
+    // Hundredths are the norm, so `.5` is half a second as well
+    let fraction_ms = format!("{:0<3}", fraction).get(..3)?.parse::<u64>().ok()?;
+    Some(Duration::from_millis(
+        (minutes * 60 + seconds) * 1000 + fraction_ms,
+    ))
+}
+
+// Enhanced LRC times single words (`<00:12.30>word`), the line time is enough here
+fn strip_word_times(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        let Some(end) = rest[start..].find('>').map(|f| start + f) else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        if parse_timestamp(&rest[start + 1..end]).is_none() {
+            result.push_str(&rest[start..=end]);
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    result.trim().to_string()
+}
+
+// The `.lrc` next to the file wins over tags, it is what people edit by hand
+pub fn read_lyrics<P: AsRef<Path>>(path: P) -> Option<Lyrics> {
+    let path = path.as_ref();
+    let sidecar = path.with_extension("lrc");
+    let text = match std::fs::read(&sidecar) {
+        Ok(data) => Some(String::from_utf8_lossy(&data).to_string()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            tracing::warn!("Could not read {}: {}", sidecar.display(), e);
+            None
+        }
+    };
+    let text = text.or_else(|| {
+        embedded_lyrics(path).unwrap_or_else(|e| {
+            tracing::warn!("Could not read the lyrics of {}: {}", path.display(), e);
+            None
+        })
+    })?;
+    Some(Lyrics::parse(&text)).filter(|f| !f.is_empty())
+}
+
+// Vorbis comments use `LYRICS` or `UNSYNCEDLYRICS`, ffmpeg turns ID3 USLT frames into
+// `lyrics-<language>`
+fn embedded_lyrics(path: &Path) -> Result<Option<String>, FFMpegError> {
+    let input_context = FFMpegInput(&path)?;
+    let find = |tags: FFMpegDictionaryRef| {
+        tags.iter()
+            .find(|(key, value)| {
+                let key = key.to_lowercase();
+                (key == "lyrics" || key.starts_with("lyrics-") || key == "unsyncedlyrics")
+                    && !value.trim().is_empty()
+            })
+            .map(|(_, value)| value.to_string())
+    };
+    let stream_lyrics = || {
+        input_context
+            .streams()
+            .find_map(|stream| find(stream.metadata()))
+    };
+    Ok(find(input_context.metadata()).or_else(stream_lyrics))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Option<Duration> {
+        Some(Duration::from_millis(ms))
+    }
+
+    fn line(time: u64, text: &str) -> LyricLine {
+        LyricLine {
+            time: ms(time),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("01:02"), ms(62_000));
+        assert_eq!(parse_timestamp("01:02.50"), ms(62_500));
+        assert_eq!(parse_timestamp("01:02.5"), ms(62_500));
+        assert_eq!(parse_timestamp("01:02.345"), ms(62_345));
+        assert_eq!(parse_timestamp("01:02:34"), ms(62_340));
+        assert_eq!(parse_timestamp(" 120:00.00 "), ms(7_200_000));
+        for tag in ["01:60.00", "ar:Someone", "01:02.x", "1", "-1:00.00"] {
+            assert_eq!(parse_timestamp(tag), None, "{}", tag);
+        }
+    }
+
+    #[test]
+    fn lines_are_sorted_by_time() {
+        let lyrics = Lyrics::parse(
+            "[ar:Someone]\r\n[ti:Something]\r\n[00:20.00][00:05.00]Chorus\r\n[00:10.00]Verse <00:10.50>with <00:11.00>words\r\n[00:15.00]\r\n",
+        );
+        assert!(lyrics.synced);
+        assert_eq!(
+            lyrics.lines,
+            [
+                line(5_000, "Chorus"),
+                line(10_000, "Verse with words"),
+                line(15_000, ""),
+                line(20_000, "Chorus"),
+            ]
+        );
+        assert_eq!(lyrics.line_at(Duration::from_millis(4_999)), None);
+        assert_eq!(lyrics.line_at(Duration::from_millis(5_000)), Some(0));
+        assert_eq!(lyrics.line_at(Duration::from_secs(12)), Some(1));
+        assert_eq!(lyrics.line_at(Duration::from_secs(60)), Some(3));
+    }
+
+    #[test]
+    fn offset_shifts_every_line() {
+        // Positive shows the lines sooner, never before the start
+        let lyrics = Lyrics::parse("[offset:+500]\n[00:00.20]First\n[00:10.00]Second\n");
+        assert_eq!(lyrics.lines, [line(0, "First"), line(9_500, "Second")]);
+        // It counts wherever it shows up in the file
+        let lyrics = Lyrics::parse("[00:10.00]Line\n[offset: -250]\n");
+        assert_eq!(lyrics.lines, [line(10_250, "Line")]);
+        let lyrics = Lyrics::parse("[offset:soon]\n[00:10.00]Line\n");
+        assert_eq!(lyrics.lines, [line(10_000, "Line")]);
+    }
+
+    #[test]
+    fn plain_lyrics() {
+        let lyrics = Lyrics::parse("\n\n[Chorus]\nSome <b>line</b>\n\nAnother\n\n");
+        assert!(!lyrics.synced);
+        assert_eq!(
+            lyrics
+                .lines
+                .iter()
+                .map(|f| (f.time, f.text.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                (None, "[Chorus]"),
+                (None, "Some <b>line</b>"),
+                (None, ""),
+                (None, "Another"),
+            ]
+        );
+        assert_eq!(lyrics.line_at(Duration::from_secs(1)), None);
+        assert!(Lyrics::parse("[ar:Someone]\n\n").is_empty());
+    }
+
+    #[test]
+    fn sidecar_lrc_is_read() {
+        let dir = std::env::temp_dir().join(format!("lyrics-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("song.lrc"), "[00:01.00]Hello\n").unwrap();
+        let lyrics = read_lyrics(dir.join("song.flac")).unwrap();
+        assert_eq!(lyrics.lines, [line(1_000, "Hello")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
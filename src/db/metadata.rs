@@ -0,0 +1,459 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use ffmpeg_next::{
+    codec::{Context as FFMpegCodecContext, Id as FFMpegCodecId},
+    ffi::AV_TIME_BASE,
+    format::{input as FFMpegInput, output as FFMpegOutput},
+    media::Type as FFMpegMediaType,
+    util::{
+        dictionary::Ref as FFMpegDictionaryRef, error::Error as FFMpegError,
+        rational::Rational as FFMpegRational,
+    },
+    Dictionary as FFMpegDictionary,
+};
+
+use super::{cover::embedded_cover, AudioData};
+
+#[derive(Debug)]
+pub enum MetadataError {
+    FFMpeg(FFMpegError),
+    Io(std::io::Error),
+    // Virtual tracks share their file, tags would end up on all of them
+    VirtualTrack(PathBuf),
+}
+
+impl std::fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FFMpeg(e) => write!(f, "MetadataFFMpegError: {}", e),
+            Self::Io(e) => write!(f, "MetadataIoError: {}", e),
+            Self::VirtualTrack(path) => write!(f, "VirtualTrack: {}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for MetadataError {}
+
+impl From<FFMpegError> for MetadataError {
+    fn from(value: FFMpegError) -> Self {
+        Self::FFMpeg(value)
+    }
+}
+
+impl From<std::io::Error> for MetadataError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+// Fields left at `None` keep their value, empty ones remove the tag
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagEdit {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track: Option<String>,
+    pub genre: Option<String>,
+}
+
+impl TagEdit {
+    pub fn is_empty(&self) -> bool {
+        self.fields().iter().all(|(_, value)| value.is_none())
+    }
+    // Every key `apply_tags` reads the field from, the first one is written
+    fn fields(&self) -> [(&'static [&'static str], Option<&String>); 5] {
+        [
+            (&["title"], self.title.as_ref()),
+            (&["artist"], self.artist.as_ref()),
+            (&["album"], self.album.as_ref()),
+            (&["track", "tracknumber"], self.track.as_ref()),
+            (&["genre"], self.genre.as_ref()),
+        ]
+    }
+    fn apply(&self, tags: &FFMpegDictionaryRef) -> FFMpegDictionary<'static> {
+        let mut edited = FFMpegDictionary::new();
+        for (key, value) in self.edit(tags.iter()) {
+            edited.set(&key, &value);
+        }
+        edited
+    }
+    // The tags as they are written, in order, untouched ones first
+    fn edit<'a>(&self, tags: impl Iterator<Item = (&'a str, &'a str)>) -> Vec<(String, String)> {
+        let mut edited = Vec::new();
+        let mut track_total: Option<String> = None;
+        for (key, value) in tags {
+            let key_lower = key.to_lowercase();
+            let replaced = self
+                .fields()
+                .iter()
+                .any(|(keys, edit)| edit.is_some() && keys.contains(&key_lower.as_str()));
+            if !replaced {
+                edited.push((key.to_string(), value.to_string()));
+            } else if key_lower == "track" {
+                // `3/12`, only the number is edited
+                track_total = value.split_once('/').map(|(_, f)| f.trim().to_string());
+            }
+        }
+        for (keys, edit) in self.fields() {
+            let Some(value) = edit.map(|f| f.trim()).filter(|f| !f.is_empty()) else {
+                continue;
+            };
+            let value = match track_total {
+                Some(ref total) if keys[0] == "track" => format!("{}/{}", value, total),
+                _ => value.to_string(),
+            };
+            edited.push((keys[0].to_string(), value));
+        }
+        edited
+    }
+}
+
+// Reads tags and stream info of `path` with ffmpeg
+pub fn read_metadata<P: AsRef<Path>>(path: P) -> Result<AudioData, FFMpegError> {
+    read_metadata_and_cover(path).map(|(data, _)| data)
+}
+
+// Same as `read_metadata`, with the embedded cover picture (if any) on top
+pub fn read_metadata_and_cover<P: AsRef<Path>>(
+    path: P,
+) -> Result<(AudioData, Option<Vec<u8>>), FFMpegError> {
+    let path = path.as_ref();
+    let mut data = AudioData::new(path.to_path_buf());
+    let input_context = FFMpegInput(&path)?;
+    let stream = input_context
+        .streams()
+        .best(FFMpegMediaType::Audio)
+        .ok_or(FFMpegError::StreamNotFound)?;
+    // Containers like FLAC/MP3 tag the file, Ogg tags the stream
+    apply_tags(&mut data, &input_context.metadata());
+    apply_tags(&mut data, &stream.metadata());
+    if input_context.duration() > 0 {
+        data.duration = Some(Duration::from_secs_f64(
+            input_context.duration() as f64 / AV_TIME_BASE as f64,
+        ));
+    } else if stream.duration() > 0 {
+        data.duration = Some(Duration::from_secs_f64(
+            stream.duration() as f64 * f64::from(stream.time_base()),
+        ));
+    }
+    data.codec = stream.parameters().id().name().to_string();
+    let decoder = FFMpegCodecContext::from_parameters(stream.parameters())?
+        .decoder()
+        .audio()?;
+    data.sample_rate = Some(decoder.rate()).filter(|f| *f > 0);
+    data.bit_rate = match input_context.bit_rate() {
+        x if x > 0 => Some(x as u64),
+        _ => Some(decoder.bit_rate() as u64).filter(|f| *f > 0),
+    };
+    Ok((data, embedded_cover(&input_context)))
+}
+
+fn apply_tags(data: &mut AudioData, tags: &FFMpegDictionaryRef) {
+    for (key, value) in tags.iter() {
+        let value = value.trim();
+        if value.is_empty() {
+            continue;
+        }
+        // ffmpeg normalizes most keys, but not all muxers agree on them
+        match key.to_lowercase().as_str() {
+            "title" => data.name = value.to_string(),
+            "artist" => data.author = value.to_string(),
+            "album" => data.album = value.to_string(),
+            "album_artist" | "albumartist" | "album artist" => {
+                data.album_artist = value.to_string()
+            }
+            "track" | "tracknumber" => data.track = parse_number(value),
+            "disc" | "discnumber" => data.disc = parse_number(value),
+            "date" | "year" => data.date = value.to_string(),
+            "genre" => data.genre = value.to_string(),
+            _ => {}
+        }
+    }
+}
+
+// Track and disc numbers tend to look like `3/12`
+fn parse_number(value: &str) -> Option<u32> {
+    value.split('/').next()?.trim().parse::<u32>().ok()
+}
+
+// Remuxes `path` with the edited tags, the streams are copied as they are. The result goes
+// to a temporary file next to it first, so a failed write never leaves a broken file behind.
+pub fn write_tags<P: AsRef<Path>>(path: P, edit: &TagEdit) -> Result<(), MetadataError> {
+    let path = path.as_ref();
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    // Keeps the extension, ffmpeg picks the muxer by it
+    let temp_file =
+        TempFile(path.with_file_name(format!(".{}.tagedit.{}", file_name, extension(path))));
+    remux_tags(path, &temp_file.0, edit)?;
+    fs::set_permissions(&temp_file.0, fs::metadata(path)?.permissions())?;
+    temp_file.persist(path)
+}
+
+// Removed again unless it replaces the original, whether the remux failed or panicked halfway
+struct TempFile(PathBuf);
+
+impl TempFile {
+    // Once renamed there is nothing left to remove
+    fn persist(self, path: &Path) -> Result<(), MetadataError> {
+        fs::rename(&self.0, path)?;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn remux_tags(path: &Path, temp_path: &Path, edit: &TagEdit) -> Result<(), MetadataError> {
+    let mut input_context = FFMpegInput(&path)?;
+    let mut output_context = FFMpegOutput(&temp_path)?;
+    let audio_index = input_context
+        .streams()
+        .best(FFMpegMediaType::Audio)
+        .map(|f| f.index())
+        .ok_or(FFMpegError::StreamNotFound)?;
+    // Input index to output index, data streams the muxer might not take are dropped
+    let mut stream_mapping: Vec<Option<usize>> = Vec::new();
+    let mut time_bases: Vec<FFMpegRational> = Vec::new();
+    let mut output_index = 0;
+    for stream in input_context.streams() {
+        time_bases.push(stream.time_base());
+        let medium = stream.parameters().medium();
+        if medium != FFMpegMediaType::Audio && medium != FFMpegMediaType::Video {
+            stream_mapping.push(None);
+            continue;
+        }
+        let mut output_stream = output_context.add_stream(FFMpegCodecId::None)?;
+        output_stream.set_parameters(stream.parameters());
+        // Ogg keeps its tags on the stream, other containers ignore them there
+        if stream.index() == audio_index {
+            output_stream.set_metadata(edit.apply(&stream.metadata()));
+        } else {
+            output_stream.set_metadata(stream.metadata().to_owned());
+        }
+        // Cover art (attached pictures) needs its disposition to be muxed as such. The codec
+        // tag of the input container may mean something else in the output, the muxer picks one.
+        unsafe {
+            (*output_stream.as_mut_ptr()).disposition = stream.disposition().bits();
+            (*(*output_stream.as_mut_ptr()).codecpar).codec_tag = 0;
+        }
+        stream_mapping.push(Some(output_index));
+        output_index += 1;
+    }
+    output_context.set_metadata(edit.apply(&input_context.metadata()));
+    output_context.write_header()?;
+    for (stream, mut packet) in input_context.packets() {
+        let Some(output_index) = stream_mapping[stream.index()] else {
+            continue;
+        };
+        let output_time_base = output_context
+            .stream(output_index)
+            .ok_or(FFMpegError::StreamNotFound)?
+            .time_base();
+        packet.rescale_ts(time_bases[stream.index()], output_time_base);
+        packet.set_position(-1);
+        packet.set_stream(output_index);
+        packet.write_interleaved(&mut output_context)?;
+    }
+    output_context.write_trailer()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "music_player_metadata_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|f| f.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn edit(tags: &[(&str, &str)], edit: TagEdit) -> Vec<(String, String)> {
+        edit.edit(tags.iter().copied())
+    }
+
+    fn pairs(tags: &[(&str, &str)]) -> Vec<(String, String)> {
+        tags.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    // A tenth of a second of silence, 16 bit mono
+    fn silent_wav() -> Vec<u8> {
+        let data_size = 4410 * 2;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_size as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&44100u32.to_le_bytes());
+        wav.extend_from_slice(&(44100u32 * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(data_size as u32).to_le_bytes());
+        wav.resize(wav.len() + data_size, 0);
+        wav
+    }
+
+    #[test]
+    fn empty_edits() {
+        assert!(TagEdit::default().is_empty());
+        let tags = [("title", "Airbag"), ("TRACK", "1/12")];
+        assert_eq!(edit(&tags, TagEdit::default()), pairs(&tags));
+        let edit = TagEdit {
+            genre: Some(String::new()),
+            ..Default::default()
+        };
+        assert!(!edit.is_empty());
+    }
+
+    #[test]
+    fn track_keeps_its_total() {
+        let tags = [("title", "Airbag"), ("TRACK", "1/12"), ("date", "1997")];
+        let edited = edit(
+            &tags,
+            TagEdit {
+                track: Some(String::from(" 3 ")),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            edited,
+            pairs(&[("title", "Airbag"), ("date", "1997"), ("track", "3/12")])
+        );
+        // Without a total there is nothing to keep
+        let edited = edit(
+            &[("track", "1")],
+            TagEdit {
+                track: Some(String::from("3")),
+                ..Default::default()
+            },
+        );
+        assert_eq!(edited, pairs(&[("track", "3")]));
+    }
+
+    #[test]
+    fn edits_replace_every_spelling() {
+        // Vorbis comments use `TRACKNUMBER`, it would win over the new `track` when read back
+        let tags = [
+            ("ARTIST", "radiohead"),
+            ("TRACKNUMBER", "1"),
+            ("album", "OK"),
+        ];
+        let edited = edit(
+            &tags,
+            TagEdit {
+                artist: Some(String::from("Radiohead")),
+                track: Some(String::from("2")),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            edited,
+            pairs(&[("album", "OK"), ("artist", "Radiohead"), ("track", "2")])
+        );
+    }
+
+    #[test]
+    fn empty_fields_clear_the_tag() {
+        let tags = [("title", "Airbag"), ("genre", "Rock"), ("track", "1/12")];
+        let edited = edit(
+            &tags,
+            TagEdit {
+                genre: Some(String::from("  ")),
+                track: Some(String::new()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(edited, pairs(&[("title", "Airbag")]));
+    }
+
+    #[test]
+    fn temp_files_are_removed_unless_kept() {
+        let dir = test_dir("temp_file");
+        let temp_path = dir.join(".song.wav.tagedit.wav");
+        fs::write(&temp_path, b"half written").unwrap();
+        drop(TempFile(temp_path.clone()));
+        assert!(file_names(&dir).is_empty());
+        fs::write(dir.join("song.wav"), b"old").unwrap();
+        fs::write(&temp_path, b"new").unwrap();
+        TempFile(temp_path).persist(&dir.join("song.wav")).unwrap();
+        assert_eq!(file_names(&dir), ["song.wav"]);
+        assert_eq!(fs::read(dir.join("song.wav")).unwrap(), b"new");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_tags_round_trips() {
+        ffmpeg_next::init().unwrap();
+        let dir = test_dir("write_tags");
+        let path = dir.join("song.wav");
+        fs::write(&path, silent_wav()).unwrap();
+        let edit = TagEdit {
+            title: Some(String::from("Airbag")),
+            artist: Some(String::from("Radiohead")),
+            ..Default::default()
+        };
+        write_tags(&path, &edit).unwrap();
+        let data = read_metadata(&path).unwrap();
+        assert_eq!(data.name, "Airbag");
+        assert_eq!(data.author, "Radiohead");
+        // Cleared tags fall back like untagged files do
+        let edit = TagEdit {
+            title: Some(String::new()),
+            ..Default::default()
+        };
+        write_tags(&path, &edit).unwrap();
+        let data = read_metadata(&path).unwrap();
+        assert_eq!(data.name, "song");
+        assert_eq!(data.author, "Radiohead");
+        assert_eq!(file_names(&dir), ["song.wav"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_writes_leave_the_file_alone() {
+        ffmpeg_next::init().unwrap();
+        let dir = test_dir("write_tags_failed");
+        let path = dir.join("song.wav");
+        fs::write(&path, b"not a wav file").unwrap();
+        let edit = TagEdit {
+            title: Some(String::from("Airbag")),
+            ..Default::default()
+        };
+        assert!(write_tags(&path, &edit).is_err());
+        assert_eq!(file_names(&dir), ["song.wav"]);
+        assert_eq!(fs::read(&path).unwrap(), b"not a wav file");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,13 +1,93 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use serde::{Deserialize, Serialize};
 
 pub mod audio_scanner;
+pub mod cover;
+pub mod cue_sheet;
+pub mod history;
+pub mod library;
+pub mod lyrics;
+pub mod metadata;
+pub mod query;
+pub mod scan_root;
+pub mod search;
+pub mod watcher;
 
 #[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
 pub struct AudioData {
-    path: PathBuf,
-    name: String,
-    author: String,
-    album: String,
+    pub path: PathBuf,
+    pub name: String,
+    pub author: String,
+    pub album: String,
+    pub album_artist: String,
+    pub track: Option<u32>,
+    pub disc: Option<u32>,
+    pub date: String,
+    pub genre: String,
+    pub duration: Option<Duration>,
+    pub codec: String,
+    // In bits per second
+    pub bit_rate: Option<u64>,
+    pub sample_rate: Option<u32>,
+    // Virtual tracks (CUE sheets) only cover part of `path`
+    pub start: Option<Duration>,
+    pub end: Option<Duration>,
+    // Set by the user, 1 to 5 stars
+    #[serde(default)]
+    pub rating: Option<u8>,
+    #[serde(default)]
+    pub favorite: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl AudioData {
+    pub fn new(path: PathBuf) -> Self {
+        let name = path
+            .file_stem()
+            .and_then(|f| f.to_str())
+            .map(String::from)
+            .unwrap_or_default();
+        Self {
+            path,
+            name,
+            author: String::new(),
+            album: String::new(),
+            album_artist: String::new(),
+            track: None,
+            disc: None,
+            date: String::new(),
+            genre: String::new(),
+            duration: None,
+            codec: String::new(),
+            bit_rate: None,
+            sample_rate: None,
+            start: None,
+            end: None,
+            rating: None,
+            favorite: false,
+            tags: Vec::new(),
+        }
+    }
+    pub fn is_virtual(&self) -> bool {
+        self.start.is_some() || self.end.is_some()
+    }
+    // Dates come in all shapes (`1997`, `1997-05-21`, ...), the year is up front
+    pub fn year(&self) -> Option<i32> {
+        self.date.get(..4).and_then(|f| f.parse::<i32>().ok())
+    }
+    // Falls back to the track artist like most players do
+    pub fn album_artist(&self) -> &str {
+        if self.album_artist.is_empty() {
+            return &self.author;
+        }
+        &self.album_artist
+    }
+    pub fn display_name(&self) -> String {
+        if self.author.is_empty() {
+            return self.name.clone();
+        }
+        format!("{} - {}", self.author, self.name)
+    }
 }
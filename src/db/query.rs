@@ -0,0 +1,473 @@
+// Structured library queries, e.g. `artist:radiohead year:>1995 codec:flac duration:<300`
+//
+// Terms are ANDed together, `-` in front of a term negates it and values with spaces
+// can be quoted (`album:"ok computer"`). Bare words match title, artist and album.
+// User data can be queried too: `rating:>=4 favorite:yes tag:workout`.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    // Char offset into the query the error points at
+    pub position: usize,
+    pub message: String,
+}
+
+impl QueryError {
+    fn new(position: usize, message: String) -> Self {
+        Self { position, message }
+    }
+}
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "QueryError at {}: {}", self.position + 1, self.message)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryField {
+    Artist,
+    AlbumArtist,
+    Album,
+    Title,
+    Genre,
+    Path,
+    Codec,
+    Year,
+    // In seconds
+    Duration,
+    // In kbit/s
+    BitRate,
+    SampleRate,
+    Track,
+    Disc,
+    // 1 to 5 stars, unrated tracks never match
+    Rating,
+    // 1 or 0, `yes` and `no` work too
+    Favorite,
+    Tag,
+}
+
+impl QueryField {
+    const NAMES: &'static [(&'static str, Self)] = &[
+        ("artist", Self::Artist),
+        ("albumartist", Self::AlbumArtist),
+        ("album", Self::Album),
+        ("title", Self::Title),
+        ("genre", Self::Genre),
+        ("path", Self::Path),
+        ("codec", Self::Codec),
+        ("year", Self::Year),
+        ("duration", Self::Duration),
+        ("bitrate", Self::BitRate),
+        ("samplerate", Self::SampleRate),
+        ("track", Self::Track),
+        ("disc", Self::Disc),
+        ("rating", Self::Rating),
+        ("favorite", Self::Favorite),
+        ("tag", Self::Tag),
+    ];
+    fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(f, _)| f.eq_ignore_ascii_case(name))
+            .map(|(_, field)| *field)
+    }
+    pub fn name(&self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|(_, field)| field == self)
+            .map(|(name, _)| *name)
+            .unwrap_or_default()
+    }
+    pub fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            Self::Year
+                | Self::Duration
+                | Self::BitRate
+                | Self::SampleRate
+                | Self::Track
+                | Self::Disc
+                | Self::Rating
+                | Self::Favorite
+        )
+    }
+    fn parse_number(&self, value: &str) -> Option<i64> {
+        let value = value.trim();
+        match self {
+            // `300`, `5:00` or `1:02:03`
+            Self::Duration => value
+                .split(':')
+                .try_fold(0i64, |total, f| Some(total * 60 + f.parse::<i64>().ok()?)),
+            Self::BitRate => value.strip_suffix(['k', 'K']).unwrap_or(value).parse().ok(),
+            // `44100` or `44.1k`
+            Self::SampleRate => match value.strip_suffix(['k', 'K']) {
+                Some(khz) => khz.parse::<f64>().ok().map(|f| (f * 1000.0) as i64),
+                None => value.parse().ok(),
+            },
+            Self::Favorite => match value.to_lowercase().as_str() {
+                "yes" | "true" => Some(1),
+                "no" | "false" => Some(0),
+                _ => value.parse().ok(),
+            },
+            _ => value.parse().ok(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuerySort {
+    #[default]
+    Path,
+    Added,
+    Title,
+    Artist,
+    Album,
+    Year,
+    Duration,
+    Rating,
+    Random,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryCompare {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl QueryCompare {
+    pub fn operator(&self) -> &'static str {
+        match self {
+            Self::Less => "<",
+            Self::LessEqual => "<=",
+            Self::Greater => ">",
+            Self::GreaterEqual => ">=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryCondition {
+    // Case insensitive substring
+    Contains(String),
+    // Case insensitive, the whole value
+    Is(String),
+    Equals(i64),
+    Compare(QueryCompare, i64),
+    // Both ends included
+    Range(i64, i64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryTermKind {
+    Text(String),
+    Field(QueryField, QueryCondition),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryTerm {
+    pub negated: bool,
+    pub kind: QueryTermKind,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    pub text: String,
+    pub terms: Vec<QueryTerm>,
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Self, QueryError> {
+        let terms = tokenize(text)?
+            .into_iter()
+            .map(|(position, token)| parse_term(position, &token))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            text: text.trim().to_string(),
+            terms,
+        })
+    }
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+}
+
+// Splits on whitespace outside of quotes, keeping the char offset of every token
+fn tokenize(text: &str) -> Result<Vec<(usize, String)>, QueryError> {
+    let mut tokens: Vec<(usize, String)> = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    let mut quote_start: Option<usize> = None;
+    for (i, c) in text.chars().enumerate() {
+        match c {
+            '"' => {
+                quote_start = match quote_start {
+                    Some(_) => None,
+                    None => Some(i),
+                };
+                current.get_or_insert_with(|| (i, String::new())).1.push(c);
+            }
+            c if c.is_whitespace() && quote_start.is_none() => {
+                tokens.extend(current.take());
+            }
+            c => current.get_or_insert_with(|| (i, String::new())).1.push(c),
+        }
+    }
+    if let Some(position) = quote_start {
+        return Err(QueryError::new(
+            position,
+            String::from("missing closing quote"),
+        ));
+    }
+    tokens.extend(current);
+    Ok(tokens)
+}
+
+fn parse_term(position: usize, token: &str) -> Result<QueryTerm, QueryError> {
+    let (negated, token, position) = match token.strip_prefix('-') {
+        Some(rest) if !rest.is_empty() => (true, rest, position + 1),
+        _ => (false, token, position),
+    };
+    let Some((name, value)) = token.split_once(':') else {
+        return Ok(QueryTerm {
+            negated,
+            kind: QueryTermKind::Text(unquote(token)),
+        });
+    };
+    let Some(field) = QueryField::from_name(name) else {
+        let names: Vec<&str> = QueryField::NAMES.iter().map(|(f, _)| *f).collect();
+        return Err(QueryError::new(
+            position,
+            format!("unknown field `{}`, try one of {}", name, names.join(", ")),
+        ));
+    };
+    // Points right after the `:`
+    let value_position = position + name.chars().count() + 1;
+    if value.is_empty() {
+        return Err(QueryError::new(
+            value_position,
+            format!("`{}:` needs a value", field.name()),
+        ));
+    }
+    let condition = if field.is_numeric() {
+        parse_numeric(field, value, value_position)?
+    } else {
+        parse_text(field, value, value_position)?
+    };
+    Ok(QueryTerm {
+        negated,
+        kind: QueryTermKind::Field(field, condition),
+    })
+}
+
+fn parse_text(
+    field: QueryField,
+    value: &str,
+    position: usize,
+) -> Result<QueryCondition, QueryError> {
+    if let Some(exact) = value.strip_prefix('=') {
+        return Ok(QueryCondition::Is(unquote(exact)));
+    }
+    if value.starts_with(['<', '>']) {
+        return Err(QueryError::new(
+            position,
+            format!("`{}` is text and can't be compared", field.name()),
+        ));
+    }
+    Ok(QueryCondition::Contains(unquote(value)))
+}
+
+fn parse_numeric(
+    field: QueryField,
+    value: &str,
+    position: usize,
+) -> Result<QueryCondition, QueryError> {
+    let value = unquote(value);
+    let number = |text: &str, offset: usize| {
+        field.parse_number(text).ok_or_else(|| {
+            QueryError::new(
+                position + offset,
+                format!("`{}` is not a valid {}", text, field.name()),
+            )
+        })
+    };
+    // Longest operators first, `>=` starts with `>`
+    for (operator, compare) in [
+        (">=", QueryCompare::GreaterEqual),
+        ("<=", QueryCompare::LessEqual),
+        (">", QueryCompare::Greater),
+        ("<", QueryCompare::Less),
+    ] {
+        if let Some(rest) = value.strip_prefix(operator) {
+            return Ok(QueryCondition::Compare(
+                compare,
+                number(rest, operator.len())?,
+            ));
+        }
+    }
+    if let Some((start, end)) = value.split_once("..") {
+        let start_number = number(start, 0)?;
+        let end_number = number(end, start.chars().count() + 2)?;
+        if start_number > end_number {
+            return Err(QueryError::new(
+                position,
+                format!("range `{}` is backwards", value),
+            ));
+        }
+        return Ok(QueryCondition::Range(start_number, end_number));
+    }
+    let value = value.strip_prefix('=').unwrap_or(&value);
+    Ok(QueryCondition::Equals(number(value, 0)?))
+}
+
+fn unquote(value: &str) -> String {
+    value.replace('"', "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(field: QueryField, condition: QueryCondition) -> QueryTerm {
+        QueryTerm {
+            negated: false,
+            kind: QueryTermKind::Field(field, condition),
+        }
+    }
+
+    fn error(text: &str) -> QueryError {
+        Query::parse(text).unwrap_err()
+    }
+
+    #[test]
+    fn fields_and_bare_words() {
+        let query = Query::parse("  artist:radiohead year:>1995 -codec:flac creep  ").unwrap();
+        assert_eq!(query.text, "artist:radiohead year:>1995 -codec:flac creep");
+        assert_eq!(
+            query.terms,
+            [
+                field(
+                    QueryField::Artist,
+                    QueryCondition::Contains("radiohead".to_string())
+                ),
+                field(
+                    QueryField::Year,
+                    QueryCondition::Compare(QueryCompare::Greater, 1995)
+                ),
+                QueryTerm {
+                    negated: true,
+                    kind: QueryTermKind::Field(
+                        QueryField::Codec,
+                        QueryCondition::Contains("flac".to_string())
+                    ),
+                },
+                QueryTerm {
+                    negated: false,
+                    kind: QueryTermKind::Text("creep".to_string()),
+                },
+            ]
+        );
+        assert!(Query::parse("   ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn quotes_keep_spaces() {
+        let query = Query::parse("ALBUM:\"ok computer\" title:=\"Airbag\"").unwrap();
+        assert_eq!(
+            query.terms,
+            [
+                field(
+                    QueryField::Album,
+                    QueryCondition::Contains("ok computer".to_string())
+                ),
+                field(QueryField::Title, QueryCondition::Is("Airbag".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers_in_every_shape() {
+        let parse = |text: &str| Query::parse(text).unwrap().terms.remove(0);
+        assert_eq!(
+            parse("duration:<5:00"),
+            field(
+                QueryField::Duration,
+                QueryCondition::Compare(QueryCompare::Less, 300)
+            )
+        );
+        assert_eq!(
+            parse("duration:1:02:03"),
+            field(QueryField::Duration, QueryCondition::Equals(3723))
+        );
+        assert_eq!(
+            parse("samplerate:>=44.1k"),
+            field(
+                QueryField::SampleRate,
+                QueryCondition::Compare(QueryCompare::GreaterEqual, 44100)
+            )
+        );
+        assert_eq!(
+            parse("bitrate:<=320k"),
+            field(
+                QueryField::BitRate,
+                QueryCondition::Compare(QueryCompare::LessEqual, 320)
+            )
+        );
+        assert_eq!(
+            parse("year:1990..1999"),
+            field(QueryField::Year, QueryCondition::Range(1990, 1999))
+        );
+        assert_eq!(
+            parse("favorite:yes"),
+            field(QueryField::Favorite, QueryCondition::Equals(1))
+        );
+        assert_eq!(
+            parse("rating:=4"),
+            field(QueryField::Rating, QueryCondition::Equals(4))
+        );
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        assert_eq!(
+            error("artist:\"ok computer"),
+            QueryError::new(7, "missing closing quote".to_string())
+        );
+        let unknown = error("genre:rock -colour:red");
+        assert_eq!(unknown.position, 12);
+        assert!(unknown
+            .message
+            .starts_with("unknown field `colour`, try one of artist, albumartist"));
+        assert_eq!(
+            error("year:"),
+            QueryError::new(5, "`year:` needs a value".to_string())
+        );
+        assert_eq!(
+            error("title:>a"),
+            QueryError::new(6, "`title` is text and can't be compared".to_string())
+        );
+        assert_eq!(
+            error("year:>=abc"),
+            QueryError::new(7, "`abc` is not a valid year".to_string())
+        );
+        assert_eq!(
+            error("year:1990..x"),
+            QueryError::new(11, "`x` is not a valid year".to_string())
+        );
+        assert_eq!(
+            error("year:2000..1990"),
+            QueryError::new(5, "range `2000..1990` is backwards".to_string())
+        );
+        // Positions are shown one based
+        assert_eq!(
+            error("year:").to_string(),
+            "QueryError at 6: `year:` needs a value"
+        );
+    }
+}
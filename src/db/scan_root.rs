@@ -0,0 +1,233 @@
+use std::path::{Component, Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use crate::config::AppConfigRoot;
+
+// A library root with its filters compiled, paths are matched relative to `path`
+#[derive(Debug, Clone)]
+pub struct ScanRoot {
+    pub path: PathBuf,
+    include: Patterns,
+    exclude: Patterns,
+    max_depth: Option<usize>,
+    hidden: bool,
+}
+
+impl ScanRoot {
+    pub fn new(root: &AppConfigRoot) -> Self {
+        Self {
+            path: PathBuf::from(&root.path),
+            include: Patterns::new(&root.include),
+            exclude: Patterns::new(&root.exclude),
+            max_depth: root.max_depth,
+            hidden: root.hidden,
+        }
+    }
+    // Used for directories outside of every configured root
+    pub fn unfiltered<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            include: Patterns::default(),
+            exclude: Patterns::default(),
+            max_depth: None,
+            hidden: true,
+        }
+    }
+    pub fn contains<P: AsRef<Path>>(&self, path: P) -> bool {
+        path.as_ref().starts_with(&self.path)
+    }
+    pub fn allows_dir<P: AsRef<Path>>(&self, dir: P) -> bool {
+        let Some(relative) = self.relative(dir.as_ref()) else {
+            return false;
+        };
+        let depth = relative.components().count();
+        if depth == 0 {
+            return true;
+        }
+        self.max_depth.is_none_or(|f| depth <= f)
+            && (self.hidden || !is_hidden(relative))
+            && !self.exclude.matches(relative)
+    }
+    // Include patterns only apply to files, otherwise `*.flac` would hide every folder
+    pub fn allows_file<P: AsRef<Path>>(&self, file: P) -> bool {
+        let Some(relative) = self.relative(file.as_ref()) else {
+            return false;
+        };
+        // A file directly in the root is at depth 0
+        let depth = relative.components().count().saturating_sub(1);
+        self.max_depth.is_none_or(|f| depth <= f)
+            && (self.hidden || !is_hidden(relative))
+            && !self.exclude.matches(relative)
+            && (self.include.is_empty() || self.include.matches(relative))
+    }
+    fn relative<'a>(&self, path: &'a Path) -> Option<&'a Path> {
+        path.strip_prefix(&self.path).ok()
+    }
+}
+
+// Patterns without a `/` match any single name (like `.gitignore`), the rest the relative path
+#[derive(Debug, Clone, Default)]
+struct Patterns {
+    names: Option<GlobSet>,
+    paths: Option<GlobSet>,
+}
+
+impl Patterns {
+    fn new(patterns: &[String]) -> Self {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        let (mut name_count, mut path_count) = (0, 0);
+        for pattern in patterns {
+            let pattern = pattern.trim_end_matches('/');
+            let glob = match Glob::new(pattern) {
+                Ok(glob) => glob,
+                Err(e) => {
+                    tracing::warn!("Ignoring pattern {}: {}", pattern, e);
+                    continue;
+                }
+            };
+            if pattern.contains('/') {
+                paths.add(glob);
+                path_count += 1;
+            } else {
+                names.add(glob);
+                name_count += 1;
+            }
+        }
+        let build = |builder: GlobSetBuilder, count: usize| {
+            if count == 0 {
+                return None;
+            }
+            builder
+                .build()
+                .map_err(|e| tracing::warn!("Ignoring patterns: {}", e))
+                .ok()
+        };
+        Self {
+            names: build(names, name_count),
+            paths: build(paths, path_count),
+        }
+    }
+    fn is_empty(&self) -> bool {
+        self.names.is_none() && self.paths.is_none()
+    }
+    fn matches(&self, relative: &Path) -> bool {
+        let name_matches = self.names.as_ref().is_some_and(|names| {
+            relative.components().any(|f| match f {
+                Component::Normal(name) => names.is_match(name),
+                _ => false,
+            })
+        });
+        name_matches || self.paths.as_ref().is_some_and(|f| f.is_match(relative))
+    }
+}
+
+fn is_hidden(relative: &Path) -> bool {
+    relative.components().any(|f| match f {
+        Component::Normal(name) => name.to_str().is_some_and(|f| f.starts_with('.')),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan_root(
+        include: &[&str],
+        exclude: &[&str],
+        max_depth: Option<usize>,
+        hidden: bool,
+    ) -> ScanRoot {
+        ScanRoot::new(&AppConfigRoot {
+            path: String::from("/music"),
+            include: include.iter().map(|f| f.to_string()).collect(),
+            exclude: exclude.iter().map(|f| f.to_string()).collect(),
+            max_depth,
+            hidden,
+        })
+    }
+
+    #[test]
+    fn paths_outside_are_refused() {
+        let root = scan_root(&[], &[], None, false);
+        assert!(root.contains("/music/a.flac"));
+        assert!(!root.contains("/musical/a.flac"));
+        assert!(!root.allows_file("/other/a.flac"));
+        assert!(!root.allows_dir("/other"));
+        assert!(root.allows_dir("/music"));
+    }
+
+    #[test]
+    fn include_only_filters_files() {
+        let root = scan_root(&["*.flac", "Live/*.mp3"], &[], None, false);
+        assert!(root.allows_file("/music/Album/a.flac"));
+        assert!(root.allows_file("/music/Live/a.mp3"));
+        assert!(!root.allows_file("/music/Album/a.mp3"));
+        assert!(root.allows_dir("/music/Album"));
+    }
+
+    #[test]
+    fn exclude_names_and_paths() {
+        let root = scan_root(&[], &["Podcasts/", "Various/Samples", "*.wav"], None, false);
+        // Names match at any depth, paths only relative to the root
+        assert!(!root.allows_dir("/music/Podcasts"));
+        assert!(!root.allows_dir("/music/Old/Podcasts"));
+        assert!(!root.allows_file("/music/Old/Podcasts/a.mp3"));
+        assert!(!root.allows_dir("/music/Various/Samples"));
+        assert!(root.allows_dir("/music/Old/Various/Samples"));
+        assert!(!root.allows_file("/music/a.wav"));
+        assert!(root.allows_file("/music/a.flac"));
+    }
+
+    #[test]
+    fn exclude_wins_over_include() {
+        let root = scan_root(&["*.flac"], &["demo*"], None, false);
+        assert!(!root.allows_file("/music/demo.flac"));
+        assert!(!root.allows_file("/music/demos/a.flac"));
+    }
+
+    #[test]
+    fn depth_limit() {
+        let root = scan_root(&[], &[], Some(1), false);
+        assert!(root.allows_file("/music/a.flac"));
+        assert!(root.allows_dir("/music/Artist"));
+        assert!(root.allows_file("/music/Artist/a.flac"));
+        assert!(!root.allows_dir("/music/Artist/Album"));
+        assert!(!root.allows_file("/music/Artist/Album/a.flac"));
+        // Only the root itself
+        let root = scan_root(&[], &[], Some(0), false);
+        assert!(root.allows_file("/music/a.flac"));
+        assert!(!root.allows_dir("/music/Artist"));
+    }
+
+    #[test]
+    fn hidden_files_and_folders() {
+        let root = scan_root(&[], &[], None, false);
+        assert!(!root.allows_file("/music/.a.flac"));
+        assert!(!root.allows_dir("/music/.trash"));
+        assert!(!root.allows_file("/music/.trash/a.flac"));
+        let root = scan_root(&[], &[], None, true);
+        assert!(root.allows_file("/music/.a.flac"));
+        assert!(root.allows_dir("/music/.trash"));
+        // Only names below the root count, a hidden root is fine
+        let root = ScanRoot::new(&AppConfigRoot::new(String::from("/home/me/.music")));
+        assert!(root.allows_file("/home/me/.music/a.flac"));
+    }
+
+    #[test]
+    fn invalid_patterns_are_ignored() {
+        let root = scan_root(&["[", "*.flac"], &["{"], None, false);
+        assert!(root.allows_file("/music/a.flac"));
+        assert!(!root.allows_file("/music/a.mp3"));
+    }
+
+    #[test]
+    fn unfiltered_allows_everything_inside() {
+        let root = ScanRoot::unfiltered("/elsewhere");
+        assert!(root.allows_file("/elsewhere/.a/b/c/d.wav"));
+        assert!(root.allows_dir("/elsewhere/.a/b/c"));
+        assert!(!root.allows_file("/music/a.flac"));
+    }
+}
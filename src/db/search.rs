@@ -0,0 +1,144 @@
+// Scoring, loosely modelled after fzf
+const SCORE_MATCH: i64 = 16;
+const BONUS_CONSECUTIVE: i64 = 8;
+const BONUS_BOUNDARY: i64 = 10;
+const PENALTY_GAP: i64 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FuzzyMatch {
+    pub score: i64,
+    // Char (not byte) indices into the text
+    pub positions: Vec<usize>,
+}
+
+// Every whitespace separated term has to match somewhere in `text`
+pub fn fuzzy_match(query: &str, text: &str) -> Option<FuzzyMatch> {
+    let mut result = FuzzyMatch::default();
+    for term in query.split_whitespace() {
+        let found = match_term(term, text)?;
+        result.score += found.score;
+        result.positions.extend(found.positions);
+    }
+    result.positions.sort_unstable();
+    result.positions.dedup();
+    Some(result)
+}
+
+fn match_term(term: &str, text: &str) -> Option<FuzzyMatch> {
+    // Smart case, like most editors
+    let case_sensitive = term.chars().any(char::is_uppercase);
+    let normalize = |f: char| {
+        if case_sensitive {
+            f
+        } else {
+            f.to_lowercase().next().unwrap_or(f)
+        }
+    };
+    let pattern: Vec<char> = term.chars().map(normalize).collect();
+    let haystack: Vec<char> = text.chars().collect();
+    let lowered: Vec<char> = haystack.iter().copied().map(normalize).collect();
+    if pattern.is_empty() {
+        return Some(FuzzyMatch::default());
+    }
+    // Forward pass finds where the first full match ends
+    let mut pattern_index = 0;
+    let mut end = None;
+    for (i, c) in lowered.iter().enumerate() {
+        if *c == pattern[pattern_index] {
+            pattern_index += 1;
+            if pattern_index == pattern.len() {
+                end = Some(i);
+                break;
+            }
+        }
+    }
+    let end = end?;
+    // Backward pass from there gives the tightest window
+    let mut positions: Vec<usize> = Vec::with_capacity(pattern.len());
+    let mut pattern_index = pattern.len();
+    for i in (0..=end).rev() {
+        if lowered[i] == pattern[pattern_index - 1] {
+            positions.push(i);
+            pattern_index -= 1;
+            if pattern_index == 0 {
+                break;
+            }
+        }
+    }
+    positions.reverse();
+    let mut score = 0;
+    for (n, position) in positions.iter().enumerate() {
+        score += SCORE_MATCH;
+        if *position == 0 || !haystack[position - 1].is_alphanumeric() {
+            score += BONUS_BOUNDARY;
+        }
+        if n > 0 {
+            let gap = position - positions[n - 1] - 1;
+            if gap == 0 {
+                score += BONUS_CONSECUTIVE;
+            } else {
+                score -= PENALTY_GAP * gap as i64;
+            }
+        }
+    }
+    Some(FuzzyMatch { score, positions })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(query: &str, text: &str) -> i64 {
+        fuzzy_match(query, text).unwrap().score
+    }
+
+    #[test]
+    fn empty_queries_match_everything() {
+        assert_eq!(fuzzy_match("", "anything"), Some(FuzzyMatch::default()));
+        assert_eq!(fuzzy_match("   ", ""), Some(FuzzyMatch::default()));
+    }
+
+    #[test]
+    fn every_term_has_to_match_in_order() {
+        assert_eq!(
+            fuzzy_match("rdh", "Radiohead").unwrap().positions,
+            [0, 2, 5]
+        );
+        assert_eq!(fuzzy_match("hdr", "Radiohead"), None);
+        assert_eq!(fuzzy_match("radio xyz", "Radiohead - Airbag"), None);
+        let found = fuzzy_match("air radio", "Radiohead - Airbag").unwrap();
+        assert_eq!(found.positions, [0, 1, 2, 3, 4, 12, 13, 14]);
+        assert_eq!(
+            found.score,
+            score("air", "Radiohead - Airbag") + score("radio", "Radiohead - Airbag")
+        );
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(fuzzy_match("radio", "RADIOHEAD").is_some());
+        assert!(fuzzy_match("Radio", "radiohead").is_none());
+        assert!(fuzzy_match("Radio", "Radiohead").is_some());
+        // Folded by char, not by byte
+        assert!(fuzzy_match("ÉTÉ", "été").is_none());
+        assert_eq!(fuzzy_match("été", "L'ÉTÉ").unwrap().positions, [2, 3, 4]);
+    }
+
+    #[test]
+    fn tight_matches_at_word_starts_score_higher() {
+        // Consecutive beats spread out
+        assert!(score("air", "airbag") > score("air", "a big rain"));
+        // Word starts beat the middle of a word
+        assert!(score("bag", "air bag") > score("bag", "airbags"));
+        // Gaps cost more the longer they are
+        assert!(score("ab", "a-b") > score("ab", "a---b"));
+        assert_eq!(score("a", "a"), SCORE_MATCH + BONUS_BOUNDARY);
+        assert_eq!(score("ab", "xaxb"), 2 * SCORE_MATCH - PENALTY_GAP);
+    }
+
+    #[test]
+    fn the_tightest_window_is_highlighted() {
+        // The first `a` is skipped, the match right before `b` is closer
+        assert_eq!(fuzzy_match("ab", "a a ab").unwrap().positions, [4, 5]);
+    }
+}
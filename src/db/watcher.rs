@@ -0,0 +1,204 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+// Copying an album fires hundreds of events, wait for things to calm down
+const WATCHER_DEBOUNCE: Duration = Duration::from_secs(2);
+
+pub struct LibraryWatcher {
+    roots: Vec<PathBuf>,
+    rx: UnboundedReceiver<Vec<PathBuf>>,
+    pending: Vec<PathBuf>,
+    last_event: Option<Instant>,
+    // Dropping the watcher stops it
+    _watcher: RecommendedWatcher,
+}
+
+impl LibraryWatcher {
+    pub fn new(roots: Vec<PathBuf>) -> notify::Result<Self> {
+        let (tx, rx) = mpsc::unbounded_channel::<Vec<PathBuf>>();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            match event {
+                Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+                    // The receiver only goes away together with the watcher
+                    let _ = tx.send(event.paths);
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Library watcher error: {}", e),
+            }
+        })?;
+        // One unreachable root (e.g. an unmounted NAS) shouldnt stop the others
+        for root in roots.iter() {
+            if let Err(e) = watcher.watch(root, RecursiveMode::Recursive) {
+                tracing::warn!("Could not watch {}: {}", root.display(), e);
+            }
+        }
+        Ok(Self {
+            roots,
+            rx,
+            pending: Vec::new(),
+            last_event: None,
+            _watcher: watcher,
+        })
+    }
+    // Returns the directories to rescan once no event came in for `WATCHER_DEBOUNCE`
+    pub fn poll(&mut self) -> Option<Vec<PathBuf>> {
+        while let Ok(paths) = self.rx.try_recv() {
+            for path in paths {
+                if let Some(dir) = self.rescan_dir(&path) {
+                    self.push_pending(dir);
+                }
+            }
+            self.last_event = Some(Instant::now());
+        }
+        let last_event = self.last_event?;
+        if self.pending.is_empty() || last_event.elapsed() < WATCHER_DEBOUNCE {
+            return None;
+        }
+        self.last_event = None;
+        Some(std::mem::take(&mut self.pending))
+    }
+    // The closest directory that still exists, renames and deletions leave nothing behind
+    fn rescan_dir(&self, path: &Path) -> Option<PathBuf> {
+        let root = self.roots.iter().find(|f| path.starts_with(f))?;
+        let mut dir = path.parent()?;
+        while !dir.is_dir() && dir != root {
+            dir = dir.parent()?;
+        }
+        dir.starts_with(root).then(|| dir.to_path_buf())
+    }
+    // Scans are recursive, so only keep the outermost directories
+    fn push_pending(&mut self, dir: PathBuf) {
+        if self.pending.iter().any(|f| dir.starts_with(f)) {
+            return;
+        }
+        self.pending.retain(|f| !f.starts_with(&dir));
+        self.pending.push(dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use tokio::sync::mpsc::UnboundedSender;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "music_player_watcher_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Events are sent by hand instead of coming from the file system
+    fn watcher(roots: Vec<PathBuf>) -> (LibraryWatcher, UnboundedSender<Vec<PathBuf>>) {
+        let (tx, rx) = mpsc::unbounded_channel::<Vec<PathBuf>>();
+        let watcher = LibraryWatcher {
+            roots,
+            rx,
+            pending: Vec::new(),
+            last_event: None,
+            _watcher: notify::recommended_watcher(|_: notify::Result<Event>| {}).unwrap(),
+        };
+        (watcher, tx)
+    }
+
+    // As if the last event came in `WATCHER_DEBOUNCE` ago
+    fn settle(watcher: &mut LibraryWatcher) {
+        if let Some(ref mut last_event) = watcher.last_event {
+            *last_event -= WATCHER_DEBOUNCE;
+        }
+    }
+
+    #[test]
+    fn rescans_the_nearest_existing_dir() {
+        let root = test_dir("nearest");
+        fs::create_dir_all(root.join("Artist/Album")).unwrap();
+        let (watcher, _tx) = watcher(Vec::from([root.clone()]));
+        assert_eq!(
+            watcher.rescan_dir(&root.join("Artist/Album/01.flac")),
+            Some(root.join("Artist/Album"))
+        );
+        // The album was deleted, or renamed and this is its old name
+        fs::remove_dir_all(root.join("Artist/Album")).unwrap();
+        assert_eq!(
+            watcher.rescan_dir(&root.join("Artist/Album/01.flac")),
+            Some(root.join("Artist"))
+        );
+        fs::remove_dir_all(root.join("Artist")).unwrap();
+        assert_eq!(
+            watcher.rescan_dir(&root.join("Artist/Album/01.flac")),
+            Some(root.clone())
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn ignores_paths_outside_the_roots() {
+        let root = test_dir("outside");
+        let (watcher, _tx) = watcher(Vec::from([root.join("music")]));
+        assert_eq!(watcher.rescan_dir(&root.join("other/01.flac")), None);
+        // A root that is gone is still handed out, the scan decides what that means
+        assert_eq!(
+            watcher.rescan_dir(&root.join("music/01.flac")),
+            Some(root.join("music"))
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn waits_for_events_to_calm_down() {
+        let root = test_dir("debounce");
+        fs::create_dir_all(root.join("A")).unwrap();
+        let (mut watcher, tx) = watcher(Vec::from([root.clone()]));
+        assert_eq!(watcher.poll(), None);
+        tx.send(Vec::from([root.join("A/01.flac")])).unwrap();
+        assert_eq!(watcher.poll(), None);
+        settle(&mut watcher);
+        // Another event starts the wait over
+        tx.send(Vec::from([root.join("A/02.flac")])).unwrap();
+        assert_eq!(watcher.poll(), None);
+        settle(&mut watcher);
+        assert_eq!(watcher.poll(), Some(Vec::from([root.join("A")])));
+        // Nothing left until the next event
+        settle(&mut watcher);
+        assert_eq!(watcher.poll(), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn keeps_only_the_outermost_dirs() {
+        let root = test_dir("outermost");
+        fs::create_dir_all(root.join("A/CD1")).unwrap();
+        fs::create_dir_all(root.join("B")).unwrap();
+        let (mut watcher, tx) = watcher(Vec::from([root.clone()]));
+        tx.send(Vec::from([
+            root.join("A/CD1/01.flac"),
+            root.join("B/01.flac"),
+        ]))
+        .unwrap();
+        tx.send(Vec::from([
+            root.join("A/cover.jpg"),
+            root.join("A/CD1/02.flac"),
+        ]))
+        .unwrap();
+        // Paths outside every root dont count
+        tx.send(Vec::from([PathBuf::from("/elsewhere/01.flac")]))
+            .unwrap();
+        watcher.poll();
+        settle(&mut watcher);
+        let mut dirs = watcher.poll().unwrap();
+        dirs.sort();
+        assert_eq!(dirs, [root.join("A"), root.join("B")]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -6,7 +6,9 @@ mod config;
 mod db;
 mod event;
 mod page;
+mod playlist;
 mod run;
+mod session;
 
 #[tokio::main]
 async fn main() {
@@ -23,6 +25,6 @@ async fn main() {
     // _audio_play_test_file("./test/wangxian.opus");
     // _audio_play_test_file("./test/futari.wav");
     _audio_play_test_file("./test/futari.flac");
-    let mut audio_player: AudioPlayer<f32> = AudioPlayer::new();
+    let mut audio_player: AudioPlayer<f32> = AudioPlayer::new().unwrap();
     audio_player.play_file("./test/futari.flac");
 }
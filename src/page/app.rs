@@ -1,32 +1,144 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
 use anyhow::Ok;
 use async_trait::async_trait;
-use crossterm::event::KeyCode;
+use crossterm::event::{KeyCode, MouseButton, MouseEventKind};
 use directories::ProjectDirs;
+use futures::FutureExt;
 use ratatui::{
-    prelude::{Constraint, Layout, Rect},
+    prelude::{Constraint, Direction, Layout, Rect},
     widgets::{Block, Borders, Paragraph},
     Frame,
 };
+use tokio::{sync::mpsc::UnboundedReceiver, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
 
 const APP_QUALIFIER: &'static str = "org";
 const APP_ORGANIZATION: &'static str = "kirikmelet";
 const APP_NAME: &'static str = "music_player";
 
+// Enough for a seek bar across the widest terminal
+const ENVELOPE_BUCKETS: usize = 1024;
+// `,` and `.` jump this far
+const SEEK_STEP: f32 = 5.0;
+
 use crate::{
+    audio::{
+        envelope::Envelope,
+        player::{read_envelope, AudioPlayer},
+        queue::PlayQueue,
+    },
     config::{AppConfig, AppConfigHandler},
-    db::audio_scanner::AudioScanner,
+    db::{
+        audio_scanner::{AudioScanner, FileStamp, ScanEvent, ScanItem},
+        cover::{decode_cover, CoverImage},
+        history::{export_plays, PlayTracker},
+        library::{Library, LibraryScan},
+        lyrics::{read_lyrics, Lyrics},
+        metadata::{read_metadata, write_tags, MetadataError, TagEdit},
+        query::Query,
+        watcher::LibraryWatcher,
+        AudioData,
+    },
     event::AppEvent,
+    playlist::Playlist,
+    session::Session,
 };
 
-use super::{file_list::FileList, Msg, Page, StatefulPage};
+use super::{
+    cover_art::{CoverArt, GraphicsProtocol},
+    file_list::{FileList, FileListMsg},
+    library_browser::{LibraryBrowser, LibraryBrowserMsg},
+    lyrics_view::LyricsView,
+    now_playing::{NowPlaying, PlaybackStatus},
+    path_prompt::{PathPrompt, PathPromptMsg},
+    queue_list::{QueueList, QueueListMsg},
+    scan_progress::ScanProgressBar,
+    seek_bar::SeekBar,
+    statistics::{Statistics, StatisticsMsg},
+    tag_editor::{TagEditor, TagEditorMsg},
+    visualizer::{Visualizer, VisualizerMode},
+    Msg, Page, StatefulPage,
+};
+
+// What the path typed into the prompt is for
+enum PromptAction {
+    // Appended to the queue
+    Import,
+    Export(Playlist),
+    // Play history, `extension` is only the suggestion, the typed one picks the format
+    ExportHistory {
+        extension: &'static str,
+        // Existing file the user was already warned about
+        warned: Option<PathBuf>,
+    },
+}
+
+// Tags being written to the selected files, one after the other
+struct TagJob {
+    count: usize,
+    // Bumped by the blocking task after every file
+    done: Arc<AtomicUsize>,
+    handle: JoinHandle<Vec<(PathBuf, Result<AudioData, MetadataError>)>>,
+}
+
+struct RunningScan {
+    rx: UnboundedReceiver<ScanEvent>,
+    cancel: CancellationToken,
+    library_scan: LibraryScan,
+}
 
 pub struct App {
     state: AppState,
     // Components
     cmp_file_list: FileList,
+    cmp_library_browser: LibraryBrowser,
+    cmp_scan_progress: ScanProgressBar,
+    cmp_queue_list: QueueList,
+    cmp_statistics: Statistics,
+    cmp_tag_editor: TagEditor,
+    cmp_cover_art: CoverArt,
+    cmp_lyrics: LyricsView,
+    show_lyrics: bool,
+    cmp_visualizer: Visualizer,
+    cmp_seek_bar: SeekBar,
+    cmp_now_playing: NowPlaying,
+    cmp_path_prompt: PathPrompt,
+    // Keys go to the prompt while it is open
+    prompt_action: Option<PromptAction>,
+    // Off in the config
+    show_cover: bool,
     layout_constraints: Vec<Constraint>,
     // App Important data
     audio_scanner: AudioScanner,
+    library: Option<Library>,
+    watcher: Option<LibraryWatcher>,
+    scan: Option<RunningScan>,
+    // Query the file list is narrowed down to, rerun whenever the library changes
+    filter: Option<Query>,
+    // Directories waiting for the running scan to finish
+    scan_queue: Vec<PathBuf>,
+    // None without an audio output, the library and queue still work then
+    player: Option<AudioPlayer<f32>>,
+    // Saved again as is while there is no player to ask
+    session_volume: f32,
+    queue: PlayQueue,
+    tracker: PlayTracker,
+    // Seek bar outline being decoded, one track at a time
+    envelope_job: Option<(AudioData, JoinHandle<Option<Envelope>>)>,
+    // The file whose cover is being decoded
+    cover_job: Option<(PathBuf, JoinHandle<Option<CoverImage>>)>,
+    // The file whose lyrics are being read
+    lyrics_job: Option<(PathBuf, JoinHandle<Option<Lyrics>>)>,
+    tag_job: Option<TagJob>,
     directories: Option<ProjectDirs>,
     config: Option<AppConfigHandler>,
 }
@@ -38,6 +150,27 @@ pub enum AppMsg {
     ListIncrement,
     ListDecrement,
     RefreshDb,
+    LibraryChanged(Vec<PathBuf>),
+    PollScan,
+    CancelScan,
+    SyncQueue,
+    CycleRepeat,
+    CycleShuffle,
+    ToggleLyrics,
+    CycleVisualizer,
+    TogglePause,
+    // Relative to the start of the track
+    Seek(Duration),
+    // In seconds, negative goes back
+    SeekBy(f32),
+    // Added to the volume, which goes from 0 to 1
+    Volume(f32),
+    LibraryBrowser(LibraryBrowserMsg),
+    FileList(FileListMsg),
+    QueueList(QueueListMsg),
+    PathPrompt(PathPromptMsg),
+    Statistics(StatisticsMsg),
+    TagEditor(TagEditorMsg),
 }
 
 impl Msg for AppMsg {}
@@ -46,6 +179,10 @@ impl Msg for AppMsg {}
 pub enum AppState {
     #[default]
     Normal,
+    Library,
+    Queue,
+    Statistics,
+    TagEditor,
     DisplayHelp,
     Quit,
 }
@@ -55,22 +192,709 @@ impl App {
         Self {
             state: AppState::Normal,
             cmp_file_list: FileList::new(),
+            cmp_library_browser: LibraryBrowser::new(),
+            cmp_scan_progress: ScanProgressBar::new(),
+            cmp_queue_list: QueueList::new(),
+            cmp_statistics: Statistics::new(),
+            cmp_tag_editor: TagEditor::new(),
+            cmp_cover_art: CoverArt::new(GraphicsProtocol::detect()),
+            show_cover: true,
+            cmp_lyrics: LyricsView::new(),
+            show_lyrics: false,
+            cmp_visualizer: Visualizer::new(),
+            cmp_seek_bar: SeekBar::new(),
+            cmp_now_playing: NowPlaying::new(),
+            cmp_path_prompt: PathPrompt::new(),
+            prompt_action: None,
             layout_constraints: Vec::from([Constraint::Max(100), Constraint::Length(3)].as_ref()),
             directories: ProjectDirs::from(APP_QUALIFIER, APP_ORGANIZATION, APP_NAME),
             audio_scanner: AudioScanner::new(AppConfig::default()),
+            library: None,
+            watcher: None,
+            scan: None,
+            filter: None,
+            scan_queue: Vec::new(),
+            player: None,
+            session_volume: 1.0,
+            queue: PlayQueue::new(),
+            tracker: PlayTracker::new(),
+            envelope_job: None,
+            cover_job: None,
+            lyrics_job: None,
+            tag_job: None,
             config: None,
         }
     }
     pub async fn init(&mut self) {
+        self.player = match AudioPlayer::new() {
+            Result::Ok(player) => Some(player),
+            Err(e) => {
+                tracing::error!("Could not open the audio output: {}", e);
+                self.cmp_now_playing
+                    .set_error(Some(format!("No audio output, playback is off ({})", e)));
+                None
+            }
+        };
         if let Some(dirs) = &self.directories {
             let data_path = dirs.data_local_dir();
             self.config = AppConfigHandler::new(data_path)
                 .or_else(|_| Ok(AppConfigHandler::default()))
                 .ok();
+            self.library = match Library::open(dirs.data_dir()) {
+                Result::Ok(library) => Some(library),
+                Err(e) => {
+                    tracing::error!("Could not open the library: {}", e);
+                    None
+                }
+            };
         }
         if let Some(ref config) = self.config {
             self.audio_scanner.set_config(config.get_config().clone());
+            match GraphicsProtocol::from_config(config.get_config().cover.protocol) {
+                Some(protocol) => self.cmp_cover_art.set_protocol(protocol),
+                None => self.show_cover = false,
+            }
+            let roots = self.library_roots();
+            self.watcher = match LibraryWatcher::new(roots) {
+                Result::Ok(watcher) => Some(watcher),
+                Err(e) => {
+                    tracing::error!("Could not watch the library: {}", e);
+                    None
+                }
+            };
+        }
+        self.load_library();
+        self.restore_session();
+    }
+    fn restore_session(&mut self) {
+        let Some(ref dirs) = self.directories else {
+            return;
+        };
+        let session = match Session::read(dirs.data_dir()) {
+            Result::Ok(session) => session,
+            Err(e) => {
+                tracing::info!("No session to restore: {}", e);
+                return;
+            }
+        };
+        self.session_volume = session.volume;
+        self.queue.append(session.queue);
+        if let Some(ref mut player) = self.player {
+            player.set_volume(session.volume);
+            // Paused, nobody wants music blasting right at startup
+            if let Some(current) = session.current {
+                let position = Duration::from_millis(session.position_ms);
+                self.queue.resume(current, position, player);
+            }
+        }
+        self.queue.set_repeat(session.repeat);
+        self.queue.set_shuffle(session.shuffle, session.seed);
+        self.cmp_queue_list
+            .set_modes(self.queue.repeat(), self.queue.shuffle());
+        self.cmp_queue_list.select(session.queue_selected);
+        self.refresh_queue();
+        if let Some(ref selected) = session.selected {
+            self.cmp_file_list.select_path(selected);
+        }
+    }
+    fn save_session(&self) {
+        let Some(ref dirs) = self.directories else {
+            return;
+        };
+        let session = Session {
+            volume: self
+                .player
+                .as_ref()
+                .map_or(self.session_volume, |f| f.volume()),
+            repeat: self.queue.repeat(),
+            shuffle: self.queue.shuffle(),
+            seed: self.queue.seed(),
+            current: self.queue.current(),
+            position_ms: self.position().map_or(0, |f| f.as_millis() as u64),
+            selected: self.cmp_file_list.selected().map(|f| f.path.clone()),
+            queue_selected: self.cmp_queue_list.get_state().unwrap_or_default(),
+            queue: self.queue.tracks(),
+        };
+        if let Err(e) = session.write(dirs.data_dir()) {
+            tracing::error!("Could not save the session: {}", e);
+        }
+    }
+    fn load_library(&mut self) {
+        let Some(ref library) = self.library else {
+            return;
+        };
+        let tracks = match library.tracks() {
+            Result::Ok(tracks) => tracks,
+            Err(e) => {
+                tracing::error!("Could not read the library: {}", e);
+                return;
+            }
+        };
+        let file_list = match self.filter {
+            Some(ref filter) => library.query(filter).unwrap_or_else(|e| {
+                tracing::error!("Could not filter the library: {}", e);
+                Vec::new()
+            }),
+            None => tracks.clone(),
+        };
+        let playlists = self
+            .config
+            .iter()
+            .flat_map(|f| f.get_config().playlists.iter())
+            .filter_map(|playlist| match library.smart_playlist(playlist) {
+                Result::Ok(tracks) => Some((playlist.name.clone(), tracks)),
+                Err(e) => {
+                    tracing::error!("Smart playlist {} is broken: {}", playlist.name, e);
+                    None
+                }
+            })
+            .collect();
+        self.cmp_library_browser.set_tracks(tracks, playlists);
+        self.cmp_file_list.set_file_list(file_list);
+    }
+    fn refresh_queue(&mut self) {
+        self.cmp_queue_list
+            .set_queue(self.queue.tracks(), self.queue.current());
+    }
+    fn open_prompt(&mut self, action: PromptAction) {
+        let (title, input) = match action {
+            PromptAction::Import => (
+                String::from("Import playlist into the queue"),
+                String::new(),
+            ),
+            // The extension picks the format
+            PromptAction::Export(ref playlist) => (
+                String::from("Export playlist (.m3u8, .pls or .xspf)"),
+                format!("{}.m3u8", playlist.name.as_deref().unwrap_or("playlist")),
+            ),
+            PromptAction::ExportHistory { extension, .. } => (
+                String::from("Export play history (.csv or .json)"),
+                history_export_path(extension).display().to_string(),
+            ),
+        };
+        self.cmp_path_prompt.open(title, input);
+        self.prompt_action = Some(action);
+    }
+    // Errors keep the prompt open, the path is probably just mistyped
+    fn run_prompt_action(&mut self, path: PathBuf) {
+        let Some(mut action) = self.prompt_action.take() else {
+            return;
+        };
+        let result = match action {
+            PromptAction::Import => self.import_playlist(&path),
+            PromptAction::Export(ref playlist) => match playlist.write(&path) {
+                Result::Ok(()) => Result::Ok(format!(
+                    "Exported {} tracks to {}",
+                    playlist.entries.len(),
+                    path.display()
+                )),
+                Err(e) => Err(e.to_string()),
+            },
+            PromptAction::ExportHistory { ref mut warned, .. } => {
+                self.export_history(&path, warned)
+            }
+        };
+        match result {
+            Result::Ok(message) => self.cmp_now_playing.set_message(message),
+            Err(e) => {
+                self.cmp_path_prompt.set_error(e);
+                self.prompt_action = Some(action);
+            }
+        }
+    }
+    fn import_playlist(&mut self, path: &Path) -> Result<String, String> {
+        // Relative entries that arent next to the playlist are looked up in the first root
+        let music_dir = self.library_roots().into_iter().next();
+        let playlist = Playlist::read(path, music_dir.as_deref()).map_err(|e| e.to_string())?;
+        let known = match self.library {
+            Some(ref library) => library.tracks().map_err(|e| e.to_string())?,
+            None => Vec::new(),
+        };
+        let (tracks, missing) = playlist.tracks(&known);
+        if tracks.is_empty() {
+            return Err(format!("None of the {} entries were found", missing));
+        }
+        let mut message = format!("Imported {} tracks from {}", tracks.len(), path.display());
+        if missing > 0 {
+            message.push_str(&format!(", {} not found", missing));
+        }
+        self.queue.append(tracks);
+        self.refresh_queue();
+        Result::Ok(message)
+    }
+    // What the file list is filtered down to, rerun so it is up to date
+    fn filter_playlist(&mut self) -> Option<Playlist> {
+        let (Some(ref filter), Some(ref library)) = (&self.filter, &self.library) else {
+            return None;
+        };
+        match library.query_playlist(filter) {
+            Result::Ok(playlist) => Some(playlist),
+            Err(e) => {
+                let message = format!("Could not run {}: {}", filter.text, e);
+                self.cmp_now_playing.set_message(message);
+                None
+            }
+        }
+    }
+    // Nothing playing yet, so start with what was just added
+    fn queue_track(&mut self, track: AudioData) {
+        self.queue_tracks(Vec::from([track]));
+    }
+    fn queue_tracks(&mut self, tracks: Vec<AudioData>) {
+        let index = self.queue.tracks().len();
+        self.queue.append(tracks);
+        if !self.queue.is_playing() {
+            self.play(index);
+        }
+        self.refresh_queue();
+    }
+    fn play(&mut self, index: usize) {
+        if let Some(ref mut player) = self.player {
+            self.queue.play(index, player);
+        }
+    }
+    // Id the player handed out for what it is playing
+    fn current_id(&self) -> Option<u64> {
+        self.player.as_ref()?.current()
+    }
+    fn position(&self) -> Option<Duration> {
+        self.player.as_ref()?.position()
+    }
+    // Turns whatever came out of the speakers into play history
+    fn track_plays(&mut self) {
+        let id = self.current_id();
+        if id != self.tracker.id() {
+            self.finish_play();
+            self.load_cover();
+            self.load_lyrics();
+            self.load_envelope();
+            let track = id.and_then(|f| self.queue.track_for_id(f)).cloned();
+            self.cmp_now_playing.set_track(track);
+            let track = id.and_then(|f| Some((f, self.queue.track_for_id(f)?.clone())));
+            if let Some((id, track)) = track {
+                self.tracker.start(id, track);
+            }
+        }
+        let position = self.position();
+        self.tracker.update(position);
+        // Lyrics of CUE sheet tracks are timed against the whole file
+        let start = id
+            .and_then(|f| self.queue.track_for_id(f))
+            .and_then(|f| f.start)
+            .unwrap_or_default();
+        self.cmp_lyrics.set_position(position.map(|f| start + f));
+        self.cmp_seek_bar.set_position(position);
+        self.cmp_now_playing.set_status(PlaybackStatus {
+            position,
+            paused: self.player.as_ref().is_some_and(|f| f.is_paused()),
+            volume: self.player.as_ref().map_or(0.0, |f| f.volume()),
+            repeat: self.queue.repeat(),
+            shuffle: self.queue.shuffle(),
+        });
+    }
+    // Cached after the first play, decoding a whole track takes a moment
+    fn load_envelope(&mut self) {
+        let track = self
+            .current_id()
+            .and_then(|f| self.queue.track_for_id(f))
+            .cloned();
+        if track
+            .as_ref()
+            .is_none_or(|f| !self.cmp_seek_bar.is_track(f))
+        {
+            self.cmp_seek_bar.set_track(track.as_ref());
         }
+        // Skipping through the queue would decode every track otherwise
+        let Some(track) = track else {
+            return;
+        };
+        if self.cmp_seek_bar.has_envelope() || self.envelope_job.is_some() {
+            return;
+        }
+        let cached = self.library.as_ref().and_then(|f| {
+            f.envelope(&track).unwrap_or_else(|e| {
+                tracing::error!(
+                    "Could not read the envelope of {}: {}",
+                    track.path.display(),
+                    e
+                );
+                None
+            })
+        });
+        if let Some(envelope) = cached.and_then(|f| Envelope::from_bytes(&f)) {
+            self.cmp_seek_bar.set_envelope(Some(envelope));
+            return;
+        }
+        let job_track = track.clone();
+        let job = tokio::task::spawn_blocking(move || read_envelope(&job_track, ENVELOPE_BUCKETS));
+        self.envelope_job = Some((track, job));
+    }
+    fn poll_envelope(&mut self) {
+        if !self
+            .envelope_job
+            .as_ref()
+            .is_some_and(|(_, job)| job.is_finished())
+        {
+            return;
+        }
+        let Some((track, job)) = self.envelope_job.take() else {
+            return;
+        };
+        let envelope = job.now_or_never().and_then(|f| f.ok()).flatten();
+        if let (Some(ref envelope), Some(ref library)) = (&envelope, &self.library) {
+            if let Err(e) = library.set_envelope(&track, &envelope.to_bytes()) {
+                tracing::error!(
+                    "Could not store the envelope of {}: {}",
+                    track.path.display(),
+                    e
+                );
+            }
+        }
+        if self.cmp_seek_bar.is_track(&track) {
+            self.cmp_seek_bar.set_envelope(envelope);
+        } else {
+            // The track changed in the meantime
+            self.load_envelope();
+        }
+    }
+    // Drained every frame, even while hidden, so it starts with fresh samples
+    fn feed_visualizer(&mut self) {
+        let Some(ref mut player) = self.player else {
+            self.cmp_visualizer.silence();
+            return;
+        };
+        let sample_rate = player.sample_rate();
+        self.cmp_visualizer.push(player.tapped(), sample_rate);
+        if player.is_paused() || player.is_idle() {
+            self.cmp_visualizer.silence();
+        }
+    }
+    // Only read while the pane is open, `.lrc` files and tags can change any time
+    fn load_lyrics(&mut self) {
+        if !self.show_lyrics {
+            return;
+        }
+        let path = self
+            .current_id()
+            .and_then(|f| self.queue.track_for_id(f))
+            .map(|f| f.path.clone());
+        if path.as_deref() == self.cmp_lyrics.source() {
+            self.lyrics_job = None;
+            return;
+        }
+        if path.is_some() && path.as_ref() == self.lyrics_job.as_ref().map(|(f, _)| f) {
+            return;
+        }
+        // Reading the tags opens the file with ffmpeg, so it is done off the async threads
+        self.lyrics_job = path.map(|path| {
+            let job_path = path.clone();
+            (
+                path,
+                tokio::task::spawn_blocking(move || read_lyrics(job_path)),
+            )
+        });
+        if self.lyrics_job.is_none() {
+            self.cmp_lyrics.set_lyrics(None, None);
+        }
+    }
+    fn poll_lyrics(&mut self) {
+        if !self
+            .lyrics_job
+            .as_ref()
+            .is_some_and(|(_, job)| job.is_finished())
+        {
+            return;
+        }
+        let Some((path, job)) = self.lyrics_job.take() else {
+            return;
+        };
+        let lyrics = job.now_or_never().and_then(|f| f.ok()).flatten();
+        self.cmp_lyrics.set_lyrics(Some(path), lyrics);
+    }
+    // Tracks of an album often share a file (CUE sheets), the cover is only decoded once then
+    fn load_cover(&mut self) {
+        let path = self
+            .current_id()
+            .and_then(|f| self.queue.track_for_id(f))
+            .map(|f| f.path.clone());
+        if path.as_deref() == self.cmp_cover_art.source() {
+            self.cover_job = None;
+            return;
+        }
+        if path.is_some() && path.as_ref() == self.cover_job.as_ref().map(|(f, _)| f) {
+            return;
+        }
+        // A job still decoding the previous cover is dropped, it finishes on its own
+        self.cover_job = None;
+        let cover = match (&path, &self.library) {
+            (Some(path), Some(library)) => library.cover(path).unwrap_or_else(|e| {
+                tracing::error!("Could not read the cover of {}: {}", path.display(), e);
+                None
+            }),
+            _ => None,
+        };
+        match (path, cover) {
+            // ffmpeg blocks, so the image is scaled off the async threads
+            (Some(path), Some(cover)) => {
+                let job = tokio::task::spawn_blocking(move || match decode_cover(&cover) {
+                    Result::Ok(image) => Some(image),
+                    Err(e) => {
+                        tracing::warn!("Could not decode a cover: {}", e);
+                        None
+                    }
+                });
+                self.cover_job = Some((path, job));
+            }
+            (path, _) => self.cmp_cover_art.set_cover(path, None),
+        }
+    }
+    fn poll_cover(&mut self) {
+        if !self
+            .cover_job
+            .as_ref()
+            .is_some_and(|(_, job)| job.is_finished())
+        {
+            return;
+        }
+        let Some((path, job)) = self.cover_job.take() else {
+            return;
+        };
+        let image = job.now_or_never().and_then(|f| f.ok()).flatten();
+        self.cmp_cover_art.set_cover(Some(path), image);
+    }
+    // Sixel images only go away with the whole screen
+    pub fn take_clear(&mut self) -> bool {
+        self.cmp_cover_art.take_clear()
+    }
+    // Kitty and sixel images are written around ratatui, right after the frame
+    pub fn draw_graphics<W: Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        self.cmp_cover_art.draw_graphics(writer)
+    }
+    fn finish_play(&mut self) {
+        let (Some(play), Some(ref library)) = (self.tracker.finish(), &self.library) else {
+            return;
+        };
+        if let Err(e) = library.record_play(&play) {
+            tracing::error!("Could not record a play: {}", e);
+        }
+        if self.state == AppState::Statistics {
+            self.load_stats();
+        }
+    }
+    fn load_stats(&mut self) {
+        let Some(ref library) = self.library else {
+            return;
+        };
+        match library.history_stats(20) {
+            Result::Ok(stats) => self.cmp_statistics.set_stats(stats),
+            Err(e) => tracing::error!("Could not read the play history: {}", e),
+        }
+    }
+    // Asks once before replacing a file, submitting the same path again overwrites it
+    fn export_history(
+        &mut self,
+        path: &Path,
+        warned: &mut Option<PathBuf>,
+    ) -> Result<String, String> {
+        let Some(ref library) = self.library else {
+            return Err(String::from("No library to export from"));
+        };
+        if path.exists() && warned.as_deref() != Some(path) {
+            *warned = Some(path.to_path_buf());
+            return Err(format!(
+                "{} already exists, submit again to overwrite",
+                path.display()
+            ));
+        }
+        let plays = library.plays().map_err(|e| e.to_string())?;
+        export_plays(&plays, path).map_err(|e| e.to_string())?;
+        let message = format!("exported {} plays to {}", plays.len(), path.display());
+        self.cmp_statistics.set_status(message.clone());
+        Result::Ok(message)
+    }
+    // ffmpeg blocks, so the files are remuxed off the async threads, see `poll_tag_job`
+    fn write_tags(&mut self, tracks: Vec<AudioData>, edit: TagEdit) {
+        if self.tag_job.is_some() {
+            return;
+        }
+        let count = tracks.len();
+        let done = Arc::new(AtomicUsize::new(0));
+        let job_done = done.clone();
+        let handle = tokio::task::spawn_blocking(move || {
+            tracks
+                .into_iter()
+                .map(|track| {
+                    let result = if track.is_virtual() {
+                        Err(MetadataError::VirtualTrack(track.path.clone()))
+                    } else {
+                        write_tags(&track.path, &edit)
+                            .and_then(|()| read_metadata(&track.path).map_err(MetadataError::from))
+                    };
+                    job_done.fetch_add(1, Ordering::Relaxed);
+                    (track.path, result)
+                })
+                .collect::<Vec<_>>()
+        });
+        self.cmp_tag_editor.set_progress(Some((0, count)));
+        self.tag_job = Some(TagJob {
+            count,
+            done,
+            handle,
+        });
+    }
+    fn poll_tag_job(&mut self) {
+        let Some(ref job) = self.tag_job else {
+            return;
+        };
+        if !job.handle.is_finished() {
+            let done = job.done.load(Ordering::Relaxed);
+            self.cmp_tag_editor.set_progress(Some((done, job.count)));
+            return;
+        }
+        let Some(job) = self.tag_job.take() else {
+            return;
+        };
+        self.cmp_tag_editor.set_progress(None);
+        let count = job.count;
+        let written = job
+            .handle
+            .now_or_never()
+            .and_then(|f| f.ok())
+            .unwrap_or_default();
+        let mut failed = count - written.len();
+        for (path, result) in written {
+            let data = match result {
+                Result::Ok(data) => data,
+                Err(e) => {
+                    tracing::error!("Could not write tags to {}: {}", path.display(), e);
+                    failed += 1;
+                    continue;
+                }
+            };
+            // Same as a rescan of the file would do, the watcher then finds nothing new
+            let (Some(ref mut library), Some(stamp)) = (&mut self.library, FileStamp::read(&path))
+            else {
+                continue;
+            };
+            if let Err(e) = library.replace_file(&path, stamp, &[data]) {
+                tracing::error!("Could not update {}: {}", path.display(), e);
+            }
+        }
+        self.load_library();
+        if failed > 0 {
+            let error = format!("Could not write {} of {} files, see the log", failed, count);
+            self.cmp_tag_editor.set_error(error.clone());
+            self.cmp_now_playing.set_message(error);
+        } else {
+            self.cmp_now_playing
+                .set_message(format!("Wrote tags to {} files", count));
+            // Unless it was left in the meantime
+            if self.state == AppState::TagEditor {
+                self.state = AppState::Normal;
+            }
+        }
+    }
+    fn library_roots(&self) -> Vec<PathBuf> {
+        self.audio_scanner
+            .roots()
+            .iter()
+            .map(|f| f.path.clone())
+            .collect()
+    }
+    // Scans are recursive, so a queued parent covers its children
+    fn queue_scan(&mut self, dir: PathBuf) {
+        if self.scan_queue.iter().any(|f| dir.starts_with(f)) {
+            return;
+        }
+        self.scan_queue.retain(|f| !f.starts_with(&dir));
+        self.scan_queue.push(dir);
+        self.start_scan();
+    }
+    fn start_scan(&mut self) {
+        if self.scan.is_some() || self.scan_queue.is_empty() {
+            return;
+        }
+        let Some(ref library) = self.library else {
+            return;
+        };
+        let known = match library.file_stamps() {
+            Result::Ok(known) => known,
+            Err(e) => {
+                tracing::error!("Could not read the library: {}", e);
+                return;
+            }
+        };
+        let root = self.scan_queue.remove(0);
+        let cancel = CancellationToken::new();
+        let rx = self.audio_scanner.spawn_scan(&root, known, cancel.clone());
+        self.cmp_scan_progress.set_progress(Default::default());
+        self.scan = Some(RunningScan {
+            rx,
+            cancel,
+            library_scan: LibraryScan::new(root),
+        });
+    }
+    fn poll_scan(&mut self) {
+        let (Some(ref mut scan), Some(ref mut library)) = (&mut self.scan, &mut self.library)
+        else {
+            return;
+        };
+        let mut items: Vec<ScanItem> = Vec::new();
+        let mut finished: Option<bool> = None;
+        while let Result::Ok(event) = scan.rx.try_recv() {
+            match event {
+                ScanEvent::Progress(progress) => self.cmp_scan_progress.set_progress(progress),
+                ScanEvent::Item(item) => items.push(item),
+                ScanEvent::Finished { cancelled, .. } => finished = Some(cancelled),
+            }
+        }
+        if let Err(e) = library.apply_scan_items(&mut scan.library_scan, items) {
+            tracing::error!("Could not store scan results: {}", e);
+        }
+        let Some(cancelled) = finished else {
+            return;
+        };
+        // Finished, there is no more use for the scan
+        let Some(scan) = self.scan.take() else {
+            return;
+        };
+        let root = scan.library_scan.root.clone();
+        match library.finish_scan(scan.library_scan, cancelled) {
+            Result::Ok(update) => {
+                tracing::info!("Library rescanned: {:?}", update);
+                // Its tracks are kept, but it shouldnt look like the scan went fine
+                if update.root_unreadable(&root) {
+                    self.cmp_now_playing.set_message(format!(
+                        "Could not read {}, its tracks were kept",
+                        root.display()
+                    ));
+                } else if !update.unreadable.is_empty() {
+                    self.cmp_now_playing.set_message(format!(
+                        "Could not read {} directories below {}, their tracks were kept",
+                        update.unreadable.len(),
+                        root.display()
+                    ));
+                }
+            }
+            Err(e) => tracing::error!("Could not rescan the library: {}", e),
+        }
+        self.load_library();
+        self.start_scan();
+    }
+}
+
+impl App {
+    // Queue, with the lyrics on top of it if they are shown
+    fn render_side(&mut self, frame: &mut Frame, rect: Rect) {
+        if !self.show_lyrics {
+            self.cmp_queue_list.render(frame, rect);
+            return;
+        }
+        let side = Layout::default()
+            .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
+            .split(rect);
+        self.cmp_lyrics.render(frame, side[0]);
+        self.cmp_queue_list.render(frame, side[1]);
     }
 }
 
@@ -80,14 +904,79 @@ impl Page for App {
         // Display help
         let display_help = Paragraph::new("Display help").block(Block::default());
         if self.get_state() == AppState::DisplayHelp {
+            self.cmp_cover_art.hide();
+            self.cmp_seek_bar.hide();
             frame.render_widget(display_help, rect);
             return;
         }
         let _block = Block::default().borders(Borders::ALL).title("title");
+        let mut constraints = self.layout_constraints.clone();
+        let show_seek_bar = self.cmp_seek_bar.has_track();
+        // Right above the now playing bar, two rows as a single one is too flat for the outline.
+        // A running scan goes on top of it, so the track can still be followed while scanning.
+        if show_seek_bar {
+            constraints.insert(1, Constraint::Length(4));
+        }
+        if self.scan.is_some() {
+            constraints.insert(1, Constraint::Length(3));
+        }
         let layout = Layout::default()
-            .constraints(self.layout_constraints.as_slice())
+            .constraints(constraints.as_slice())
             .split(rect);
-        self.cmp_file_list.render(frame, layout[0]);
+        let mut main = layout[0];
+        if self.cmp_visualizer.mode() != VisualizerMode::Off {
+            let rows = Layout::default()
+                .constraints([Constraint::Min(0), Constraint::Length(10)].as_ref())
+                .split(main);
+            main = rows[0];
+            self.cmp_visualizer.render(frame, rows[1]);
+        }
+        if self.prompt_action.is_some() {
+            let rows = Layout::default()
+                .constraints(
+                    [
+                        Constraint::Min(0),
+                        Constraint::Length(self.cmp_path_prompt.height()),
+                    ]
+                    .as_ref(),
+                )
+                .split(main);
+            main = rows[0];
+            self.cmp_path_prompt.render(frame, rows[1]);
+        }
+        let panes = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(65), Constraint::Percentage(35)].as_ref())
+            .split(main);
+        match self.get_state() {
+            AppState::Statistics => self.cmp_statistics.render(frame, panes[0]),
+            AppState::Library => self.cmp_library_browser.render(frame, panes[0]),
+            AppState::TagEditor => self.cmp_tag_editor.render(frame, panes[0]),
+            _ => self.cmp_file_list.render(frame, panes[0]),
+        }
+        if self.show_cover && self.cmp_cover_art.has_cover() {
+            // About square, the rest stays for the queue
+            let height = (panes[1].width / 2 + 2).min(panes[1].height / 2);
+            let side = Layout::default()
+                .constraints([Constraint::Length(height), Constraint::Min(0)].as_ref())
+                .split(panes[1]);
+            self.cmp_cover_art.render(frame, side[0]);
+            self.render_side(frame, side[1]);
+        } else {
+            self.cmp_cover_art.hide();
+            self.render_side(frame, panes[1]);
+        }
+        let mut row = 1;
+        if self.scan.is_some() {
+            self.cmp_scan_progress.render(frame, layout[row]);
+            row += 1;
+        }
+        if show_seek_bar {
+            self.cmp_seek_bar.render(frame, layout[row]);
+        } else {
+            self.cmp_seek_bar.hide();
+        }
+        self.cmp_now_playing.render(frame, layout[layout.len() - 1]);
     }
 }
 
@@ -99,6 +988,10 @@ impl StatefulPage for App {
         match msg {
             AppMsg::State(state) => {
                 self.state = state;
+                self.cmp_queue_list.set_focused(state == AppState::Queue);
+                if state == AppState::Statistics {
+                    self.load_stats();
+                }
             }
             AppMsg::ListDecrement => {
                 self.cmp_file_list.prev();
@@ -107,17 +1000,249 @@ impl StatefulPage for App {
                 self.cmp_file_list.next();
             }
             AppMsg::RefreshDb => {
-                let Some(ref audio_config) = self.config else {
+                let roots = self.library_roots();
+                if let Some(ref mut library) = self.library {
+                    match library.remove_outside(&roots) {
+                        Result::Ok(removed) if removed > 0 => {
+                            tracing::info!("Removed {} files outside the library roots", removed);
+                            self.load_library();
+                        }
+                        Result::Ok(_) => {}
+                        Err(e) => tracing::error!("Could not clean up the library: {}", e),
+                    }
+                }
+                for root in roots {
+                    self.queue_scan(root);
+                }
+            }
+            AppMsg::LibraryChanged(dirs) => {
+                // Same incremental pipeline as `R`, just limited to what changed
+                for dir in dirs {
+                    self.queue_scan(dir);
+                }
+            }
+            AppMsg::PollScan => {
+                self.poll_scan();
+                return Some(AppMsg::SyncQueue);
+            }
+            AppMsg::CycleRepeat => {
+                self.queue.set_repeat(self.queue.repeat().next());
+                self.cmp_queue_list
+                    .set_modes(self.queue.repeat(), self.queue.shuffle());
+            }
+            AppMsg::CycleShuffle => {
+                // A fresh order every time shuffle is switched on
+                let seed = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|f| f.as_nanos() as u64)
+                    .unwrap_or_default();
+                self.queue.set_shuffle(self.queue.shuffle().next(), seed);
+                self.cmp_queue_list
+                    .set_modes(self.queue.repeat(), self.queue.shuffle());
+            }
+            AppMsg::SyncQueue => {
+                if let Some(ref mut player) = self.player {
+                    if self.queue.sync(player) {
+                        self.cmp_queue_list.set_current(self.queue.current());
+                    }
+                }
+                self.track_plays();
+                self.poll_envelope();
+                self.poll_cover();
+                self.poll_lyrics();
+                self.poll_tag_job();
+                self.feed_visualizer();
+            }
+            AppMsg::FileList(FileListMsg::RunFilter(query)) => {
+                self.filter = (!query.is_empty()).then_some(query);
+                let text = self.filter.as_ref().map(|f| f.text.clone());
+                self.cmp_file_list.set_filter(text);
+                self.load_library();
+            }
+            AppMsg::FileList(FileListMsg::Queue(track)) => self.queue_track(*track),
+            AppMsg::FileList(FileListMsg::QueueNext(track)) => {
+                self.queue.insert_next(Vec::from([*track]));
+                self.refresh_queue();
+            }
+            AppMsg::FileList(FileListMsg::SaveUserData(track)) => {
+                let saved = self.library.as_mut().map(|f| f.set_user_data(&track));
+                match saved {
+                    Some(Result::Ok(())) => self.cmp_file_list.replace_track(*track),
+                    Some(Err(e)) => {
+                        tracing::error!("Could not save {}: {}", track.path.display(), e)
+                    }
+                    None => {}
+                }
+            }
+            AppMsg::FileList(FileListMsg::QueueFilter) => {
+                let (Some(ref filter), Some(ref library)) = (&self.filter, &self.library) else {
                     return None;
                 };
-                let music_dir = audio_config.get_config().dir.music_dir.clone();
-                self.cmp_file_list.update_file_list(music_dir).await;
+                let tracks = match library.query(filter) {
+                    Result::Ok(tracks) => tracks,
+                    Err(e) => {
+                        let message = format!("Could not run {}: {}", filter.text, e);
+                        self.cmp_now_playing.set_message(message);
+                        return None;
+                    }
+                };
+                self.queue_tracks(tracks);
+            }
+            AppMsg::FileList(FileListMsg::ExportFilter) => {
+                let playlist = self.filter_playlist()?;
+                self.open_prompt(PromptAction::Export(playlist));
+            }
+            AppMsg::FileList(FileListMsg::EditTags(tracks)) => {
+                self.cmp_tag_editor.open(tracks);
+                self.state = AppState::TagEditor;
+            }
+            AppMsg::FileList(msg) => {
+                return self.cmp_file_list.update(msg).await.map(AppMsg::FileList);
+            }
+            AppMsg::LibraryBrowser(LibraryBrowserMsg::Export(playlist)) => {
+                self.open_prompt(PromptAction::Export(playlist));
+            }
+            AppMsg::LibraryBrowser(msg) => {
+                return self
+                    .cmp_library_browser
+                    .update(msg)
+                    .await
+                    .map(AppMsg::LibraryBrowser);
+            }
+            AppMsg::QueueList(QueueListMsg::Play(index)) => {
+                self.play(index);
+                self.refresh_queue();
+            }
+            AppMsg::QueueList(QueueListMsg::Remove(index)) => {
+                self.queue.remove(index, self.player.as_mut());
+                self.refresh_queue();
+            }
+            AppMsg::QueueList(QueueListMsg::Move(from, to)) => {
+                self.queue.move_entry(from, to);
+                self.refresh_queue();
+                // Keep the cursor on the track being moved
+                self.cmp_queue_list.select(to);
+            }
+            AppMsg::QueueList(QueueListMsg::Clear) => {
+                self.queue.clear(self.player.as_mut());
+                self.refresh_queue();
+            }
+            AppMsg::QueueList(QueueListMsg::Import) => self.open_prompt(PromptAction::Import),
+            AppMsg::QueueList(QueueListMsg::Export) => {
+                let playlist =
+                    Playlist::from_tracks(Some(String::from("queue")), &self.queue.tracks());
+                self.open_prompt(PromptAction::Export(playlist));
+            }
+            AppMsg::QueueList(msg) => {
+                return self.cmp_queue_list.update(msg).await.map(AppMsg::QueueList);
+            }
+            AppMsg::PathPrompt(PathPromptMsg::Submit(path)) => self.run_prompt_action(path),
+            AppMsg::PathPrompt(PathPromptMsg::Cancel) => self.prompt_action = None,
+            AppMsg::PathPrompt(msg) => {
+                return self
+                    .cmp_path_prompt
+                    .update(msg)
+                    .await
+                    .map(AppMsg::PathPrompt);
+            }
+            AppMsg::TagEditor(TagEditorMsg::Save(tracks, edit)) => self.write_tags(tracks, *edit),
+            AppMsg::TagEditor(TagEditorMsg::Cancel) => self.state = AppState::Normal,
+            AppMsg::TagEditor(msg) => {
+                return self.cmp_tag_editor.update(msg).await.map(AppMsg::TagEditor);
+            }
+            AppMsg::Statistics(StatisticsMsg::ExportCsv) => {
+                self.open_prompt(PromptAction::ExportHistory {
+                    extension: "csv",
+                    warned: None,
+                })
+            }
+            AppMsg::Statistics(StatisticsMsg::ExportJson) => {
+                self.open_prompt(PromptAction::ExportHistory {
+                    extension: "json",
+                    warned: None,
+                })
+            }
+            AppMsg::CancelScan => {
+                self.scan_queue.clear();
+                if let Some(ref scan) = self.scan {
+                    scan.cancel.cancel();
+                }
+            }
+            AppMsg::ToggleLyrics => {
+                self.show_lyrics = !self.show_lyrics;
+                if !self.show_lyrics {
+                    self.lyrics_job = None;
+                    self.cmp_lyrics.set_lyrics(None, None);
+                }
+                self.load_lyrics();
+            }
+            AppMsg::CycleVisualizer => {
+                self.cmp_visualizer
+                    .set_mode(self.cmp_visualizer.mode().next());
+            }
+            AppMsg::TogglePause => {
+                let player = self.player.as_mut()?;
+                if player.is_paused() {
+                    player.resume();
+                } else {
+                    player.pause();
+                }
+            }
+            AppMsg::Seek(position) => self.player.as_mut()?.seek(position),
+            AppMsg::SeekBy(seconds) => {
+                let position = self.position()?;
+                let target = (position.as_secs_f32() + seconds).max(0.0);
+                return Some(AppMsg::Seek(Duration::from_secs_f32(target)));
+            }
+            AppMsg::Volume(change) => {
+                let player = self.player.as_mut()?;
+                player.set_volume(player.volume() + change);
+            }
+            AppMsg::Quit => {
+                self.tracker.update(self.position());
+                self.finish_play();
+                self.save_session();
+                self.state = AppState::Quit;
             }
-            AppMsg::Quit => self.state = AppState::Quit,
         }
         None
     }
     async fn handle_events(&mut self, event: AppEvent) -> Option<Self::Message> {
+        if self.prompt_action.is_some() && matches!(event, AppEvent::Key(_)) {
+            return self
+                .cmp_path_prompt
+                .handle_events(event)
+                .await
+                .map(AppMsg::PathPrompt);
+        }
+        match self.get_state() {
+            AppState::Normal => {
+                if let Some(msg) = self.cmp_file_list.handle_events(event.clone()).await {
+                    return Some(AppMsg::FileList(msg));
+                }
+            }
+            AppState::Library => {
+                if let Some(msg) = self.cmp_library_browser.handle_events(event.clone()).await {
+                    return Some(AppMsg::LibraryBrowser(msg));
+                }
+            }
+            AppState::Queue => {
+                if let Some(msg) = self.cmp_queue_list.handle_events(event.clone()).await {
+                    return Some(AppMsg::QueueList(msg));
+                }
+            }
+            AppState::TagEditor => {
+                if let Some(msg) = self.cmp_tag_editor.handle_events(event.clone()).await {
+                    return Some(AppMsg::TagEditor(msg));
+                }
+            }
+            AppState::Statistics => {
+                if let Some(msg) = self.cmp_statistics.handle_events(event.clone()).await {
+                    return Some(AppMsg::Statistics(msg));
+                }
+            }
+            _ => {}
+        }
         match event {
             AppEvent::Key(x) => match x {
                 KeyCode::Char('q') => Some(AppMsg::Quit),
@@ -127,11 +1252,35 @@ impl StatefulPage for App {
                     }
                     Some(AppMsg::State(AppState::DisplayHelp))
                 }
+                KeyCode::Char('1') => Some(AppMsg::State(AppState::Normal)),
+                KeyCode::Char('2') => Some(AppMsg::State(AppState::Library)),
+                KeyCode::Char('3') => Some(AppMsg::State(AppState::Queue)),
+                KeyCode::Char('4') => Some(AppMsg::State(AppState::Statistics)),
                 KeyCode::Char('j') => Some(AppMsg::ListIncrement),
                 KeyCode::Char('k') => Some(AppMsg::ListDecrement),
                 KeyCode::Char('R') => Some(AppMsg::RefreshDb),
+                KeyCode::Char('r') => Some(AppMsg::CycleRepeat),
+                KeyCode::Char('s') => Some(AppMsg::CycleShuffle),
+                KeyCode::Char('L') => Some(AppMsg::ToggleLyrics),
+                KeyCode::Char('v') => Some(AppMsg::CycleVisualizer),
+                KeyCode::Char(' ') => Some(AppMsg::TogglePause),
+                KeyCode::Char('+') | KeyCode::Char('=') => Some(AppMsg::Volume(0.05)),
+                KeyCode::Char('-') => Some(AppMsg::Volume(-0.05)),
+                KeyCode::Char(',') => Some(AppMsg::SeekBy(-SEEK_STEP)),
+                KeyCode::Char('.') => Some(AppMsg::SeekBy(SEEK_STEP)),
+                KeyCode::Esc if self.scan.is_some() => Some(AppMsg::CancelScan),
                 _ => None,
             },
+            AppEvent::Render if self.scan.is_some() => Some(AppMsg::PollScan),
+            AppEvent::Render => Some(AppMsg::SyncQueue),
+            AppEvent::Tick => self
+                .watcher
+                .as_mut()
+                .and_then(|f| f.poll())
+                .map(AppMsg::LibraryChanged),
+            AppEvent::Mouse(x) if x.kind == MouseEventKind::Down(MouseButton::Left) => {
+                self.cmp_seek_bar.target(x.column, x.row).map(AppMsg::Seek)
+            }
             AppEvent::Error => Some(AppMsg::Quit),
             _ => None,
         }
@@ -140,3 +1289,14 @@ impl StatefulPage for App {
         self.state
     }
 }
+
+// Next to the users documents rather than wherever the player was started from
+fn history_export_path(extension: &str) -> PathBuf {
+    let dirs = directories::UserDirs::new();
+    let dir = dirs
+        .as_ref()
+        .and_then(|f| f.document_dir().map(Path::to_path_buf))
+        .or_else(|| dirs.as_ref().map(|f| f.home_dir().to_path_buf()))
+        .unwrap_or_default();
+    dir.join(format!("history.{}", extension))
+}
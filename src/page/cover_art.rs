@@ -0,0 +1,343 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use crossterm::{cursor::MoveTo, QueueableCommand};
+use ratatui::{
+    prelude::Rect,
+    style::Color,
+    widgets::{Block, Borders},
+    Frame,
+};
+
+use crate::{config::AppConfigCoverProtocol, db::cover::CoverImage};
+
+use super::Page;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphicsProtocol {
+    // Two pixels per cell, `▀` with the upper one as foreground and the lower one as background
+    HalfBlocks,
+    Kitty,
+    Sixel,
+}
+
+impl GraphicsProtocol {
+    // `None` hides the cover pane
+    pub fn from_config(protocol: AppConfigCoverProtocol) -> Option<Self> {
+        match protocol {
+            AppConfigCoverProtocol::Auto => Some(Self::detect()),
+            AppConfigCoverProtocol::HalfBlocks => Some(Self::HalfBlocks),
+            AppConfigCoverProtocol::Kitty => Some(Self::Kitty),
+            AppConfigCoverProtocol::Sixel => Some(Self::Sixel),
+            AppConfigCoverProtocol::Off => None,
+        }
+    }
+    // Asking the terminal means reading its answer in between key presses, the environment
+    // tells enough. Multiplexers swallow the escape codes, half blocks always work.
+    pub fn detect() -> Self {
+        let var = |name: &str| std::env::var(name).unwrap_or_default();
+        let (term, program) = (var("TERM"), var("TERM_PROGRAM"));
+        if !var("TMUX").is_empty() || term.starts_with("screen") {
+            return Self::HalfBlocks;
+        }
+        if !var("KITTY_WINDOW_ID").is_empty()
+            || term == "xterm-kitty"
+            || term == "xterm-ghostty"
+            || program == "WezTerm"
+            || program == "ghostty"
+        {
+            return Self::Kitty;
+        }
+        // Sixel images are sized in pixels, some terminals dont say how large their cells are
+        let sixel_terms = ["foot", "mlterm", "yaft", "contour"];
+        let sixel = sixel_terms.iter().any(|f| term.starts_with(f)) || program == "iTerm.app";
+        if sixel && cell_size().is_some() {
+            return Self::Sixel;
+        }
+        Self::HalfBlocks
+    }
+}
+
+#[derive(Debug)]
+pub struct CoverArt {
+    protocol: GraphicsProtocol,
+    image: Option<CoverImage>,
+    // The file the cover belongs to
+    source: Option<PathBuf>,
+    // Half blocks scaled to the cells they were last rendered into
+    scaled: Option<(Rect, CoverImage)>,
+    // Kitty and sixel images go around ratatui, they are written after every frame they changed
+    area: Option<Rect>,
+    drawn: Option<Rect>,
+    dirty: bool,
+    // Sixel pixels stay on screen until the screen is cleared
+    clear: bool,
+}
+
+impl CoverArt {
+    pub fn new(protocol: GraphicsProtocol) -> Self {
+        Self {
+            protocol,
+            image: None,
+            source: None,
+            scaled: None,
+            area: None,
+            drawn: None,
+            dirty: false,
+            clear: false,
+        }
+    }
+    pub fn set_protocol(&mut self, protocol: GraphicsProtocol) {
+        self.protocol = protocol;
+        self.scaled = None;
+        self.dirty = true;
+    }
+    pub fn set_cover(&mut self, source: Option<PathBuf>, image: Option<CoverImage>) {
+        self.source = source;
+        self.image = image;
+        self.scaled = None;
+        self.area = None;
+        self.dirty = true;
+    }
+    // Not rendered this frame, whatever image is on screen has to go
+    pub fn hide(&mut self) {
+        self.area = None;
+    }
+    pub fn source(&self) -> Option<&Path> {
+        self.source.as_deref()
+    }
+    pub fn has_cover(&self) -> bool {
+        self.image.is_some()
+    }
+    // Once, right before the next frame is drawn
+    pub fn take_clear(&mut self) -> bool {
+        std::mem::take(&mut self.clear)
+    }
+    // Cells the cover takes up inside `rect`, centered and as large as it fits. Cells are
+    // about twice as high as they are wide.
+    fn fit(image: &CoverImage, rect: Rect) -> Rect {
+        let scale = (rect.width as f64 / image.width as f64)
+            .min(rect.height as f64 * 2.0 / image.height as f64);
+        let width = ((image.width as f64 * scale) as u16).clamp(1, rect.width);
+        let height = ((image.height as f64 * scale / 2.0).ceil() as u16).clamp(1, rect.height);
+        Rect::new(rect.x + (rect.width - width) / 2, rect.y, width, height)
+    }
+    fn render_half_blocks(&mut self, frame: &mut Frame, area: Rect) {
+        let Some(ref image) = self.image else {
+            return;
+        };
+        let scaled = match self.scaled {
+            Some((scaled_area, ref scaled)) if scaled_area == area => scaled,
+            _ => {
+                let scaled = image.resized(area.width as u32, area.height as u32 * 2);
+                &self.scaled.insert((area, scaled)).1
+            }
+        };
+        let buffer = frame.buffer_mut();
+        for y in 0..area.height {
+            for x in 0..area.width {
+                let (top_r, top_g, top_b) = scaled.pixel(x as u32, y as u32 * 2);
+                let (bottom_r, bottom_g, bottom_b) = scaled.pixel(x as u32, y as u32 * 2 + 1);
+                buffer
+                    .get_mut(area.x + x, area.y + y)
+                    .set_symbol("▀")
+                    .set_fg(Color::Rgb(top_r, top_g, top_b))
+                    .set_bg(Color::Rgb(bottom_r, bottom_g, bottom_b));
+            }
+        }
+    }
+    // Writes kitty or sixel images, whatever changed since the last frame
+    pub fn draw_graphics<W: Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        if self.protocol == GraphicsProtocol::HalfBlocks || (!self.dirty && self.drawn == self.area)
+        {
+            return Ok(());
+        }
+        if self.protocol == GraphicsProtocol::Kitty {
+            // Deletes every image placed so far, there only ever is the one
+            write!(writer, "\x1b_Ga=d,q=2\x1b\\")?;
+        } else if self.drawn.take().is_some() {
+            // The next frame clears the old one off the screen, the new one follows after
+            self.clear = true;
+            return Ok(());
+        }
+        self.dirty = false;
+        self.drawn = self.area;
+        let (Some(ref image), Some(area)) = (&self.image, self.area) else {
+            return writer.flush();
+        };
+        writer.queue(MoveTo(area.x, area.y))?;
+        match (self.protocol, cell_size()) {
+            (GraphicsProtocol::Kitty, _) => write_kitty(writer, image, area)?,
+            (_, Some(cell_size)) => write_sixel(writer, image, area, cell_size)?,
+            (_, None) => tracing::warn!("Unknown cell size, cannot size sixel images"),
+        }
+        writer.flush()
+    }
+}
+
+#[async_trait]
+impl Page for CoverArt {
+    fn render(&mut self, frame: &mut Frame, rect: Rect) {
+        let block = Block::default().borders(Borders::ALL).title("Cover");
+        let inner = block.inner(rect);
+        frame.render_widget(block, rect);
+        let Some(ref image) = self.image else {
+            self.area = None;
+            return;
+        };
+        if inner.width == 0 || inner.height == 0 {
+            self.area = None;
+            return;
+        }
+        let area = Self::fit(image, inner);
+        if self.protocol == GraphicsProtocol::HalfBlocks {
+            self.render_half_blocks(frame, area);
+            return;
+        }
+        // Whatever ratatui would draw there would end up on top of the image
+        let buffer = frame.buffer_mut();
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                buffer.get_mut(x, y).set_skip(true);
+            }
+        }
+        self.area = Some(area);
+    }
+}
+
+// Raw RGB, kitty scales it to the cells itself
+fn write_kitty<W: Write>(writer: &mut W, image: &CoverImage, area: Rect) -> std::io::Result<()> {
+    let encoded = base64(&image.rgb);
+    // Escape codes are limited to 4096 bytes of payload, `m=1` says more chunks follow
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(4096).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i + 1 < chunks.len()) as u8;
+        if i == 0 {
+            write!(
+                writer,
+                "\x1b_Ga=T,f=24,s={},v={},c={},r={},C=1,q=2,m={};",
+                image.width, image.height, area.width, area.height, more
+            )?;
+        } else {
+            write!(writer, "\x1b_Gm={};", more)?;
+        }
+        writer.write_all(chunk)?;
+        write!(writer, "\x1b\\")?;
+    }
+    Ok(())
+}
+
+// Six rows of pixels per line, one pass per color. Colors are rounded to a 6x6x6 cube.
+fn write_sixel<W: Write>(
+    writer: &mut W,
+    image: &CoverImage,
+    area: Rect,
+    (cell_width, cell_height): (u16, u16),
+) -> std::io::Result<()> {
+    let width = area.width as u32 * cell_width as u32;
+    let height = area.height as u32 * cell_height as u32;
+    let scale = (width as f64 / image.width as f64).min(height as f64 / image.height as f64);
+    let image = image.resized(
+        (image.width as f64 * scale) as u32,
+        (image.height as f64 * scale) as u32,
+    );
+    let level = |value: u8| (value as u16 * 5 + 127) / 255;
+    let colors: Vec<u8> = image
+        .rgb
+        .chunks(3)
+        .map(|f| (level(f[0]) * 36 + level(f[1]) * 6 + level(f[2])) as u8)
+        .collect();
+    write!(writer, "\x1bPq\"1;1;{};{}", image.width, image.height)?;
+    for color in 0..216u16 {
+        let percent = |level: u16| level * 100 / 5;
+        write!(
+            writer,
+            "#{};2;{};{};{}",
+            color,
+            percent(color / 36),
+            percent(color / 6 % 6),
+            percent(color % 6)
+        )?;
+    }
+    let width = image.width as usize;
+    for band in colors.chunks(width * 6) {
+        let rows = band.len() / width;
+        let mut used: Vec<u8> = band.to_vec();
+        used.sort_unstable();
+        used.dedup();
+        for color in used {
+            write!(writer, "#{}", color)?;
+            // Run length encoded, `!<count><char>`
+            let mut run: Option<(u8, usize)> = None;
+            for x in 0..width {
+                let bits = (0..rows)
+                    .filter(|row| band[row * width + x] == color)
+                    .fold(0u8, |bits, row| bits | 1 << row);
+                let sixel = 63 + bits;
+                run = match run {
+                    Some((last, count)) if last == sixel => Some((last, count + 1)),
+                    Some((last, count)) => {
+                        write_sixel_run(writer, last, count)?;
+                        Some((sixel, 1))
+                    }
+                    None => Some((sixel, 1)),
+                };
+            }
+            if let Some((last, count)) = run {
+                write_sixel_run(writer, last, count)?;
+            }
+            // Back to the start of the band for the next color
+            write!(writer, "$")?;
+        }
+        write!(writer, "-")?;
+    }
+    write!(writer, "\x1b\\")
+}
+
+fn write_sixel_run<W: Write>(writer: &mut W, sixel: u8, count: usize) -> std::io::Result<()> {
+    if count > 3 {
+        write!(writer, "!{}{}", count, sixel as char)
+    } else {
+        writer.write_all(&vec![sixel; count])
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let value = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(value >> (18 - i * 6) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// Pixels per cell, sixel images are sized in pixels while everything else counts cells
+#[cfg(unix)]
+fn cell_size() -> Option<(u16, u16)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if result != 0 || size.ws_col == 0 || size.ws_row == 0 || size.ws_xpixel == 0 {
+        return None;
+    }
+    Some((size.ws_xpixel / size.ws_col, size.ws_ypixel / size.ws_row))
+}
+
+#[cfg(not(unix))]
+fn cell_size() -> Option<(u16, u16)> {
+    None
+}
@@ -1,62 +1,251 @@
-use std::path::Path;
+use std::{
+    cmp::Reverse,
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use crate::db::audio_scanner::AudioScanner;
+use crate::{
+    db::{
+        query::{Query, QueryError},
+        search::fuzzy_match,
+        AudioData,
+    },
+    event::AppEvent,
+};
 
-use super::Page;
+use super::{Msg, Page, StatefulPage};
 use async_trait::async_trait;
-use futures::pin_mut;
+use crossterm::event::KeyCode;
 use ratatui::{
-    prelude::{Alignment, Rect},
+    prelude::{Alignment, Constraint, Layout, Rect},
     style::{Color, Modifier, Style},
+    text::{Line, Span},
     widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
     Frame,
 };
-use tokio_stream::StreamExt;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FileListState {
+    #[default]
+    Normal,
+    // Keys go into the search query
+    Search,
+    // Keys go into the filter query
+    Filter,
+    // Keys go into the tags of the selected track
+    Tags,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum FileListMsg {
+    StartSearch,
+    SearchInput(char),
+    SearchBackspace,
+    SubmitSearch,
+    ClearSearch,
+    StartFilter,
+    FilterInput(char),
+    FilterBackspace,
+    SubmitFilter,
+    // Handled by the app, an empty query shows the whole library again
+    RunFilter(Query),
+    // Handled by the app, at the end of the queue or right after the current track
+    Queue(Box<AudioData>),
+    QueueNext(Box<AudioData>),
+    ToggleFavorite,
+    ChangeRating(i8),
+    StartTags,
+    TagsInput(char),
+    TagsBackspace,
+    SubmitTags,
+    CancelTags,
+    // Handled by the app, the selected track with its rating, favorite or tags changed
+    SaveUserData(Box<AudioData>),
+    ToggleMark,
+    ClearMarks,
+    // Handled by the app, the marked tracks or the selected one
+    EditTags(Vec<AudioData>),
+    // Handled by the app, everything the filter matches as a playlist
+    QueueFilter,
+    ExportFilter,
+}
+
+impl Msg for FileListMsg {}
 
 #[derive(Debug)]
 pub struct FileList {
-    file_list: Vec<String>,
+    file_list: Vec<AudioData>,
+    // Indices into `file_list` that are shown, with the matched chars of their label
+    visible: Vec<(usize, Vec<usize>)>,
     file_list_state: ListState,
     style: Style,
+    state: FileListState,
+    query: String,
+    filter_input: String,
+    filter_error: Option<QueryError>,
+    // The query the list currently shows the results of
+    filter: Option<String>,
+    // Comma separated
+    tags_input: String,
+    // Tracks picked for editing, by path and start so they survive a reload
+    marked: BTreeSet<(PathBuf, Option<Duration>)>,
+}
+
+// CUE tracks share their file, the start tells them apart
+fn mark_key(track: &AudioData) -> (PathBuf, Option<Duration>) {
+    (track.path.clone(), track.start)
 }
 
 impl FileList {
     pub fn new() -> Self {
         let mut file_list_state = ListState::default();
-        let file_list: Vec<String> = Vec::new();
+        let file_list: Vec<AudioData> = Vec::new();
         file_list_state.select(Some(0));
         let style = Style::default();
         Self {
             style,
             file_list,
+            visible: Vec::new(),
             file_list_state,
+            state: FileListState::Normal,
+            query: String::new(),
+            filter_input: String::new(),
+            filter_error: None,
+            filter: None,
+            tags_input: String::new(),
+            marked: BTreeSet::new(),
         }
     }
-    pub fn _set_file_list(&mut self, file_list: Vec<String>) {
+    pub fn set_file_list(&mut self, file_list: Vec<AudioData>) {
         tracing::info!("Recieved: {} items", file_list.len());
         self.file_list = file_list;
+        // Only forget the marks of tracks that are gone
+        let present: BTreeSet<_> = self.file_list.iter().map(mark_key).collect();
+        self.marked.retain(|f| present.contains(f));
+        self.apply_search();
+        // Keep the selection inside the (possibly shorter) new list
+        let selected = self
+            .file_list_state
+            .selected()
+            .unwrap_or_default()
+            .min(self.visible.len().saturating_sub(1));
+        self.file_list_state.select(Some(selected));
+    }
+    pub fn selected(&self) -> Option<&AudioData> {
+        self.selected_index().and_then(|i| self.file_list.get(i))
+    }
+    fn selected_index(&self) -> Option<usize> {
+        self.file_list_state
+            .selected()
+            .and_then(|i| self.visible.get(i))
+            .map(|(i, _)| *i)
+    }
+    fn marked_or_selected(&self) -> Vec<AudioData> {
+        if self.marked.is_empty() {
+            return self.selected().cloned().into_iter().collect();
+        }
+        self.file_list
+            .iter()
+            .filter(|f| self.marked.contains(&mark_key(f)))
+            .cloned()
+            .collect()
+    }
+    // Updates the entries of `track` after its user data changed
+    pub fn replace_track(&mut self, track: AudioData) {
+        for data in self.file_list.iter_mut() {
+            if data.path == track.path && data.start == track.start {
+                *data = track.clone();
+            }
+        }
+        self.apply_search();
+    }
+    // A copy of the selected track with `change` applied, to be saved by the app
+    fn edit_selected<F: FnOnce(&mut AudioData)>(&self, change: F) -> Option<FileListMsg> {
+        let mut track = self.selected()?.clone();
+        change(&mut track);
+        Some(FileListMsg::SaveUserData(Box::new(track)))
+    }
+    pub fn select_path(&mut self, path: &Path) {
+        let index = self
+            .visible
+            .iter()
+            .position(|(i, _)| self.file_list[*i].path == path);
+        if let Some(index) = index {
+            self.file_list_state.select(Some(index));
+        }
+    }
+    fn label(&self, data: &AudioData) -> String {
+        // The album is searchable too, so show it while searching
+        let mut label = if self.query.is_empty() {
+            data.display_name()
+        } else {
+            Self::search_text(data)
+        };
+        // Appended so the highlighted positions of the name stay the same
+        if data.favorite {
+            label.push_str("  ♥");
+        }
+        if let Some(rating) = data.rating {
+            label.push_str("  ");
+            label.push_str(&"★".repeat(rating as usize));
+        }
+        for tag in data.tags.iter() {
+            label.push_str("  #");
+            label.push_str(tag);
+        }
+        label
     }
-    pub async fn update_file_list<P: AsRef<Path>>(&mut self, path: P) {
-        let stream = AudioScanner::scan_dir(path);
-        // Only for debugging
-        let mut item_count: i32 = 0;
-        // We need to pin this as of now
-        pin_mut!(stream);
-        // For loops dont work with streams (yet)
-        while let Some(item) = stream.next().await {
-            tracing::info!("Recieved item: {} item", item.display());
-            self.file_list.push(item.display().to_string());
-            item_count += 1;
+    // Title, artist and album, the decorations of the label arent searched
+    fn search_text(data: &AudioData) -> String {
+        if data.album.is_empty() {
+            return data.display_name();
         }
-        tracing::info!("Recieved total items: {}", item_count);
+        format!("{}  [{}]", data.display_name(), data.album)
+    }
+    fn apply_search(&mut self) {
+        if self.query.is_empty() {
+            self.visible = (0..self.file_list.len()).map(|f| (f, Vec::new())).collect();
+            return;
+        }
+        let mut matches: Vec<(i64, usize, Vec<usize>)> = Vec::new();
+        for (index, data) in self.file_list.iter().enumerate() {
+            if let Some(found) = fuzzy_match(&self.query, &Self::search_text(data)) {
+                matches.push((found.score, index, found.positions));
+            } else if let Some(found) = fuzzy_match(&self.query, &data.path.to_string_lossy()) {
+                // Path matches rank below everything else and arent highlighted
+                matches.push((found.score - i64::from(u16::MAX), index, Vec::new()));
+            }
+        }
+        // Stable, so equal scores keep the library order
+        matches.sort_by_key(|f| Reverse(f.0));
+        self.visible = matches
+            .into_iter()
+            .map(|(_, index, positions)| (index, positions))
+            .collect();
+    }
+    pub fn set_filter(&mut self, filter: Option<String>) {
+        self.state = FileListState::Normal;
+        self.filter = filter;
+        self.file_list_state.select(Some(0));
+    }
+    // Errors show up while typing already
+    fn set_filter_input(&mut self, filter_input: String) {
+        self.filter_error = Query::parse(&filter_input).err();
+        self.filter_input = filter_input;
+    }
+    fn set_query(&mut self, query: String) {
+        self.query = query;
+        self.apply_search();
+        self.file_list_state.select(Some(0));
     }
     pub fn next(&mut self) {
-        if self.file_list.is_empty() {
+        if self.visible.is_empty() {
             return;
         }
         let i = match self.file_list_state.selected() {
             Some(i) => {
-                if i >= self.file_list.len() - 1 {
+                if i >= self.visible.len() - 1 {
                     0
                 } else {
                     i + 1
@@ -67,13 +256,13 @@ impl FileList {
         self.file_list_state.select(Some(i));
     }
     pub fn prev(&mut self) {
-        if self.file_list.is_empty() {
+        if self.visible.is_empty() {
             return;
         }
         let i = match self.file_list_state.selected() {
             Some(i) => {
-                if i <= 0 {
-                    self.file_list.len() - 1
+                if i == 0 {
+                    self.visible.len() - 1
                 } else {
                     i - 1
                 }
@@ -87,7 +276,70 @@ impl FileList {
 #[async_trait]
 impl Page for FileList {
     fn render(&mut self, frame: &mut Frame, rect: Rect) {
-        let block = Block::default().borders(Borders::ALL).title("File list");
+        let searching = self.state == FileListState::Search || !self.query.is_empty();
+        let (rect, search_rect) = if searching {
+            let layout = Layout::default()
+                .constraints([Constraint::Min(0), Constraint::Length(3)].as_ref())
+                .split(rect);
+            (layout[0], Some(layout[1]))
+        } else {
+            (rect, None)
+        };
+        let (rect, filter_rect) = match self.state {
+            FileListState::Filter => {
+                // One more line for the error
+                let height = 3 + self.filter_error.is_some() as u16;
+                let layout = Layout::default()
+                    .constraints([Constraint::Min(0), Constraint::Length(height)].as_ref())
+                    .split(rect);
+                (layout[0], Some(layout[1]))
+            }
+            FileListState::Tags => {
+                let layout = Layout::default()
+                    .constraints([Constraint::Min(0), Constraint::Length(3)].as_ref())
+                    .split(rect);
+                (layout[0], Some(layout[1]))
+            }
+            _ => (rect, None),
+        };
+        if let (FileListState::Tags, Some(tags_rect)) = (self.state, filter_rect) {
+            let tags = Paragraph::new(format!("{}_", self.tags_input)).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Tags (comma separated)"),
+            );
+            frame.render_widget(tags, tags_rect);
+        } else if let Some(filter_rect) = filter_rect {
+            let mut lines = Vec::from([Line::from(format!(":{}_", self.filter_input))]);
+            if let Some(ref error) = self.filter_error {
+                // Point at the offending char, the `:` takes up the first column
+                lines.push(Line::from(Span::styled(
+                    format!("{}^ {}", " ".repeat(error.position + 1), error.message),
+                    Style::default().fg(Color::Red),
+                )));
+            }
+            let filter =
+                Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("Filter"));
+            frame.render_widget(filter, filter_rect);
+        }
+        if let Some(search_rect) = search_rect {
+            let mut input = format!("/{}", self.query);
+            if self.state == FileListState::Search {
+                input.push('_');
+            }
+            let title = format!("Search ({}/{})", self.visible.len(), self.file_list.len());
+            let search =
+                Paragraph::new(input).block(Block::default().borders(Borders::ALL).title(title));
+            frame.render_widget(search, search_rect);
+        }
+        let mut title = match self.filter {
+            Some(ref filter) => format!("File list ({}) (A: queue all, x: export)", filter),
+            None => String::from("File list"),
+        };
+        if !self.marked.is_empty() {
+            title.push_str(&format!(" [{} marked]", self.marked.len()));
+        }
+        let block = Block::default().borders(Borders::ALL).title(title);
         if self.file_list.is_empty() {
             let error_text = Paragraph::new("Directory is empty/invalid!")
                 .alignment(Alignment::Center)
@@ -95,10 +347,34 @@ impl Page for FileList {
             frame.render_widget(error_text, rect);
             return;
         }
+        let match_style = Style::default()
+            .fg(Color::Yellow)
+            .add_modifier(Modifier::BOLD);
+        let marked_style = Style::default().fg(Color::Magenta);
         let items: Vec<ListItem<'_>> = self
-            .file_list
+            .visible
             .iter()
-            .map(|f| ListItem::new(f.as_str()))
+            .map(|(index, positions)| {
+                let track = &self.file_list[*index];
+                let label = self.label(track);
+                let style = if self.marked.contains(&mark_key(track)) {
+                    marked_style
+                } else {
+                    Style::default()
+                };
+                if positions.is_empty() {
+                    return ListItem::new(label).style(style);
+                }
+                let spans: Vec<Span<'_>> = label
+                    .chars()
+                    .enumerate()
+                    .map(|(i, c)| match positions.binary_search(&i) {
+                        Ok(_) => Span::styled(c.to_string(), match_style),
+                        Err(_) => Span::raw(c.to_string()),
+                    })
+                    .collect();
+                ListItem::new(Line::from(spans)).style(style)
+            })
             .collect();
         let list = List::new(items)
             .block(block)
@@ -113,50 +389,202 @@ impl Page for FileList {
     }
 }
 
-//#[async_trait]
-//impl StatefulPage for FileList {
-//    type State = FileListState;
-//    type Message = FileListMsg;
-//    async fn update(&mut self, msg: Self::Message) -> Option<Self::Message> {
-//        match msg {
-//            FileListMsg::State(state) => {
-//                self.state = state;
-//                match state {
-//                    FileListState::Inactive => {
-//                        self.style = self.style.add_modifier(Modifier::DIM);
-//                    }
-//                    FileListState::Active => {
-//                        self.style = self.style.remove_modifier(Modifier::DIM);
-//                    }
-//                };
-//            }
-//            FileListMsg::UpdateFileList(list) => {
-//                self.file_list = list;
-//            }
-//            FileListMsg::Increment => {
-//                self.next();
-//            }
-//            FileListMsg::Decrement => {
-//                self.prev();
-//            }
-//        }
-//        None
-//    }
-//    async fn handle_events(&mut self, event: AppEvent) -> Option<Self::Message> {
-//        if self.get_state() == FileListState::Inactive {
-//            return None;
-//        }
-//        match event {
-//            AppEvent::Key(x) => match x {
-//                KeyCode::Char('j') => return Some(FileListMsg::Increment),
-//                KeyCode::Char('k') => return Some(FileListMsg::Decrement),
-//                _ => {}
-//            },
-//            _ => {}
-//        }
-//        None
-//    }
-//    fn get_state(&self) -> Self::State {
-//        self.state
-//    }
-//}
+#[async_trait]
+impl StatefulPage for FileList {
+    type State = FileListState;
+    type Message = FileListMsg;
+    async fn update(&mut self, msg: Self::Message) -> Option<Self::Message> {
+        match msg {
+            FileListMsg::StartSearch => self.state = FileListState::Search,
+            FileListMsg::SearchInput(c) => {
+                let mut query = self.query.clone();
+                query.push(c);
+                self.set_query(query);
+            }
+            FileListMsg::SearchBackspace => {
+                let mut query = self.query.clone();
+                query.pop();
+                self.set_query(query);
+            }
+            FileListMsg::SubmitSearch => self.state = FileListState::Normal,
+            FileListMsg::StartFilter => {
+                self.state = FileListState::Filter;
+                let filter_input = self.filter.clone().unwrap_or_default();
+                self.set_filter_input(filter_input);
+            }
+            FileListMsg::FilterInput(c) => {
+                let mut filter_input = self.filter_input.clone();
+                filter_input.push(c);
+                self.set_filter_input(filter_input);
+            }
+            FileListMsg::FilterBackspace => {
+                let mut filter_input = self.filter_input.clone();
+                filter_input.pop();
+                self.set_filter_input(filter_input);
+            }
+            FileListMsg::SubmitFilter => match Query::parse(&self.filter_input) {
+                Ok(query) => {
+                    self.state = FileListState::Normal;
+                    return Some(FileListMsg::RunFilter(query));
+                }
+                Err(e) => self.filter_error = Some(e),
+            },
+            FileListMsg::ToggleFavorite => {
+                return self.edit_selected(|f| f.favorite = !f.favorite);
+            }
+            FileListMsg::ChangeRating(change) => {
+                // Lowering a single star clears the rating
+                return self.edit_selected(|f| {
+                    let rating = f.rating.unwrap_or_default() as i8 + change;
+                    f.rating = (rating > 0).then_some(rating.min(5) as u8);
+                });
+            }
+            FileListMsg::StartTags => {
+                if let Some(track) = self.selected() {
+                    self.tags_input = track.tags.join(", ");
+                    self.state = FileListState::Tags;
+                }
+            }
+            FileListMsg::TagsInput(c) => self.tags_input.push(c),
+            FileListMsg::TagsBackspace => {
+                self.tags_input.pop();
+            }
+            FileListMsg::SubmitTags => {
+                self.state = FileListState::Normal;
+                let mut tags: Vec<String> = Vec::new();
+                for tag in self.tags_input.split(',').map(str::trim) {
+                    // Tags are matched without case, so are duplicates
+                    if !tag.is_empty() && !tags.iter().any(|f| f.eq_ignore_ascii_case(tag)) {
+                        tags.push(tag.to_string());
+                    }
+                }
+                return self.edit_selected(|f| f.tags = tags);
+            }
+            FileListMsg::CancelTags => self.state = FileListState::Normal,
+            FileListMsg::ToggleMark => {
+                if let Some(key) = self.selected().map(mark_key) {
+                    if !self.marked.remove(&key) {
+                        self.marked.insert(key);
+                    }
+                    self.next();
+                }
+            }
+            FileListMsg::ClearMarks => self.marked.clear(),
+            FileListMsg::RunFilter(_)
+            | FileListMsg::Queue(_)
+            | FileListMsg::QueueNext(_)
+            | FileListMsg::SaveUserData(_)
+            | FileListMsg::EditTags(_)
+            | FileListMsg::QueueFilter
+            | FileListMsg::ExportFilter => {}
+            FileListMsg::ClearSearch => {
+                self.state = FileListState::Normal;
+                self.set_query(String::new());
+            }
+        }
+        None
+    }
+    async fn handle_events(&mut self, event: AppEvent) -> Option<Self::Message> {
+        let AppEvent::Key(x) = event else {
+            return None;
+        };
+        if self.get_state() == FileListState::Filter {
+            return match x {
+                KeyCode::Char(c) => Some(FileListMsg::FilterInput(c)),
+                KeyCode::Backspace => Some(FileListMsg::FilterBackspace),
+                KeyCode::Enter => Some(FileListMsg::SubmitFilter),
+                KeyCode::Esc => Some(FileListMsg::RunFilter(Query::default())),
+                _ => None,
+            };
+        }
+        if self.get_state() == FileListState::Tags {
+            return match x {
+                KeyCode::Char(c) => Some(FileListMsg::TagsInput(c)),
+                KeyCode::Backspace => Some(FileListMsg::TagsBackspace),
+                KeyCode::Enter => Some(FileListMsg::SubmitTags),
+                KeyCode::Esc => Some(FileListMsg::CancelTags),
+                _ => None,
+            };
+        }
+        if self.get_state() == FileListState::Search {
+            return match x {
+                KeyCode::Char(c) => Some(FileListMsg::SearchInput(c)),
+                KeyCode::Backspace => Some(FileListMsg::SearchBackspace),
+                KeyCode::Enter => Some(FileListMsg::SubmitSearch),
+                KeyCode::Esc => Some(FileListMsg::ClearSearch),
+                _ => None,
+            };
+        }
+        match x {
+            KeyCode::Char('/') => Some(FileListMsg::StartSearch),
+            KeyCode::Char(':') => Some(FileListMsg::StartFilter),
+            KeyCode::Enter => self
+                .selected()
+                .cloned()
+                .map(|f| FileListMsg::Queue(Box::new(f))),
+            KeyCode::Char('n') => self
+                .selected()
+                .cloned()
+                .map(|f| FileListMsg::QueueNext(Box::new(f))),
+            KeyCode::Char('f') => Some(FileListMsg::ToggleFavorite),
+            KeyCode::Char('>') => Some(FileListMsg::ChangeRating(1)),
+            KeyCode::Char('<') => Some(FileListMsg::ChangeRating(-1)),
+            KeyCode::Char('t') => Some(FileListMsg::StartTags),
+            KeyCode::Char('m') => Some(FileListMsg::ToggleMark),
+            KeyCode::Char('e') => {
+                let tracks = self.marked_or_selected();
+                (!tracks.is_empty()).then_some(FileListMsg::EditTags(tracks))
+            }
+            KeyCode::Esc if !self.query.is_empty() => Some(FileListMsg::ClearSearch),
+            KeyCode::Esc if !self.marked.is_empty() => Some(FileListMsg::ClearMarks),
+            KeyCode::Esc if self.filter.is_some() => Some(FileListMsg::RunFilter(Query::default())),
+            KeyCode::Char('A') if self.filter.is_some() => Some(FileListMsg::QueueFilter),
+            KeyCode::Char('x') if self.filter.is_some() => Some(FileListMsg::ExportFilter),
+            _ => None,
+        }
+    }
+    fn get_state(&self) -> Self::State {
+        self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn visible_names(file_list: &FileList) -> Vec<String> {
+        file_list
+            .visible
+            .iter()
+            .map(|(f, _)| file_list.file_list[*f].name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn search_ignores_ratings_and_tags() {
+        let mut file_list = FileList::new();
+        let mut rated = AudioData::new(PathBuf::from("/music/rated.flac"));
+        rated.rating = Some(5);
+        rated.favorite = true;
+        rated.tags = Vec::from([String::from("workout")]);
+        let mut plain = AudioData::new(PathBuf::from("/music/hash #1.flac"));
+        plain.author = String::from("Someone");
+        plain.album = String::from("Star");
+        file_list.set_file_list(Vec::from([rated, plain]));
+        for query in ["★", "♥", "workout"] {
+            file_list.set_query(query.to_string());
+            assert!(visible_names(&file_list).is_empty(), "{}", query);
+        }
+        // Only in the name of the second track
+        file_list.set_query(String::from("#"));
+        assert_eq!(visible_names(&file_list), ["hash #1"]);
+        // Artist and album still count
+        file_list.set_query(String::from("someone star"));
+        assert_eq!(visible_names(&file_list), ["hash #1"]);
+        // The path is the fallback
+        file_list.set_query(String::from("music rated"));
+        assert_eq!(visible_names(&file_list), ["rated"]);
+    }
+}
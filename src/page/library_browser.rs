@@ -0,0 +1,430 @@
+use std::collections::{BTreeSet, HashSet};
+
+use async_trait::async_trait;
+use crossterm::event::KeyCode;
+use ratatui::{
+    prelude::{Alignment, Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Tabs},
+    Frame,
+};
+
+use crate::{db::AudioData, event::AppEvent, playlist::Playlist};
+
+use super::{Msg, Page, StatefulPage};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BrowserView {
+    #[default]
+    Artists,
+    Genres,
+    Years,
+    Folders,
+    Playlists,
+}
+
+impl BrowserView {
+    const ALL: [Self; 5] = [
+        Self::Artists,
+        Self::Genres,
+        Self::Years,
+        Self::Folders,
+        Self::Playlists,
+    ];
+    fn title(&self) -> &'static str {
+        match self {
+            Self::Artists => "Artists",
+            Self::Genres => "Genres",
+            Self::Years => "Years",
+            Self::Folders => "Folders",
+            Self::Playlists => "Playlists",
+        }
+    }
+    // Everything below the last level is a track
+    fn levels(&self) -> &'static [BrowserLevel] {
+        match self {
+            Self::Artists => &[BrowserLevel::Artist, BrowserLevel::Album],
+            Self::Genres => &[BrowserLevel::Genre, BrowserLevel::ArtistAlbum],
+            Self::Years => &[BrowserLevel::Year, BrowserLevel::ArtistAlbum],
+            Self::Folders => &[BrowserLevel::Folder],
+            // Smart playlists keep their own order, see `playlist_entries`
+            Self::Playlists => &[],
+        }
+    }
+    fn next(&self) -> Self {
+        let index = Self::ALL.iter().position(|f| f == self).unwrap_or_default();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BrowserLevel {
+    Artist,
+    Album,
+    // Albums of different artists show up next to each other here
+    ArtistAlbum,
+    Genre,
+    Year,
+    Folder,
+}
+
+impl BrowserLevel {
+    // (sort key, label), the label doubles as the key of the group
+    fn group(&self, track: &AudioData) -> (i64, String, String) {
+        let year = track.year().map_or(i64::MAX, i64::from);
+        match self {
+            Self::Artist => {
+                let artist = or_unknown(track.album_artist(), "Unknown Artist");
+                (0, sort_name(&artist), artist)
+            }
+            Self::Album => {
+                let album = or_unknown(&track.album, "Unknown Album");
+                let label = match track.year() {
+                    Some(year) => format!("{} ({})", album, year),
+                    None => album.clone(),
+                };
+                (year, sort_name(&album), label)
+            }
+            Self::ArtistAlbum => {
+                let artist = or_unknown(track.album_artist(), "Unknown Artist");
+                let album = or_unknown(&track.album, "Unknown Album");
+                let sort = format!("{}\0{}", sort_name(&artist), sort_name(&album));
+                (0, sort, format!("{} - {}", artist, album))
+            }
+            Self::Genre => {
+                let genre = or_unknown(&track.genre, "Unknown Genre");
+                (0, genre.to_lowercase(), genre)
+            }
+            Self::Year => match track.year() {
+                Some(year) => (year as i64, String::new(), year.to_string()),
+                None => (year, String::new(), String::from("Unknown Year")),
+            },
+            Self::Folder => {
+                let folder = track
+                    .path
+                    .parent()
+                    .map(|f| f.display().to_string())
+                    .unwrap_or_default();
+                (0, folder.to_lowercase(), folder)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum BrowserEntry {
+    Group(String),
+    // Index into `LibraryBrowser::tracks`
+    Track(usize),
+    // Index into `LibraryBrowser::playlists` and its tracks
+    PlaylistTrack(usize, usize),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum LibraryBrowserMsg {
+    NextView,
+    Increment,
+    Decrement,
+    Enter,
+    Back,
+    // Handled by the app, the smart playlist to write to a file
+    Export(Playlist),
+}
+
+impl Msg for LibraryBrowserMsg {}
+
+#[derive(Debug)]
+pub struct LibraryBrowser {
+    view: BrowserView,
+    tracks: Vec<AudioData>,
+    playlists: Vec<(String, Vec<AudioData>)>,
+    // The group picked on every level above the current one
+    selection: Vec<String>,
+    entries: Vec<BrowserEntry>,
+    list_state: ListState,
+}
+
+impl LibraryBrowser {
+    pub fn new() -> Self {
+        Self {
+            view: BrowserView::default(),
+            tracks: Vec::new(),
+            playlists: Vec::new(),
+            selection: Vec::new(),
+            entries: Vec::new(),
+            list_state: ListState::default(),
+        }
+    }
+    // Smart playlists are evaluated by the library, so they come in separately
+    pub fn set_tracks(&mut self, tracks: Vec<AudioData>, playlists: Vec<(String, Vec<AudioData>)>) {
+        self.tracks = tracks;
+        self.playlists = playlists;
+        self.refresh();
+        // Groups can vanish after a rescan, step back until something is left
+        while self.entries.is_empty() && !self.selection.is_empty() {
+            self.selection.pop();
+            self.refresh();
+        }
+    }
+    pub fn selected(&self) -> Option<&AudioData> {
+        match self.list_state.selected().and_then(|i| self.entries.get(i)) {
+            Some(BrowserEntry::Track(index)) => self.tracks.get(*index),
+            Some(BrowserEntry::PlaylistTrack(playlist, index)) => self
+                .playlists
+                .get(*playlist)
+                .and_then(|(_, tracks)| tracks.get(*index)),
+            _ => None,
+        }
+    }
+    // The smart playlist under the cursor, or the one that is open
+    fn selected_playlist(&self) -> Option<Playlist> {
+        if self.view != BrowserView::Playlists {
+            return None;
+        }
+        let name = match self.selection.first() {
+            Some(name) => name,
+            None => match self.list_state.selected().and_then(|i| self.entries.get(i)) {
+                Some(BrowserEntry::Group(name)) => name,
+                _ => return None,
+            },
+        };
+        let (name, tracks) = self.playlists.iter().find(|(f, _)| f == name)?;
+        Some(Playlist::from_tracks(Some(name.clone()), tracks))
+    }
+    fn refresh(&mut self) {
+        self.entries = match self.view {
+            BrowserView::Playlists => self.playlist_entries(),
+            _ => self.library_entries(),
+        };
+        let selected = self
+            .list_state
+            .selected()
+            .unwrap_or_default()
+            .min(self.entries.len().saturating_sub(1));
+        self.list_state.select(Some(selected));
+    }
+    fn playlist_entries(&self) -> Vec<BrowserEntry> {
+        let Some(name) = self.selection.first() else {
+            return self
+                .playlists
+                .iter()
+                .map(|(name, _)| BrowserEntry::Group(name.clone()))
+                .collect();
+        };
+        let Some(playlist) = self.playlists.iter().position(|(f, _)| f == name) else {
+            return Vec::new();
+        };
+        (0..self.playlists[playlist].1.len())
+            .map(|index| BrowserEntry::PlaylistTrack(playlist, index))
+            .collect()
+    }
+    fn library_entries(&self) -> Vec<BrowserEntry> {
+        let levels = self.view.levels();
+        let depth = self.selection.len();
+        let matching = self.tracks.iter().enumerate().filter(|(_, track)| {
+            levels
+                .iter()
+                .zip(self.selection.iter())
+                .all(|(level, key)| level.group(track).2 == *key)
+        });
+        match levels.get(depth) {
+            Some(level) => {
+                let groups: BTreeSet<(i64, String, String)> =
+                    matching.map(|(_, track)| level.group(track)).collect();
+                // The same label can come with several sort keys, keep the first one
+                let mut seen: HashSet<String> = HashSet::new();
+                groups
+                    .into_iter()
+                    .filter(|(_, _, label)| seen.insert(label.clone()))
+                    .map(|(_, _, label)| BrowserEntry::Group(label))
+                    .collect()
+            }
+            None => {
+                let mut indices: Vec<usize> = matching.map(|(index, _)| index).collect();
+                indices.sort_by(|a, b| track_order(&self.tracks[*a], &self.tracks[*b]));
+                indices.into_iter().map(BrowserEntry::Track).collect()
+            }
+        }
+    }
+    fn enter(&mut self) {
+        let Some(BrowserEntry::Group(label)) = self
+            .list_state
+            .selected()
+            .and_then(|i| self.entries.get(i))
+            .cloned()
+        else {
+            return;
+        };
+        self.selection.push(label);
+        self.list_state.select(Some(0));
+        self.refresh();
+    }
+    fn back(&mut self) {
+        let Some(label) = self.selection.pop() else {
+            return;
+        };
+        self.refresh();
+        // Land on the group we just left
+        let index = self
+            .entries
+            .iter()
+            .position(|f| *f == BrowserEntry::Group(label.clone()));
+        self.list_state.select(index.or(Some(0)));
+    }
+    fn set_view(&mut self, view: BrowserView) {
+        self.view = view;
+        self.selection.clear();
+        self.list_state.select(Some(0));
+        self.refresh();
+    }
+    fn next(&mut self) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let i = match self.list_state.selected() {
+            Some(i) if i + 1 < self.entries.len() => i + 1,
+            _ => 0,
+        };
+        self.list_state.select(Some(i));
+    }
+    fn prev(&mut self) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let i = match self.list_state.selected() {
+            Some(0) | None => self.entries.len() - 1,
+            Some(i) => i - 1,
+        };
+        self.list_state.select(Some(i));
+    }
+    fn entry_label(&self, entry: &BrowserEntry) -> String {
+        match entry {
+            BrowserEntry::Group(label) => label.clone(),
+            BrowserEntry::Track(index) => Self::track_label(&self.tracks[*index]),
+            // Playlists mix albums, so always say who it is
+            BrowserEntry::PlaylistTrack(playlist, index) => {
+                self.playlists[*playlist].1[*index].display_name()
+            }
+        }
+    }
+    fn track_label(track: &AudioData) -> String {
+        let mut label = match track.track {
+            Some(number) => format!("{:02}. ", number),
+            None => String::new(),
+        };
+        // Only mention the artist on compilations and the like
+        if !track.author.is_empty() && track.author != track.album_artist() {
+            label.push_str(&track.display_name());
+        } else {
+            label.push_str(&track.name);
+        }
+        if let Some(duration) = track.duration {
+            let seconds = duration.as_secs();
+            label.push_str(&format!(" [{}:{:02}]", seconds / 60, seconds % 60));
+        }
+        label
+    }
+}
+
+#[async_trait]
+impl Page for LibraryBrowser {
+    fn render(&mut self, frame: &mut Frame, rect: Rect) {
+        let layout = Layout::default()
+            .constraints([Constraint::Length(3), Constraint::Min(0)].as_ref())
+            .split(rect);
+        let titles: Vec<&str> = BrowserView::ALL.iter().map(|f| f.title()).collect();
+        let selected_view = BrowserView::ALL
+            .iter()
+            .position(|f| *f == self.view)
+            .unwrap_or_default();
+        let tabs = Tabs::new(titles)
+            .block(Block::default().borders(Borders::ALL).title("Library"))
+            .select(selected_view)
+            .highlight_style(Style::default().add_modifier(Modifier::BOLD));
+        frame.render_widget(tabs, layout[0]);
+        let mut breadcrumb = Vec::from([self.view.title()]);
+        breadcrumb.extend(self.selection.iter().map(String::as_str));
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(breadcrumb.join(" > "));
+        if self.entries.is_empty() {
+            let error_text = Paragraph::new("Library is empty!")
+                .alignment(Alignment::Center)
+                .block(block);
+            frame.render_widget(error_text, layout[1]);
+            return;
+        }
+        let items: Vec<ListItem<'_>> = self
+            .entries
+            .iter()
+            .map(|f| ListItem::new(self.entry_label(f)))
+            .collect();
+        let list = List::new(items)
+            .block(block)
+            .highlight_symbol("> ")
+            .highlight_style(
+                Style::default()
+                    .fg(Color::White)
+                    .add_modifier(Modifier::BOLD),
+            );
+        frame.render_stateful_widget(list, layout[1], &mut self.list_state);
+    }
+}
+
+#[async_trait]
+impl StatefulPage for LibraryBrowser {
+    type State = BrowserView;
+    type Message = LibraryBrowserMsg;
+    async fn update(&mut self, msg: Self::Message) -> Option<Self::Message> {
+        match msg {
+            LibraryBrowserMsg::NextView => self.set_view(self.view.next()),
+            LibraryBrowserMsg::Increment => self.next(),
+            LibraryBrowserMsg::Decrement => self.prev(),
+            LibraryBrowserMsg::Enter => self.enter(),
+            LibraryBrowserMsg::Back => self.back(),
+            LibraryBrowserMsg::Export(_) => {}
+        }
+        None
+    }
+    async fn handle_events(&mut self, event: AppEvent) -> Option<Self::Message> {
+        match event {
+            AppEvent::Key(x) => match x {
+                KeyCode::Tab => Some(LibraryBrowserMsg::NextView),
+                KeyCode::Char('j') | KeyCode::Down => Some(LibraryBrowserMsg::Increment),
+                KeyCode::Char('k') | KeyCode::Up => Some(LibraryBrowserMsg::Decrement),
+                KeyCode::Char('l') | KeyCode::Enter => Some(LibraryBrowserMsg::Enter),
+                KeyCode::Char('h') | KeyCode::Backspace => Some(LibraryBrowserMsg::Back),
+                KeyCode::Char('x') => self.selected_playlist().map(LibraryBrowserMsg::Export),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    fn get_state(&self) -> Self::State {
+        self.view
+    }
+}
+
+fn or_unknown(value: &str, unknown: &str) -> String {
+    if value.trim().is_empty() {
+        return String::from(unknown);
+    }
+    value.to_string()
+}
+
+// "The Beatles" sorts under B
+fn sort_name(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.strip_prefix("the ") {
+        Some(stripped) => stripped.to_string(),
+        None => name,
+    }
+}
+
+fn track_order(a: &AudioData, b: &AudioData) -> std::cmp::Ordering {
+    (a.disc.unwrap_or(1), a.track.unwrap_or(u32::MAX))
+        .cmp(&(b.disc.unwrap_or(1), b.track.unwrap_or(u32::MAX)))
+        .then_with(|| a.path.cmp(&b.path))
+        .then_with(|| a.start.cmp(&b.start))
+        .then_with(|| a.name.cmp(&b.name))
+}
@@ -0,0 +1,93 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use ratatui::{
+    prelude::{Alignment, Rect},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Block, Borders, Paragraph},
+    Frame,
+};
+
+use crate::db::lyrics::Lyrics;
+
+use super::Page;
+
+#[derive(Debug, Default)]
+pub struct LyricsView {
+    lyrics: Option<Lyrics>,
+    // The file the lyrics belong to
+    source: Option<PathBuf>,
+    current: Option<usize>,
+}
+
+impl LyricsView {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn set_lyrics(&mut self, source: Option<PathBuf>, lyrics: Option<Lyrics>) {
+        self.source = source;
+        self.lyrics = lyrics;
+        self.current = None;
+    }
+    pub fn source(&self) -> Option<&Path> {
+        self.source.as_deref()
+    }
+    // Position in the file, virtual tracks have to add their start
+    pub fn set_position(&mut self, position: Option<Duration>) {
+        self.current = self
+            .lyrics
+            .as_ref()
+            .zip(position)
+            .and_then(|(lyrics, position)| lyrics.line_at(position));
+    }
+}
+
+#[async_trait]
+impl Page for LyricsView {
+    fn render(&mut self, frame: &mut Frame, rect: Rect) {
+        let title = match self.lyrics {
+            Some(ref lyrics) if !lyrics.synced => "Lyrics (not synced)",
+            _ => "Lyrics",
+        };
+        let block = Block::default().borders(Borders::ALL).title(title);
+        let Some(ref lyrics) = self.lyrics else {
+            let empty = Paragraph::new("No lyrics")
+                .alignment(Alignment::Center)
+                .style(Style::default().fg(Color::DarkGray))
+                .block(block);
+            frame.render_widget(empty, rect);
+            return;
+        };
+        let past_style = Style::default().fg(Color::DarkGray);
+        let current_style = Style::default()
+            .fg(Color::Yellow)
+            .add_modifier(Modifier::BOLD);
+        let lines: Vec<Line<'_>> = lyrics
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let style = match self.current {
+                    Some(current) if i == current => current_style,
+                    Some(current) if i < current => past_style,
+                    _ => Style::default(),
+                };
+                Line::styled(line.text.as_str(), style)
+            })
+            .collect();
+        // The current line stays in the middle, unsynced lyrics just start at the top
+        let height = rect.height.saturating_sub(2);
+        let scroll = self
+            .current
+            .map_or(0, |f| (f as u16).saturating_sub(height / 2));
+        let lyrics = Paragraph::new(lines)
+            .alignment(Alignment::Center)
+            .scroll((scroll, 0))
+            .block(block);
+        frame.render_widget(lyrics, rect);
+    }
+}
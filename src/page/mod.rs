@@ -5,7 +5,18 @@ use async_trait::async_trait;
 use ratatui::{prelude::Rect, Frame};
 
 pub mod app;
+pub mod cover_art;
 pub mod file_list;
+pub mod library_browser;
+pub mod lyrics_view;
+pub mod now_playing;
+pub mod path_prompt;
+pub mod queue_list;
+pub mod scan_progress;
+pub mod seek_bar;
+pub mod statistics;
+pub mod tag_editor;
+pub mod visualizer;
 
 pub trait Msg: Send + Sync {}
 
@@ -0,0 +1,207 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use ratatui::{
+    prelude::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph},
+    Frame,
+};
+
+use crate::{
+    audio::queue::{RepeatMode, ShuffleMode},
+    db::AudioData,
+};
+
+use super::Page;
+
+// Long enough to read, short enough not to go stale
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Everything about playback that changes from frame to frame
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PlaybackStatus {
+    pub position: Option<Duration>,
+    pub paused: bool,
+    // From 0 to 1
+    pub volume: f32,
+    pub repeat: RepeatMode,
+    pub shuffle: ShuffleMode,
+}
+
+#[derive(Debug, Default)]
+pub struct NowPlaying {
+    track: Option<AudioData>,
+    status: PlaybackStatus,
+    // Errors and notices that dont belong to any pane, shown in the border for a while
+    message: Option<(String, Instant)>,
+    // Stays until it is fixed, e.g. no audio output
+    error: Option<String>,
+}
+
+impl NowPlaying {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn set_track(&mut self, track: Option<AudioData>) {
+        self.track = track;
+    }
+    pub fn set_status(&mut self, status: PlaybackStatus) {
+        self.status = status;
+    }
+    pub fn set_message(&mut self, message: String) {
+        self.message = Some((message, Instant::now()));
+    }
+    pub fn set_error(&mut self, error: Option<String>) {
+        self.error = error;
+    }
+    fn state_icon(&self) -> &'static str {
+        match (&self.track, self.status.paused) {
+            (None, _) => "■",
+            (Some(_), true) => "⏸",
+            (Some(_), false) => "▶",
+        }
+    }
+    // `FLAC 1411 kbps 44.1 kHz`, whatever of it is known
+    fn format_info(track: &AudioData) -> String {
+        let mut info: Vec<String> = Vec::new();
+        if !track.codec.is_empty() {
+            info.push(track.codec.to_uppercase());
+        }
+        if let Some(bit_rate) = track.bit_rate {
+            info.push(format!("{} kbps", bit_rate / 1000));
+        }
+        if let Some(sample_rate) = track.sample_rate {
+            // 44.1 kHz, but 48 kHz
+            let khz = sample_rate as f64 / 1000.0;
+            info.push(match sample_rate % 1000 {
+                0 => format!("{} kHz", khz),
+                _ => format!("{:.1} kHz", khz),
+            });
+        }
+        info.join(" ")
+    }
+}
+
+fn format_time(duration: Option<Duration>) -> String {
+    let Some(duration) = duration else {
+        return String::from("--:--");
+    };
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        return format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        );
+    }
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+#[async_trait]
+impl Page for NowPlaying {
+    fn render(&mut self, frame: &mut Frame, rect: Rect) {
+        let mut block = Block::default().borders(Borders::ALL);
+        self.message = self
+            .message
+            .take()
+            .filter(|(_, shown)| shown.elapsed() < MESSAGE_TIMEOUT);
+        // The latest message goes over the error for a while
+        match (&self.message, &self.error) {
+            (Some((message, _)), _) => {
+                block = block.title(Span::styled(
+                    format!(" {} ", message),
+                    Style::default().fg(Color::Yellow),
+                ));
+            }
+            (None, Some(error)) => {
+                block = block.title(Span::styled(
+                    format!(" {} ", error),
+                    Style::default().fg(Color::Red),
+                ));
+            }
+            (None, None) => {}
+        }
+        let area = block.inner(rect);
+        frame.render_widget(block, rect);
+        let dim = Style::default().fg(Color::DarkGray);
+        let separator = || Span::styled(" │ ", dim);
+        let icon = Span::styled(
+            format!(" {} ", self.state_icon()),
+            Style::default().fg(Color::Cyan),
+        );
+        let mut left = Vec::from([icon]);
+        match self.track {
+            Some(ref track) => {
+                left.push(Span::styled(
+                    track.name.clone(),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ));
+                for part in [&track.author, &track.album] {
+                    if !part.is_empty() {
+                        left.push(separator());
+                        left.push(Span::raw(part.clone()));
+                    }
+                }
+            }
+            None => left.push(Span::styled("Nothing playing", dim)),
+        }
+        let status = self.status;
+        let mut right = Vec::new();
+        if let Some(ref track) = self.track {
+            right.push(Span::raw(format!(
+                "{} / {}",
+                format_time(status.position),
+                format_time(track.duration)
+            )));
+            right.push(separator());
+        }
+        right.push(Span::raw(format!(
+            "vol {}%",
+            (status.volume * 100.0).round() as u32
+        )));
+        right.push(separator());
+        // Off is the normal case, it stays dim
+        let mode_style = |on: bool| {
+            if on {
+                Style::default().fg(Color::Yellow)
+            } else {
+                dim
+            }
+        };
+        right.push(Span::styled(
+            status.repeat.name(),
+            mode_style(status.repeat != RepeatMode::Off),
+        ));
+        right.push(Span::styled(", ", dim));
+        right.push(Span::styled(
+            status.shuffle.name(),
+            mode_style(status.shuffle != ShuffleMode::Off),
+        ));
+        if let Some(info) = self.track.as_ref().map(Self::format_info) {
+            if !info.is_empty() {
+                right.push(separator());
+                right.push(Span::styled(info, dim));
+            }
+        }
+        let right = Line::from(right);
+        // Track info gives way first when the terminal is narrow
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(
+                [
+                    Constraint::Min(0),
+                    Constraint::Length(right.width() as u16 + 1),
+                ]
+                .as_ref(),
+            )
+            .split(area);
+        frame.render_widget(Paragraph::new(Line::from(left)), columns[0]);
+        frame.render_widget(
+            Paragraph::new(right).alignment(Alignment::Right),
+            columns[1],
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use crossterm::event::KeyCode;
+use ratatui::{
+    prelude::Rect,
+    style::{Color, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph},
+    Frame,
+};
+
+use crate::event::AppEvent;
+
+use super::{Msg, Page, StatefulPage};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PathPromptMsg {
+    Input(char),
+    Backspace,
+    // Handled by the app, it knows what the path is for
+    Submit(PathBuf),
+    Cancel,
+}
+
+impl Msg for PathPromptMsg {}
+
+// A single line asking for a file, e.g. the playlist to import
+#[derive(Debug, Default)]
+pub struct PathPrompt {
+    title: String,
+    input: String,
+    // Why the last path didnt work, the prompt stays open to fix it
+    error: Option<String>,
+}
+
+impl PathPrompt {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn open(&mut self, title: String, input: String) {
+        self.title = title;
+        self.input = input;
+        self.error = None;
+    }
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+    // Room it needs, one more line for the error
+    pub fn height(&self) -> u16 {
+        3 + self.error.is_some() as u16
+    }
+    fn path(&self) -> Option<PathBuf> {
+        let input = self.input.trim();
+        if input.is_empty() {
+            return None;
+        }
+        // The shell isnt there to expand it
+        match (input.strip_prefix("~/"), dirs::home_dir()) {
+            (Some(rest), Some(home)) => Some(home.join(rest)),
+            _ => Some(PathBuf::from(input)),
+        }
+    }
+}
+
+#[async_trait]
+impl Page for PathPrompt {
+    fn render(&mut self, frame: &mut Frame, rect: Rect) {
+        let mut lines = Vec::from([Line::from(format!("{}_", self.input))]);
+        if let Some(ref error) = self.error {
+            lines.push(Line::from(Span::styled(
+                error.clone(),
+                Style::default().fg(Color::Red),
+            )));
+        }
+        let prompt = Paragraph::new(lines).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!("{} (Enter to confirm, Esc to cancel)", self.title)),
+        );
+        frame.render_widget(prompt, rect);
+    }
+}
+
+#[async_trait]
+impl StatefulPage for PathPrompt {
+    type State = String;
+    type Message = PathPromptMsg;
+    async fn update(&mut self, msg: Self::Message) -> Option<Self::Message> {
+        match msg {
+            PathPromptMsg::Input(c) => self.input.push(c),
+            PathPromptMsg::Backspace => {
+                self.input.pop();
+            }
+            PathPromptMsg::Submit(_) | PathPromptMsg::Cancel => {}
+        }
+        None
+    }
+    async fn handle_events(&mut self, event: AppEvent) -> Option<Self::Message> {
+        let AppEvent::Key(x) = event else {
+            return None;
+        };
+        match x {
+            KeyCode::Char(c) => Some(PathPromptMsg::Input(c)),
+            KeyCode::Backspace => Some(PathPromptMsg::Backspace),
+            KeyCode::Enter => self.path().map(PathPromptMsg::Submit),
+            KeyCode::Esc => Some(PathPromptMsg::Cancel),
+            _ => None,
+        }
+    }
+    fn get_state(&self) -> Self::State {
+        self.input.clone()
+    }
+}
@@ -0,0 +1,186 @@
+use async_trait::async_trait;
+use crossterm::event::KeyCode;
+use ratatui::{
+    prelude::{Alignment, Rect},
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    Frame,
+};
+
+use crate::{
+    audio::queue::{RepeatMode, ShuffleMode},
+    db::AudioData,
+    event::AppEvent,
+};
+
+use super::{Msg, Page, StatefulPage};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum QueueListMsg {
+    Increment,
+    Decrement,
+    // Handled by the app, it owns the queue
+    Play(usize),
+    Remove(usize),
+    Move(usize, usize),
+    Clear,
+    // Handled by the app, through a playlist file
+    Import,
+    Export,
+}
+
+impl Msg for QueueListMsg {}
+
+#[derive(Debug, Default)]
+pub struct QueueList {
+    tracks: Vec<AudioData>,
+    current: Option<usize>,
+    // Repeat and shuffle, shown in the title
+    modes: String,
+    list_state: ListState,
+    // Keys only reach the queue while it has focus
+    focused: bool,
+}
+
+impl QueueList {
+    pub fn new() -> Self {
+        let mut queue_list = Self::default();
+        queue_list.set_modes(RepeatMode::default(), ShuffleMode::default());
+        queue_list
+    }
+    pub fn set_queue(&mut self, tracks: Vec<AudioData>, current: Option<usize>) {
+        self.tracks = tracks;
+        self.current = current;
+        let selected = self
+            .list_state
+            .selected()
+            .unwrap_or_default()
+            .min(self.tracks.len().saturating_sub(1));
+        self.list_state.select(Some(selected));
+    }
+    pub fn set_current(&mut self, current: Option<usize>) {
+        self.current = current;
+    }
+    pub fn set_modes(&mut self, repeat: RepeatMode, shuffle: ShuffleMode) {
+        self.modes = format!("{}, {}", repeat.name(), shuffle.name());
+    }
+    pub fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+    }
+    pub fn select(&mut self, index: usize) {
+        self.list_state.select(Some(index));
+    }
+    fn next(&mut self) {
+        if self.tracks.is_empty() {
+            return;
+        }
+        let i = match self.list_state.selected() {
+            Some(i) if i + 1 < self.tracks.len() => i + 1,
+            _ => 0,
+        };
+        self.list_state.select(Some(i));
+    }
+    fn prev(&mut self) {
+        if self.tracks.is_empty() {
+            return;
+        }
+        let i = match self.list_state.selected() {
+            Some(i) if i > 0 => i - 1,
+            _ => self.tracks.len() - 1,
+        };
+        self.list_state.select(Some(i));
+    }
+}
+
+#[async_trait]
+impl Page for QueueList {
+    fn render(&mut self, frame: &mut Frame, rect: Rect) {
+        let border_style = if self.focused {
+            Style::default().fg(Color::Cyan)
+        } else {
+            Style::default()
+        };
+        let mut title = format!("Queue ({}) [{}]", self.tracks.len(), self.modes);
+        if self.focused {
+            title.push_str(" (i: import, x: export)");
+        }
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(border_style)
+            .title(title);
+        if self.tracks.is_empty() {
+            let empty_text = Paragraph::new("Queue is empty")
+                .alignment(Alignment::Center)
+                .block(block);
+            frame.render_widget(empty_text, rect);
+            return;
+        }
+        let current_style = Style::default()
+            .fg(Color::Green)
+            .add_modifier(Modifier::BOLD);
+        let items: Vec<ListItem<'_>> = self
+            .tracks
+            .iter()
+            .enumerate()
+            .map(|(i, track)| match self.current {
+                Some(current) if current == i => {
+                    ListItem::new(format!("♪ {}", track.display_name())).style(current_style)
+                }
+                _ => ListItem::new(format!("  {}", track.display_name())),
+            })
+            .collect();
+        let mut list = List::new(items).block(block);
+        // Only show where the cursor is while it can be moved
+        if self.focused {
+            list = list.highlight_symbol("> ").highlight_style(
+                Style::default()
+                    .fg(Color::White)
+                    .add_modifier(Modifier::BOLD),
+            );
+        }
+        frame.render_stateful_widget(list, rect, &mut self.list_state);
+    }
+}
+
+#[async_trait]
+impl StatefulPage for QueueList {
+    type State = Option<usize>;
+    type Message = QueueListMsg;
+    async fn update(&mut self, msg: Self::Message) -> Option<Self::Message> {
+        match msg {
+            QueueListMsg::Increment => self.next(),
+            QueueListMsg::Decrement => self.prev(),
+            QueueListMsg::Play(_)
+            | QueueListMsg::Remove(_)
+            | QueueListMsg::Move(_, _)
+            | QueueListMsg::Clear
+            | QueueListMsg::Import
+            | QueueListMsg::Export => {}
+        }
+        None
+    }
+    async fn handle_events(&mut self, event: AppEvent) -> Option<Self::Message> {
+        let AppEvent::Key(x) = event else {
+            return None;
+        };
+        let selected = self.get_state().filter(|f| *f < self.tracks.len());
+        match (x, selected) {
+            (KeyCode::Char('j') | KeyCode::Down, _) => Some(QueueListMsg::Increment),
+            (KeyCode::Char('k') | KeyCode::Up, _) => Some(QueueListMsg::Decrement),
+            (KeyCode::Enter, Some(i)) => Some(QueueListMsg::Play(i)),
+            (KeyCode::Char('d') | KeyCode::Delete, Some(i)) => Some(QueueListMsg::Remove(i)),
+            // Shift drags the selected track along
+            (KeyCode::Char('J'), Some(i)) if i + 1 < self.tracks.len() => {
+                Some(QueueListMsg::Move(i, i + 1))
+            }
+            (KeyCode::Char('K'), Some(i)) if i > 0 => Some(QueueListMsg::Move(i, i - 1)),
+            (KeyCode::Char('C'), _) => Some(QueueListMsg::Clear),
+            (KeyCode::Char('i'), _) => Some(QueueListMsg::Import),
+            (KeyCode::Char('x'), _) if !self.tracks.is_empty() => Some(QueueListMsg::Export),
+            _ => None,
+        }
+    }
+    fn get_state(&self) -> Self::State {
+        self.list_state.selected()
+    }
+}
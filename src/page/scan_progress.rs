@@ -0,0 +1,47 @@
+use async_trait::async_trait;
+use ratatui::{
+    prelude::Rect,
+    style::{Color, Style},
+    widgets::{Block, Borders, Gauge},
+    Frame,
+};
+
+use crate::db::audio_scanner::ScanProgress;
+
+use super::Page;
+
+#[derive(Debug, Default)]
+pub struct ScanProgressBar {
+    progress: ScanProgress,
+}
+
+impl ScanProgressBar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn set_progress(&mut self, progress: ScanProgress) {
+        self.progress = progress;
+    }
+}
+
+#[async_trait]
+impl Page for ScanProgressBar {
+    fn render(&mut self, frame: &mut Frame, rect: Rect) {
+        let progress = self.progress;
+        let label = format!(
+            "{} dirs, {}/{} files, {} probed, {} errors, {} broken links (Esc to cancel)",
+            progress.dirs_visited,
+            progress.files_done,
+            progress.files_found,
+            progress.files_probed,
+            progress.errors,
+            progress.broken_links
+        );
+        let gauge = Gauge::default()
+            .block(Block::default().borders(Borders::ALL).title("Scanning"))
+            .gauge_style(Style::default().fg(Color::Cyan))
+            .ratio(progress.ratio())
+            .label(label);
+        frame.render_widget(gauge, rect);
+    }
+}
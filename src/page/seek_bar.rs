@@ -0,0 +1,144 @@
+use std::{path::PathBuf, time::Duration};
+
+use async_trait::async_trait;
+use ratatui::{
+    prelude::Rect,
+    style::{Color, Style},
+    widgets::{Block, Borders},
+    Frame,
+};
+
+use crate::{audio::envelope::Envelope, db::AudioData};
+
+use super::Page;
+
+// Eighths of a cell, from empty to full
+const BAR_SYMBOLS: [&str; 9] = [" ", "▁", "▂", "▃", "▄", "▅", "▆", "▇", "█"];
+
+#[derive(Debug, Default)]
+pub struct SeekBar {
+    // Path and start of the track, CUE sheet tracks share their file
+    source: Option<(PathBuf, Option<Duration>)>,
+    length: Option<Duration>,
+    position: Option<Duration>,
+    envelope: Option<Envelope>,
+    // Where the bars were drawn, for mouse clicks
+    area: Option<Rect>,
+}
+
+impl SeekBar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn set_track(&mut self, track: Option<&AudioData>) {
+        self.source = track.map(|f| (f.path.clone(), f.start));
+        self.length = track.and_then(|f| f.duration);
+        self.position = None;
+        self.envelope = None;
+    }
+    pub fn is_track(&self, track: &AudioData) -> bool {
+        self.source
+            .as_ref()
+            .is_some_and(|(path, start)| *path == track.path && *start == track.start)
+    }
+    pub fn has_track(&self) -> bool {
+        self.source.is_some()
+    }
+    pub fn has_envelope(&self) -> bool {
+        self.envelope.is_some()
+    }
+    pub fn set_envelope(&mut self, envelope: Option<Envelope>) {
+        self.envelope = envelope;
+    }
+    // Not rendered this frame, clicks go elsewhere
+    pub fn hide(&mut self) {
+        self.area = None;
+    }
+    pub fn set_position(&mut self, position: Option<Duration>) {
+        self.position = position;
+    }
+    // Position in the track under a click, None outside of the bars
+    pub fn target(&self, column: u16, row: u16) -> Option<Duration> {
+        let area = self.area?;
+        let length = self.length?;
+        let inside = (area.x..area.x + area.width).contains(&column)
+            && (area.y..area.y + area.height).contains(&row);
+        if !inside {
+            return None;
+        }
+        let ratio = (column - area.x) as f64 / area.width.max(1) as f64;
+        Some(length.mul_f64(ratio))
+    }
+    fn progress(&self) -> f64 {
+        match (self.position, self.length) {
+            (Some(position), Some(length)) if !length.is_zero() => {
+                (position.as_secs_f64() / length.as_secs_f64()).clamp(0.0, 1.0)
+            }
+            _ => 0.0,
+        }
+    }
+    fn render_envelope(&self, frame: &mut Frame, area: Rect) {
+        let Some(ref envelope) = self.envelope else {
+            return;
+        };
+        let played = (self.progress() * area.width as f64).round() as u16;
+        let buffer = frame.buffer_mut();
+        let eighths = area.height as f64 * 8.0;
+        for x in 0..area.width {
+            let from = x as f64 / area.width as f64;
+            let to = (x + 1) as f64 / area.width as f64;
+            let (peak, rms) = envelope.range(from, to);
+            // Peaks outline the bar, the louder RMS part inside is brighter
+            let peak = (peak as f64 / 255.0 * eighths).round().max(1.0) as u16;
+            let rms = (rms as f64 / 255.0 * eighths).round() as u16;
+            for y in 0..area.height {
+                let level = peak.saturating_sub(y * 8).min(8);
+                let color = match (x < played, rms > y * 8) {
+                    (true, true) => Color::Cyan,
+                    (true, false) => Color::Blue,
+                    (false, true) => Color::Gray,
+                    (false, false) => Color::DarkGray,
+                };
+                buffer
+                    .get_mut(area.x + x, area.y + area.height - 1 - y)
+                    .set_symbol(BAR_SYMBOLS[level as usize])
+                    .set_fg(color);
+            }
+        }
+    }
+    // Until the outline is ready
+    fn render_line(&self, frame: &mut Frame, area: Rect) {
+        let played = (self.progress() * area.width as f64).round() as u16;
+        let buffer = frame.buffer_mut();
+        let y = area.y + area.height / 2;
+        for x in 0..area.width {
+            let style = if x < played {
+                Style::default().fg(Color::Cyan)
+            } else {
+                Style::default().fg(Color::DarkGray)
+            };
+            buffer
+                .get_mut(area.x + x, y)
+                .set_symbol("━")
+                .set_style(style);
+        }
+    }
+}
+
+#[async_trait]
+impl Page for SeekBar {
+    fn render(&mut self, frame: &mut Frame, rect: Rect) {
+        // Times are on the now playing bar right below
+        let block = Block::default().borders(Borders::ALL);
+        let area = block.inner(rect);
+        frame.render_widget(block, rect);
+        self.area = Some(area).filter(|f| f.width > 0 && f.height > 0);
+        if self.area.is_none() {
+            return;
+        }
+        match self.envelope {
+            Some(_) => self.render_envelope(frame, area),
+            None => self.render_line(frame, area),
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use crossterm::event::KeyCode;
+use ratatui::{
+    prelude::{Constraint, Direction, Layout, Rect},
+    style::{Color, Style},
+    widgets::{BarChart, Block, Borders, List, ListItem},
+    Frame,
+};
+
+use crate::{
+    db::history::{unix_millis, HistoryStats, PlayCount},
+    event::AppEvent,
+};
+
+use super::{Msg, Page, StatefulPage};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum StatisticsMsg {
+    // Handled by the app, it has the library
+    ExportCsv,
+    ExportJson,
+}
+
+impl Msg for StatisticsMsg {}
+
+#[derive(Debug, Default)]
+pub struct Statistics {
+    stats: HistoryStats,
+    // Outcome of the last export
+    status: Option<String>,
+}
+
+impl Statistics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn set_stats(&mut self, stats: HistoryStats) {
+        self.stats = stats;
+    }
+    pub fn set_status(&mut self, status: String) {
+        self.status = Some(status);
+    }
+    fn count_items(counts: &[PlayCount]) -> Vec<ListItem<'_>> {
+        counts
+            .iter()
+            .map(|f| {
+                ListItem::new(format!(
+                    "{:>4}x {} ({})",
+                    f.plays,
+                    f.label,
+                    format_duration(f.listened)
+                ))
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Page for Statistics {
+    fn render(&mut self, frame: &mut Frame, rect: Rect) {
+        let title = match self.status {
+            Some(ref status) => format!(
+                "Statistics, {} listened ({})",
+                format_duration(self.stats.total),
+                status
+            ),
+            None => format!(
+                "Statistics, {} listened (e: export CSV, E: export JSON)",
+                format_duration(self.stats.total)
+            ),
+        };
+        let outer = Block::default().borders(Borders::ALL).title(title);
+        let inner = outer.inner(rect);
+        frame.render_widget(outer, rect);
+        let rows = Layout::default()
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .split(inner);
+        let top = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .split(rows[0]);
+        let bottom = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .split(rows[1]);
+        let top_tracks = List::new(Self::count_items(&self.stats.top_tracks))
+            .block(Block::default().borders(Borders::ALL).title("Most played"));
+        frame.render_widget(top_tracks, top[0]);
+        let top_artists = List::new(Self::count_items(&self.stats.top_artists))
+            .block(Block::default().borders(Borders::ALL).title("Top artists"));
+        frame.render_widget(top_artists, top[1]);
+        // Oldest week on the left, in minutes
+        let weeks: Vec<(String, u64)> = self
+            .stats
+            .weeks
+            .iter()
+            .rev()
+            .map(|(week, listened)| {
+                let label = week.get(5..).unwrap_or(week).to_string();
+                (label, listened.as_secs() / 60)
+            })
+            .collect();
+        let week_data: Vec<(&str, u64)> = weeks.iter().map(|(f, v)| (f.as_str(), *v)).collect();
+        let week_chart = BarChart::default()
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Minutes per week"),
+            )
+            .data(&week_data)
+            .bar_width(5)
+            .bar_gap(1)
+            .bar_style(Style::default().fg(Color::Cyan));
+        frame.render_widget(week_chart, bottom[0]);
+        let now = unix_millis();
+        let recent: Vec<ListItem<'_>> = self
+            .stats
+            .recent
+            .iter()
+            .map(|f| {
+                let ago = Duration::from_millis((now - f.started).max(0) as u64);
+                let name = match f.artist.is_empty() {
+                    true => f.title.clone(),
+                    false => format!("{} - {}", f.artist, f.title),
+                };
+                let skipped = if f.completed { "" } else { " (skipped)" };
+                ListItem::new(format!("{:>4} {}{}", format_ago(ago), name, skipped))
+            })
+            .collect();
+        let recent = List::new(recent).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Recently played"),
+        );
+        frame.render_widget(recent, bottom[1]);
+    }
+}
+
+#[async_trait]
+impl StatefulPage for Statistics {
+    type State = Option<String>;
+    type Message = StatisticsMsg;
+    async fn update(&mut self, msg: Self::Message) -> Option<Self::Message> {
+        match msg {
+            StatisticsMsg::ExportCsv | StatisticsMsg::ExportJson => {}
+        }
+        None
+    }
+    async fn handle_events(&mut self, event: AppEvent) -> Option<Self::Message> {
+        match event {
+            AppEvent::Key(KeyCode::Char('e')) => Some(StatisticsMsg::ExportCsv),
+            AppEvent::Key(KeyCode::Char('E')) => Some(StatisticsMsg::ExportJson),
+            _ => None,
+        }
+    }
+    fn get_state(&self) -> Self::State {
+        self.status.clone()
+    }
+}
+
+// `3h 25m` or `25m`
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    if minutes < 60 {
+        return format!("{}m", minutes);
+    }
+    format!("{}h {}m", minutes / 60, minutes % 60)
+}
+
+fn format_ago(ago: Duration) -> String {
+    let seconds = ago.as_secs();
+    match seconds {
+        0..=59 => String::from("now"),
+        60..=3599 => format!("{}m", seconds / 60),
+        3600..=86399 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
@@ -0,0 +1,253 @@
+use async_trait::async_trait;
+use crossterm::event::KeyCode;
+use ratatui::{
+    prelude::Rect,
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph},
+    Frame,
+};
+
+use crate::{
+    db::{metadata::TagEdit, AudioData},
+    event::AppEvent,
+};
+
+use super::{Msg, Page, StatefulPage};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum TagEditorMsg {
+    NextField,
+    PrevField,
+    Input(char),
+    Backspace,
+    Submit,
+    // Handled by the app, it writes the files and updates the library
+    Save(Vec<AudioData>, Box<TagEdit>),
+    Cancel,
+}
+
+impl Msg for TagEditorMsg {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TagField {
+    Title,
+    Artist,
+    Album,
+    Track,
+    Genre,
+}
+
+impl TagField {
+    const ALL: [Self; 5] = [
+        Self::Title,
+        Self::Artist,
+        Self::Album,
+        Self::Track,
+        Self::Genre,
+    ];
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Title => "Title",
+            Self::Artist => "Artist",
+            Self::Album => "Album",
+            Self::Track => "Track",
+            Self::Genre => "Genre",
+        }
+    }
+    fn value(&self, data: &AudioData) -> String {
+        match self {
+            Self::Title => data.name.clone(),
+            Self::Artist => data.author.clone(),
+            Self::Album => data.album.clone(),
+            Self::Track => data.track.map(|f| f.to_string()).unwrap_or_default(),
+            Self::Genre => data.genre.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TagInput {
+    field: TagField,
+    value: String,
+    // The tracks disagree, untouched it keeps whatever each track has
+    mixed: bool,
+    edited: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct TagEditor {
+    tracks: Vec<AudioData>,
+    inputs: Vec<TagInput>,
+    selected: usize,
+    error: Option<String>,
+    // Files written and how many there are, while the app writes them
+    progress: Option<(usize, usize)>,
+}
+
+impl TagEditor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn open(&mut self, tracks: Vec<AudioData>) {
+        self.inputs = TagField::ALL
+            .iter()
+            .map(|field| {
+                let mut values = tracks.iter().map(|f| field.value(f));
+                let first = values.next().unwrap_or_default();
+                let mixed = values.any(|f| f != first);
+                TagInput {
+                    field: *field,
+                    value: if mixed { String::new() } else { first },
+                    mixed,
+                    edited: false,
+                }
+            })
+            .collect();
+        self.tracks = tracks;
+        self.selected = 0;
+        self.error = None;
+        self.progress = None;
+    }
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+    pub fn set_progress(&mut self, progress: Option<(usize, usize)>) {
+        self.progress = progress;
+    }
+    fn edit(&self) -> Result<TagEdit, String> {
+        let mut edit = TagEdit::default();
+        for input in self.inputs.iter().filter(|f| f.edited) {
+            let value = Some(input.value.trim().to_string());
+            match input.field {
+                TagField::Title => edit.title = value,
+                TagField::Artist => edit.artist = value,
+                TagField::Album => edit.album = value,
+                TagField::Track => {
+                    if !input.value.trim().is_empty() && input.value.trim().parse::<u32>().is_err()
+                    {
+                        return Err(String::from("Track has to be a number"));
+                    }
+                    edit.track = value;
+                }
+                TagField::Genre => edit.genre = value,
+            }
+        }
+        Ok(edit)
+    }
+}
+
+#[async_trait]
+impl Page for TagEditor {
+    fn render(&mut self, frame: &mut Frame, rect: Rect) {
+        let title = match self.tracks.len() {
+            1 => format!("Edit tags ({})", self.tracks[0].path.display()),
+            n => format!("Edit tags ({} tracks)", n),
+        };
+        let selected_style = Style::default()
+            .fg(Color::White)
+            .add_modifier(Modifier::BOLD);
+        let mixed_style = Style::default().fg(Color::DarkGray);
+        let mut lines: Vec<Line<'_>> = self
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(i, input)| {
+                let selected = i == self.selected;
+                let label = format!(
+                    "{}{:<8}",
+                    if selected { "> " } else { "  " },
+                    input.field.name()
+                );
+                let mut spans = Vec::from([Span::styled(
+                    label,
+                    if selected {
+                        selected_style
+                    } else {
+                        Style::default()
+                    },
+                )]);
+                if input.mixed && !input.edited {
+                    spans.push(Span::styled("(multiple values)", mixed_style));
+                } else {
+                    spans.push(Span::raw(input.value.clone()));
+                }
+                if selected {
+                    spans.push(Span::raw("_"));
+                }
+                Line::from(spans)
+            })
+            .collect();
+        lines.push(Line::from(""));
+        match (self.progress, &self.error) {
+            (Some((done, count)), _) => lines.push(Line::from(Span::styled(
+                format!("Writing {} files... {} done", count, done),
+                Style::default().fg(Color::Yellow),
+            ))),
+            (None, Some(error)) => lines.push(Line::from(Span::styled(
+                error.clone(),
+                Style::default().fg(Color::Red),
+            ))),
+            (None, None) => lines.push(Line::from(Span::styled(
+                "Tab: next field, Enter: write to the files, Esc: cancel",
+                mixed_style,
+            ))),
+        }
+        let editor =
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title));
+        frame.render_widget(editor, rect);
+    }
+}
+
+#[async_trait]
+impl StatefulPage for TagEditor {
+    type State = usize;
+    type Message = TagEditorMsg;
+    async fn update(&mut self, msg: Self::Message) -> Option<Self::Message> {
+        match msg {
+            TagEditorMsg::NextField => self.selected = (self.selected + 1) % self.inputs.len(),
+            TagEditorMsg::PrevField => {
+                self.selected = (self.selected + self.inputs.len() - 1) % self.inputs.len()
+            }
+            TagEditorMsg::Input(c) => {
+                let input = &mut self.inputs[self.selected];
+                input.value.push(c);
+                input.edited = true;
+            }
+            // Backspace on an empty mixed field clears it on all tracks
+            TagEditorMsg::Backspace => {
+                let input = &mut self.inputs[self.selected];
+                input.value.pop();
+                input.edited = true;
+            }
+            TagEditorMsg::Submit => match self.edit() {
+                Ok(edit) if edit.is_empty() => return Some(TagEditorMsg::Cancel),
+                Ok(edit) => return Some(TagEditorMsg::Save(self.tracks.clone(), Box::new(edit))),
+                Err(e) => self.error = Some(e),
+            },
+            TagEditorMsg::Save(_, _) | TagEditorMsg::Cancel => {}
+        }
+        None
+    }
+    async fn handle_events(&mut self, event: AppEvent) -> Option<Self::Message> {
+        let AppEvent::Key(x) = event else {
+            return None;
+        };
+        // The fields are being written, the rest of the app keeps working meanwhile
+        if self.progress.is_some() {
+            return None;
+        }
+        match x {
+            KeyCode::Tab | KeyCode::Down => Some(TagEditorMsg::NextField),
+            KeyCode::BackTab | KeyCode::Up => Some(TagEditorMsg::PrevField),
+            KeyCode::Char(c) => Some(TagEditorMsg::Input(c)),
+            KeyCode::Backspace => Some(TagEditorMsg::Backspace),
+            KeyCode::Enter => Some(TagEditorMsg::Submit),
+            KeyCode::Esc => Some(TagEditorMsg::Cancel),
+            _ => None,
+        }
+    }
+    fn get_state(&self) -> Self::State {
+        self.selected
+    }
+}
@@ -0,0 +1,125 @@
+use async_trait::async_trait;
+use ratatui::{
+    prelude::Rect,
+    style::Color,
+    symbols::Marker,
+    widgets::{
+        canvas::{Canvas, Points},
+        Block, Borders,
+    },
+    Frame,
+};
+
+use crate::audio::spectrum::SpectrumAnalyzer;
+
+use super::Page;
+
+// Eighths of a cell, from empty to full
+const BAR_SYMBOLS: [&str; 9] = [" ", "▁", "▂", "▃", "▄", "▅", "▆", "▇", "█"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VisualizerMode {
+    #[default]
+    Off,
+    Spectrum,
+    Oscilloscope,
+}
+
+impl VisualizerMode {
+    pub fn next(&self) -> Self {
+        match self {
+            Self::Off => Self::Spectrum,
+            Self::Spectrum => Self::Oscilloscope,
+            Self::Oscilloscope => Self::Off,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Visualizer {
+    mode: VisualizerMode,
+    analyzer: SpectrumAnalyzer,
+    sample_rate: u32,
+}
+
+impl Visualizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn mode(&self) -> VisualizerMode {
+        self.mode
+    }
+    pub fn set_mode(&mut self, mode: VisualizerMode) {
+        self.mode = mode;
+    }
+    pub fn push<I: IntoIterator<Item = f32>>(&mut self, samples: I, sample_rate: u32) {
+        self.analyzer.push(samples);
+        self.sample_rate = sample_rate;
+    }
+    pub fn silence(&mut self) {
+        self.analyzer.silence();
+    }
+    fn render_spectrum(&mut self, frame: &mut Frame, area: Rect) {
+        if area.width == 0 || area.height == 0 {
+            return;
+        }
+        let bands = self.analyzer.bands(area.width as usize, self.sample_rate);
+        let buffer = frame.buffer_mut();
+        for (x, level) in bands.iter().enumerate() {
+            let mut eighths = (level * area.height as f32 * 8.0).round() as u16;
+            for y in (0..area.height).rev() {
+                // Quiet at the bottom, loud at the top
+                let color = match (area.height - y) * 3 / area.height.max(1) {
+                    0 => Color::Green,
+                    1 => Color::Yellow,
+                    _ => Color::Red,
+                };
+                let symbol = BAR_SYMBOLS[eighths.min(8) as usize];
+                eighths = eighths.saturating_sub(8);
+                buffer
+                    .get_mut(area.x + x as u16, area.y + y)
+                    .set_symbol(symbol)
+                    .set_fg(color);
+            }
+        }
+    }
+    fn render_oscilloscope(&mut self, frame: &mut Frame, block: Block, rect: Rect) {
+        let coords: Vec<(f64, f64)> = self
+            .analyzer
+            .waveform()
+            .enumerate()
+            .map(|(i, sample)| (i as f64, sample.clamp(-1.0, 1.0) as f64))
+            .collect();
+        let scope = Canvas::default()
+            .block(block)
+            .marker(Marker::Braille)
+            .x_bounds([0.0, coords.len().max(1) as f64])
+            .y_bounds([-1.0, 1.0])
+            .paint(|ctx| {
+                ctx.draw(&Points {
+                    coords: &coords,
+                    color: Color::Cyan,
+                })
+            });
+        frame.render_widget(scope, rect);
+    }
+}
+
+#[async_trait]
+impl Page for Visualizer {
+    fn render(&mut self, frame: &mut Frame, rect: Rect) {
+        let block = Block::default().borders(Borders::ALL);
+        match self.mode {
+            VisualizerMode::Off => {}
+            VisualizerMode::Spectrum => {
+                let block = block.title("Spectrum");
+                let area = block.inner(rect);
+                frame.render_widget(block, rect);
+                self.render_spectrum(frame, area);
+            }
+            VisualizerMode::Oscilloscope => {
+                self.render_oscilloscope(frame, block.title("Oscilloscope"), rect)
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use super::{PathResolver, Playlist, PlaylistEntry};
+
+pub fn parse(contents: &str, resolver: &PathResolver) -> Playlist {
+    let mut playlist = Playlist::new(None);
+    // `#EXTINF` describes the next location line
+    let mut pending_info: Option<(Option<Duration>, Option<String>)> = None;
+    for line in contents.lines() {
+        let line = line.trim().trim_start_matches('\u{feff}');
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            pending_info = Some(parse_extinf(info));
+            continue;
+        }
+        if let Some(name) = line.strip_prefix("#PLAYLIST:") {
+            playlist.name = Some(name.trim().to_string());
+            continue;
+        }
+        // Any other directive (or comment) is ignored
+        if line.starts_with('#') {
+            continue;
+        }
+        let mut entry = PlaylistEntry::new(resolver.resolve(line));
+        if let Some((duration, title)) = pending_info.take() {
+            entry.duration = duration;
+            entry.title = title;
+        }
+        playlist.entries.push(entry);
+    }
+    playlist
+}
+
+// `#EXTINF:<seconds> [attributes],<title>`; -1 seconds means unknown
+fn parse_extinf(info: &str) -> (Option<Duration>, Option<String>) {
+    let (head, title) = info.split_once(',').unwrap_or((info, ""));
+    let duration = head
+        .split_whitespace()
+        .next()
+        .and_then(|f| f.parse::<f64>().ok())
+        .filter(|f| *f >= 0f64)
+        .map(Duration::from_secs_f64);
+    let title = Some(title.trim())
+        .filter(|f| !f.is_empty())
+        .map(String::from);
+    (duration, title)
+}
+
+pub fn write(playlist: &Playlist, resolver: &PathResolver) -> String {
+    let mut contents = String::from("#EXTM3U\n");
+    if let Some(ref name) = playlist.name {
+        contents.push_str(&format!("#PLAYLIST:{}\n", name));
+    }
+    for entry in playlist.entries.iter() {
+        if entry.duration.is_some() || entry.title.is_some() {
+            let seconds = entry
+                .duration
+                .map_or(-1i64, |f| f.as_secs_f64().round() as i64);
+            contents.push_str(&format!(
+                "#EXTINF:{},{}\n",
+                seconds,
+                entry.title.as_deref().unwrap_or_default()
+            ));
+        }
+        contents.push_str(&resolver.relative(&entry.location));
+        contents.push('\n');
+    }
+    contents
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::playlist::PlaylistLocation;
+
+    #[test]
+    fn parses_extinf() {
+        assert_eq!(
+            parse_extinf("123,Artist - Title"),
+            (
+                Some(Duration::from_secs(123)),
+                Some(String::from("Artist - Title"))
+            )
+        );
+        // Attributes come before the comma, titles can have commas of their own
+        assert_eq!(
+            parse_extinf("61 tvg-id=\"x\",Last, First"),
+            (
+                Some(Duration::from_secs(61)),
+                Some(String::from("Last, First"))
+            )
+        );
+        assert_eq!(parse_extinf("-1,"), (None, None));
+    }
+
+    #[test]
+    fn extinf_describes_the_next_location() {
+        let resolver = PathResolver::new("/music/list.m3u8", None);
+        let playlist = parse(
+            "\u{feff}#EXTM3U\n#PLAYLIST:Mix\n#EXTINF:200,A - B\n\n# comment\na.flac\nb.flac\n",
+            &resolver,
+        );
+        assert_eq!(playlist.name.as_deref(), Some("Mix"));
+        assert_eq!(
+            playlist.entries,
+            Vec::from([
+                PlaylistEntry {
+                    location: PlaylistLocation::File(PathBuf::from("/music/a.flac")),
+                    title: Some(String::from("A - B")),
+                    duration: Some(Duration::from_secs(200)),
+                },
+                PlaylistEntry::new(PlaylistLocation::File(PathBuf::from("/music/b.flac"))),
+            ])
+        );
+    }
+
+    #[test]
+    fn writes_extinf_only_when_known() {
+        let resolver = PathResolver::new("/music/list.m3u8", None);
+        let mut playlist = Playlist::new(None);
+        playlist.entries.push(PlaylistEntry {
+            location: PlaylistLocation::File(PathBuf::from("/music/a.flac")),
+            title: Some(String::from("A - B")),
+            duration: None,
+        });
+        playlist
+            .entries
+            .push(PlaylistEntry::new(PlaylistLocation::File(PathBuf::from(
+                "/elsewhere/b.flac",
+            ))));
+        assert_eq!(
+            write(&playlist, &resolver),
+            "#EXTM3U\n#EXTINF:-1,A - B\na.flac\n/elsewhere/b.flac\n"
+        );
+    }
+}
@@ -0,0 +1,450 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::AudioData;
+
+pub mod m3u;
+pub mod pls;
+pub mod xspf;
+
+#[derive(Debug)]
+pub enum PlaylistError {
+    Io(std::io::Error),
+    Xml(quick_xml::Error),
+    UnknownFormat(PathBuf),
+    Parse(String),
+}
+
+impl std::fmt::Display for PlaylistError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "PlaylistIoError: {}", e),
+            Self::Xml(e) => write!(f, "PlaylistXmlError: {}", e),
+            Self::UnknownFormat(path) => {
+                write!(f, "UnknownPlaylistFormat: {}", path.display())
+            }
+            Self::Parse(e) => write!(f, "PlaylistParseError: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PlaylistError {}
+
+impl From<std::io::Error> for PlaylistError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<quick_xml::Error> for PlaylistError {
+    fn from(value: quick_xml::Error) -> Self {
+        Self::Xml(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    M3u,
+    Pls,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "m3u" | "m3u8" => Some(Self::M3u),
+            "pls" => Some(Self::Pls),
+            "xspf" => Some(Self::Xspf),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlaylistLocation {
+    File(PathBuf),
+    Url(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlaylistEntry {
+    pub location: PlaylistLocation,
+    pub title: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+impl PlaylistEntry {
+    pub fn new(location: PlaylistLocation) -> Self {
+        Self {
+            location,
+            title: None,
+            duration: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Playlist {
+    pub name: Option<String>,
+    pub entries: Vec<PlaylistEntry>,
+}
+
+impl Playlist {
+    pub fn new(name: Option<String>) -> Self {
+        Self {
+            name,
+            entries: Vec::new(),
+        }
+    }
+    pub fn from_tracks(name: Option<String>, tracks: &[AudioData]) -> Self {
+        let entries = tracks
+            .iter()
+            .map(|track| PlaylistEntry {
+                location: PlaylistLocation::File(track.path.clone()),
+                title: Some(track.display_name()),
+                duration: track.duration,
+            })
+            .collect();
+        Self { name, entries }
+    }
+    // Entries as tracks, taken from `known` (the library) when they are in there,
+    // together with how many entries couldnt be found
+    pub fn tracks(&self, known: &[AudioData]) -> (Vec<AudioData>, usize) {
+        let mut by_path: HashMap<&Path, Vec<&AudioData>> = HashMap::new();
+        for track in known {
+            by_path.entry(track.path.as_path()).or_default().push(track);
+        }
+        let mut tracks: Vec<AudioData> = Vec::new();
+        let mut missing = 0;
+        for entry in self.entries.iter() {
+            let PlaylistLocation::File(ref path) = entry.location else {
+                // The queue only plays files
+                missing += 1;
+                continue;
+            };
+            match by_path.get(path.as_path()) {
+                // CUE sheet tracks share their file, the title tells them apart
+                Some(found) => match found
+                    .iter()
+                    .find(|f| entry.title.as_ref() == Some(&f.display_name()))
+                {
+                    Some(track) => tracks.push((*track).clone()),
+                    None => tracks.extend(found.iter().map(|f| (*f).clone())),
+                },
+                None if path.is_file() => {
+                    let mut track = AudioData::new(path.clone());
+                    track.duration = entry.duration;
+                    tracks.push(track);
+                }
+                None => missing += 1,
+            }
+        }
+        (tracks, missing)
+    }
+    // Relative entries are looked up next to the playlist first, then in `music_dir`
+    pub fn read<P: AsRef<Path>>(path: P, music_dir: Option<&Path>) -> Result<Self, PlaylistError> {
+        let path = path.as_ref();
+        let format = PlaylistFormat::from_path(path)
+            .ok_or_else(|| PlaylistError::UnknownFormat(path.to_path_buf()))?;
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut file_buffer: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut file_buffer)?;
+        // Old M3U files tend not to be UTF-8, dont fail on them
+        let contents = String::from_utf8_lossy(&file_buffer);
+        let resolver = PathResolver::new(path, music_dir);
+        let mut playlist = match format {
+            PlaylistFormat::M3u => m3u::parse(&contents, &resolver),
+            PlaylistFormat::Pls => pls::parse(&contents, &resolver),
+            PlaylistFormat::Xspf => xspf::parse(&contents, &resolver)?,
+        };
+        if playlist.name.is_none() {
+            playlist.name = path.file_stem().and_then(|f| f.to_str()).map(String::from);
+        }
+        Ok(playlist)
+    }
+    // Entries below the playlist directory are written relative to it
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), PlaylistError> {
+        let path = path.as_ref();
+        let format = PlaylistFormat::from_path(path)
+            .ok_or_else(|| PlaylistError::UnknownFormat(path.to_path_buf()))?;
+        let resolver = PathResolver::new(path, None);
+        let contents = match format {
+            PlaylistFormat::M3u => m3u::write(self, &resolver),
+            PlaylistFormat::Pls => pls::write(self, &resolver),
+            PlaylistFormat::Xspf => xspf::write(self, &resolver)?,
+        };
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(contents.as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+pub struct PathResolver {
+    base_dir: PathBuf,
+    music_dir: Option<PathBuf>,
+}
+
+impl PathResolver {
+    pub fn new<P: AsRef<Path>>(playlist_path: P, music_dir: Option<&Path>) -> Self {
+        let base_dir = playlist_path
+            .as_ref()
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        Self {
+            base_dir,
+            music_dir: music_dir.map(Path::to_path_buf),
+        }
+    }
+    pub fn resolve(&self, location: &str) -> PlaylistLocation {
+        let location = location.trim();
+        if let Some(path) = location.strip_prefix("file://") {
+            return PlaylistLocation::File(PathBuf::from(percent_decode(path)));
+        }
+        if location.contains("://") {
+            return PlaylistLocation::Url(location.to_string());
+        }
+        // Windows playlists are everywhere, so accept their separators as well
+        let path = PathBuf::from(location.replace('\\', std::path::MAIN_SEPARATOR_STR));
+        if path.is_absolute() {
+            return PlaylistLocation::File(path);
+        }
+        let next_to_playlist = self.base_dir.join(&path);
+        if !next_to_playlist.exists() {
+            if let Some(ref music_dir) = self.music_dir {
+                let in_music_dir = music_dir.join(&path);
+                if in_music_dir.exists() {
+                    return PlaylistLocation::File(normalize(&in_music_dir));
+                }
+            }
+        }
+        PlaylistLocation::File(normalize(&next_to_playlist))
+    }
+    pub fn relative(&self, location: &PlaylistLocation) -> String {
+        match location {
+            PlaylistLocation::Url(url) => url.clone(),
+            PlaylistLocation::File(path) => match path.strip_prefix(&self.base_dir) {
+                Ok(relative) if !self.base_dir.as_os_str().is_empty() => {
+                    relative.display().to_string()
+                }
+                _ => path.display().to_string(),
+            },
+        }
+    }
+}
+
+// Drop `.` and `..` without touching the filesystem
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                _ => normalized.push(component),
+            },
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+// Minimal URI escaping for `file://` locations, keeps `/` as is
+pub fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Fresh directory per test, the resolver checks what exists
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "music_player_playlist_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn touch(path: &Path) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(path).unwrap();
+    }
+
+    fn sample_playlist(dir: &Path) -> Playlist {
+        Playlist {
+            name: Some(String::from("Road trip")),
+            entries: Vec::from([
+                PlaylistEntry {
+                    location: PlaylistLocation::File(dir.join("Artist/Album/01 Intro.flac")),
+                    title: Some(String::from("Artist - Intro")),
+                    duration: Some(Duration::from_secs(95)),
+                },
+                PlaylistEntry {
+                    location: PlaylistLocation::File(dir.join("Other & Co/02 Song.mp3")),
+                    title: Some(String::from("Other & Co - Song")),
+                    duration: Some(Duration::from_secs(201)),
+                },
+                PlaylistEntry::new(PlaylistLocation::Url(String::from(
+                    "http://radio.example.com/stream",
+                ))),
+            ]),
+        }
+    }
+
+    fn round_trip(extension: &str) {
+        let dir = test_dir(extension);
+        let playlist = sample_playlist(&dir);
+        let path = dir.join(format!("road trip.{}", extension));
+        playlist.write(&path).unwrap();
+        let read = Playlist::read(&path, None).unwrap();
+        assert_eq!(read, playlist);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn round_trips_m3u() {
+        round_trip("m3u8");
+    }
+
+    #[test]
+    fn round_trips_pls() {
+        round_trip("pls");
+    }
+
+    #[test]
+    fn round_trips_xspf() {
+        round_trip("xspf");
+    }
+
+    #[test]
+    fn writes_paths_relative_to_the_playlist() {
+        let dir = test_dir("relative");
+        let playlist = sample_playlist(&dir);
+        let path = dir.join("list.m3u");
+        playlist.write(&path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.contains(&format!(
+            "\n{}\n",
+            Path::new("Artist/Album/01 Intro.flac").display()
+        )));
+        assert!(!contents.contains(&dir.display().to_string()));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn resolves_relative_to_the_playlist_dir() {
+        let dir = test_dir("playlist_dir");
+        let resolver = PathResolver::new(dir.join("lists/list.m3u"), None);
+        assert_eq!(
+            resolver.resolve("../Artist/./01.flac"),
+            PlaylistLocation::File(dir.join("Artist/01.flac"))
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn falls_back_to_the_music_dir() {
+        let dir = test_dir("music_dir");
+        let music_dir = dir.join("music");
+        touch(&music_dir.join("Artist/01.flac"));
+        // Next to the playlist wins when it is there
+        touch(&dir.join("lists/Artist/02.flac"));
+        touch(&music_dir.join("Artist/02.flac"));
+        let resolver = PathResolver::new(dir.join("lists/list.m3u"), Some(&music_dir));
+        assert_eq!(
+            resolver.resolve("Artist/01.flac"),
+            PlaylistLocation::File(music_dir.join("Artist/01.flac"))
+        );
+        assert_eq!(
+            resolver.resolve("Artist/02.flac"),
+            PlaylistLocation::File(dir.join("lists/Artist/02.flac"))
+        );
+        // Found nowhere, so it stays next to the playlist
+        assert_eq!(
+            resolver.resolve("Artist/03.flac"),
+            PlaylistLocation::File(dir.join("lists/Artist/03.flac"))
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn accepts_windows_separators() {
+        let dir = test_dir("windows");
+        let music_dir = dir.join("music");
+        touch(&music_dir.join("Artist/Album/01.flac"));
+        let resolver = PathResolver::new(dir.join("list.m3u"), Some(&music_dir));
+        assert_eq!(
+            resolver.resolve("Artist\\Album\\01.flac"),
+            PlaylistLocation::File(music_dir.join("Artist").join("Album").join("01.flac"))
+        );
+        assert_eq!(
+            resolver.resolve("..\\shared\\02.flac"),
+            PlaylistLocation::File(dir.parent().unwrap().join("shared").join("02.flac"))
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn resolves_uris() {
+        let resolver = PathResolver::new("/music/list.m3u", None);
+        assert_eq!(
+            resolver.resolve("file:///music/A%20B/01.flac"),
+            PlaylistLocation::File(PathBuf::from("/music/A B/01.flac"))
+        );
+        assert_eq!(
+            resolver.resolve("https://example.com/live.mp3"),
+            PlaylistLocation::Url(String::from("https://example.com/live.mp3"))
+        );
+    }
+
+    #[test]
+    fn percent_encoding_round_trips() {
+        let text = "/music/Sigur Rós/01 (live) #1.flac";
+        assert_eq!(percent_decode(&percent_encode(text)), text);
+        assert_eq!(percent_encode("a b/c"), "a%20b/c");
+    }
+}
@@ -0,0 +1,99 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use super::{PathResolver, Playlist, PlaylistEntry};
+
+pub fn parse(contents: &str, resolver: &PathResolver) -> Playlist {
+    let mut playlist = Playlist::new(None);
+    // Entries are numbered and can come in any order
+    let mut entries: BTreeMap<usize, PlaylistEntry> = BTreeMap::new();
+    let mut titles: BTreeMap<usize, String> = BTreeMap::new();
+    let mut lengths: BTreeMap<usize, Duration> = BTreeMap::new();
+    for line in contents.lines() {
+        let line = line.trim().trim_start_matches('\u{feff}');
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let (key, value) = (key.trim().to_lowercase(), value.trim());
+        if key == "x-playlist-name" || key == "playlistname" {
+            playlist.name = Some(value.to_string());
+            continue;
+        }
+        let (field, index) =
+            key.split_at(key.find(|f: char| f.is_ascii_digit()).unwrap_or(key.len()));
+        let Ok(index) = index.parse::<usize>() else {
+            continue;
+        };
+        match field {
+            "file" => {
+                entries.insert(index, PlaylistEntry::new(resolver.resolve(value)));
+            }
+            "title" if !value.is_empty() => {
+                titles.insert(index, value.to_string());
+            }
+            "length" => {
+                if let Some(length) = value.parse::<i64>().ok().filter(|f| *f >= 0) {
+                    lengths.insert(index, Duration::from_secs(length as u64));
+                }
+            }
+            _ => {}
+        }
+    }
+    for (index, mut entry) in entries {
+        entry.title = titles.remove(&index);
+        entry.duration = lengths.remove(&index);
+        playlist.entries.push(entry);
+    }
+    playlist
+}
+
+pub fn write(playlist: &Playlist, resolver: &PathResolver) -> String {
+    let mut contents = String::from("[playlist]\n");
+    if let Some(ref name) = playlist.name {
+        contents.push_str(&format!("X-Playlist-Name={}\n", name));
+    }
+    for (index, entry) in playlist.entries.iter().enumerate() {
+        // PLS is 1-indexed
+        let index = index + 1;
+        contents.push_str(&format!(
+            "File{}={}\n",
+            index,
+            resolver.relative(&entry.location)
+        ));
+        if let Some(ref title) = entry.title {
+            contents.push_str(&format!("Title{}={}\n", index, title));
+        }
+        let length = entry.duration.map_or(-1i64, |f| f.as_secs() as i64);
+        contents.push_str(&format!("Length{}={}\n", index, length));
+    }
+    contents.push_str(&format!("NumberOfEntries={}\n", playlist.entries.len()));
+    contents.push_str("Version=2\n");
+    contents
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::playlist::PlaylistLocation;
+
+    #[test]
+    fn entries_keep_their_numbers() {
+        let resolver = PathResolver::new("/music/list.pls", None);
+        let playlist = parse(
+            "[playlist]\nFile2=b.flac\nTitle2=Second\nFile1=a.flac\nLength1=-1\nLength2=30\nNumberOfEntries=2\nVersion=2\n",
+            &resolver,
+        );
+        assert_eq!(
+            playlist.entries,
+            Vec::from([
+                PlaylistEntry::new(PlaylistLocation::File(PathBuf::from("/music/a.flac"))),
+                PlaylistEntry {
+                    location: PlaylistLocation::File(PathBuf::from("/music/b.flac")),
+                    title: Some(String::from("Second")),
+                    duration: Some(Duration::from_secs(30)),
+                },
+            ])
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use std::time::Duration;
+
+use quick_xml::{
+    events::{BytesText, Event},
+    Reader, Writer,
+};
+
+use super::{
+    percent_decode, percent_encode, PathResolver, Playlist, PlaylistEntry, PlaylistError,
+    PlaylistLocation,
+};
+
+#[derive(Default)]
+struct PendingTrack {
+    location: Option<String>,
+    title: Option<String>,
+    duration: Option<Duration>,
+}
+
+pub fn parse(contents: &str, resolver: &PathResolver) -> Result<Playlist, PlaylistError> {
+    let mut reader = Reader::from_str(contents);
+    reader.trim_text(true);
+    let mut playlist = Playlist::new(None);
+    let mut found_root = false;
+    let mut track: Option<PendingTrack> = None;
+    // Name of the innermost element we are in
+    let mut element: Vec<u8> = Vec::new();
+    loop {
+        let text = match reader.read_event()? {
+            Event::Start(e) => {
+                element = e.local_name().as_ref().to_vec();
+                match element.as_slice() {
+                    b"playlist" => found_root = true,
+                    b"track" => track = Some(PendingTrack::default()),
+                    _ => {}
+                }
+                continue;
+            }
+            Event::End(e) => {
+                if e.local_name().as_ref() == b"track" {
+                    if let Some(pending) = track.take() {
+                        // A track without a location is of no use to us
+                        if let Some(location) = pending.location {
+                            playlist.entries.push(PlaylistEntry {
+                                location: resolve(&location, resolver),
+                                title: pending.title,
+                                duration: pending.duration,
+                            });
+                        }
+                    }
+                }
+                element.clear();
+                continue;
+            }
+            Event::Text(e) => e.unescape()?.into_owned(),
+            Event::CData(e) => String::from_utf8_lossy(&e.into_inner()).into_owned(),
+            Event::Eof => break,
+            _ => continue,
+        };
+        match (track.as_mut(), element.as_slice()) {
+            (Some(pending), b"location") if pending.location.is_none() => {
+                pending.location = Some(text)
+            }
+            (Some(pending), b"title") => pending.title = Some(text),
+            // XSPF durations are in milliseconds
+            (Some(pending), b"duration") => {
+                pending.duration = text.trim().parse::<u64>().ok().map(Duration::from_millis)
+            }
+            (None, b"title") => playlist.name = Some(text),
+            _ => {}
+        }
+    }
+    if !found_root {
+        return Err(PlaylistError::Parse(String::from(
+            "missing <playlist> element",
+        )));
+    }
+    Ok(playlist)
+}
+
+// Locations are URIs, relative ones are relative to the playlist
+fn resolve(location: &str, resolver: &PathResolver) -> PlaylistLocation {
+    if location.contains("://") {
+        return resolver.resolve(location);
+    }
+    resolver.resolve(&percent_decode(location))
+}
+
+pub fn write(playlist: &Playlist, resolver: &PathResolver) -> Result<String, PlaylistError> {
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    writer.write_event(Event::Decl(quick_xml::events::BytesDecl::new(
+        "1.0",
+        Some("UTF-8"),
+        None,
+    )))?;
+    writer
+        .create_element("playlist")
+        .with_attribute(("version", "1"))
+        .with_attribute(("xmlns", "http://xspf.org/ns/0/"))
+        .write_inner_content(|writer| {
+            if let Some(ref name) = playlist.name {
+                writer
+                    .create_element("title")
+                    .write_text_content(BytesText::new(name))?;
+            }
+            writer
+                .create_element("trackList")
+                .write_inner_content(|writer| {
+                    for entry in playlist.entries.iter() {
+                        write_track(writer, entry, resolver)?;
+                    }
+                    Ok::<(), quick_xml::Error>(())
+                })?;
+            Ok::<(), quick_xml::Error>(())
+        })?;
+    Ok(String::from_utf8_lossy(&writer.into_inner()).into_owned())
+}
+
+fn write_track(
+    writer: &mut Writer<Vec<u8>>,
+    entry: &PlaylistEntry,
+    resolver: &PathResolver,
+) -> Result<(), quick_xml::Error> {
+    let location = match entry.location {
+        PlaylistLocation::Url(ref url) => url.clone(),
+        PlaylistLocation::File(ref path) => {
+            let relative = resolver.relative(&entry.location);
+            // URIs always use forward slashes
+            let relative = relative.replace('\\', "/");
+            if path.is_absolute() && relative == path.display().to_string().replace('\\', "/") {
+                let absolute = if relative.starts_with('/') {
+                    relative
+                } else {
+                    format!("/{}", relative)
+                };
+                format!("file://{}", percent_encode(&absolute))
+            } else {
+                percent_encode(&relative)
+            }
+        }
+    };
+    writer
+        .create_element("track")
+        .write_inner_content(|writer| {
+            writer
+                .create_element("location")
+                .write_text_content(BytesText::new(&location))?;
+            if let Some(ref title) = entry.title {
+                writer
+                    .create_element("title")
+                    .write_text_content(BytesText::new(title))?;
+            }
+            if let Some(duration) = entry.duration {
+                writer
+                    .create_element("duration")
+                    .write_text_content(BytesText::new(&duration.as_millis().to_string()))?;
+            }
+            Ok::<(), quick_xml::Error>(())
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn reads_escaped_locations() {
+        let resolver = PathResolver::new("/music/list.xspf", None);
+        let playlist = parse(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/">
+  <title>Mix</title>
+  <trackList>
+    <track><location>A%20B/01.flac</location><title>Rock &amp; Roll</title><duration>1500</duration></track>
+    <track><location>file:///other/02.flac</location></track>
+    <track><title>No location</title></track>
+  </trackList>
+</playlist>"#,
+            &resolver,
+        )
+        .unwrap();
+        assert_eq!(playlist.name.as_deref(), Some("Mix"));
+        assert_eq!(
+            playlist.entries,
+            Vec::from([
+                PlaylistEntry {
+                    location: PlaylistLocation::File(PathBuf::from("/music/A B/01.flac")),
+                    title: Some(String::from("Rock & Roll")),
+                    duration: Some(Duration::from_millis(1500)),
+                },
+                PlaylistEntry::new(PlaylistLocation::File(PathBuf::from("/other/02.flac"))),
+            ])
+        );
+    }
+
+    #[test]
+    fn needs_a_playlist_element() {
+        let resolver = PathResolver::new("/music/list.xspf", None);
+        assert!(parse("<trackList/>", &resolver).is_err());
+    }
+}
@@ -5,6 +5,7 @@ use crate::page::{
 };
 use anyhow::{Ok, Result};
 use crossterm::{
+    event::{DisableMouseCapture, EnableMouseCapture},
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
@@ -14,6 +15,8 @@ pub async fn run() -> Result<()> {
     // initialize terminal
     let mut stdout = std::io::stdout();
     stdout.execute(EnterAlternateScreen)?;
+    // Clicks on the seek bar
+    stdout.execute(EnableMouseCapture)?;
     enable_raw_mode()?;
     let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
     // Event Reader
@@ -29,7 +32,11 @@ pub async fn run() -> Result<()> {
         }
         let event = event_reader.read().await?;
         if &event == &AppEvent::Render {
+            if app.take_clear() {
+                terminal.clear()?;
+            }
             terminal.draw(|f| app.render(f, f.size()))?;
+            app.draw_graphics(terminal.backend_mut())?;
         }
         let mut msg = app.handle_events(event).await;
         while msg != None {
@@ -37,6 +44,7 @@ pub async fn run() -> Result<()> {
         }
     }
     // de-initialize terminal
+    std::io::stdout().execute(DisableMouseCapture)?;
     std::io::stdout().execute(LeaveAlternateScreen)?;
     disable_raw_mode()?;
     println!("Goodbye!");
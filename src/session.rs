@@ -0,0 +1,168 @@
+use anyhow::{Ok, Result};
+use std::{
+    fs::{DirBuilder, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audio::queue::{RepeatMode, ShuffleMode},
+    db::AudioData,
+};
+
+const SESSION_FILE: &str = "session.toml";
+
+// Whatever is needed to pick up where the last run stopped
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Session {
+    #[serde(default = "Session::default_volume")]
+    pub volume: f32,
+    #[serde(default)]
+    pub repeat: RepeatMode,
+    #[serde(default)]
+    pub shuffle: ShuffleMode,
+    // Same seed and queue give the same shuffle order again
+    #[serde(default, with = "seed_bits")]
+    pub seed: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current: Option<usize>,
+    // Into the current track
+    #[serde(default)]
+    pub position_ms: u64,
+    // Selected file list item, by path since the library might have changed in between
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selected: Option<PathBuf>,
+    #[serde(default)]
+    pub queue_selected: usize,
+    #[serde(default)]
+    pub queue: Vec<AudioData>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            volume: Self::default_volume(),
+            repeat: RepeatMode::default(),
+            shuffle: ShuffleMode::default(),
+            seed: 0,
+            current: None,
+            position_ms: 0,
+            selected: None,
+            queue_selected: 0,
+            queue: Vec::new(),
+        }
+    }
+}
+
+impl Session {
+    fn default_volume() -> f32 {
+        1f32
+    }
+    pub fn read<P: AsRef<Path>>(data_dir: P) -> Result<Self> {
+        let file = File::open(data_dir.as_ref().join(SESSION_FILE))?;
+        let mut reader = BufReader::new(file);
+        let mut file_buffer: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut file_buffer)?;
+        let session: Session = toml_edit::de::from_slice(file_buffer.as_slice())?;
+        Ok(session)
+    }
+    pub fn write<P: AsRef<Path>>(&self, data_dir: P) -> Result<()> {
+        let data_dir = data_dir.as_ref();
+        if !data_dir.exists() {
+            DirBuilder::new().recursive(true).create(data_dir)?;
+        }
+        let file = File::create(data_dir.join(SESSION_FILE))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(toml_edit::ser::to_vec(self)?.as_slice())?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+// TOML integers are signed, the seed is kept as the same 64 bits
+mod seed_bits {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(seed: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(*seed as i64)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        i64::deserialize(deserializer).map(|f| f as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "music_player_session_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn cue_track(title: &str, start: Option<u64>, end: Option<u64>) -> AudioData {
+        let mut track = AudioData::new(PathBuf::from("/music/Live/album.flac"));
+        track.name = title.to_string();
+        track.start = start.map(Duration::from_millis);
+        track.end = end.map(Duration::from_millis);
+        track
+    }
+
+    #[test]
+    fn session_round_trips() {
+        let dir = test_dir("round_trip");
+        let mut file = AudioData::new(PathBuf::from("/music/Album/01 Airbag.flac"));
+        file.name = String::from("Airbag");
+        file.author = String::from("Radiohead");
+        file.track = Some(1);
+        file.duration = Some(Duration::from_millis(284_360));
+        file.sample_rate = Some(44100);
+        file.rating = Some(5);
+        file.favorite = true;
+        file.tags = Vec::from([String::from("night")]);
+        let session = Session {
+            volume: 0.35,
+            repeat: RepeatMode::default(),
+            shuffle: ShuffleMode::default(),
+            // Past what a TOML integer holds
+            seed: u64::MAX,
+            current: Some(2),
+            position_ms: 61_500,
+            selected: Some(PathBuf::from("/music/Album")),
+            queue_selected: 1,
+            queue: Vec::from([
+                file,
+                // The first CUE track starts at 0, the last one runs until the end of the file
+                cue_track("Intro", Some(0), Some(90_000)),
+                cue_track("Encore", Some(90_000), None),
+                // Nothing known about it at all
+                AudioData::new(PathBuf::from("/music/unknown.mp3")),
+            ]),
+        };
+        // Written into a directory that doesnt exist yet
+        session.write(&dir).unwrap();
+        assert_eq!(Session::read(&dir).unwrap(), session);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn empty_sessions_use_the_defaults() {
+        let dir = test_dir("empty");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(SESSION_FILE), "").unwrap();
+        assert_eq!(Session::read(&dir).unwrap(), Session::default());
+        Session::default().write(&dir).unwrap();
+        assert_eq!(Session::read(&dir).unwrap(), Session::default());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(Session::read(&dir).is_err());
+    }
+}